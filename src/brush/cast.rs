@@ -0,0 +1,162 @@
+//! Ray and box casts against [`ConvexHull`]s.
+
+use super::*;
+
+/// Where a cast hit a [`ConvexHull`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HullHit {
+	/// How far along the cast the hit is, as a multiple of the cast direction's length.
+	pub distance: f64,
+	/// The normal of the plane that was hit, pointing out of the hull.
+	pub normal: DVec3,
+	/// The index of the plane that was hit in [`ConvexHull::planes`], which for [`Brush`]es is also the index of the surface.
+	///
+	/// `None` if one of the bevel planes added for box casts was hit.
+	pub plane_idx: Option<usize>,
+}
+
+/// Casts a ray from `origin` along `direction` through the convex hull made by `planes`, tagged with the index to put in [`HullHit::plane_idx`].
+///
+/// Rays starting inside the hull don't hit it.
+pub(crate) fn cast_through_planes(
+	planes: impl IntoIterator<Item = (Option<usize>, BrushPlane)>,
+	origin: DVec3,
+	direction: DVec3,
+	max_distance: f64,
+) -> Option<HullHit> {
+	let mut hit: Option<HullHit> = None;
+	let mut exit = max_distance;
+
+	for (plane_idx, plane) in planes {
+		let side = plane.point_side(origin);
+		let towards = plane.normal.dot(direction);
+
+		if towards.abs() < f64::EPSILON {
+			// Parallel, and outside of this plane, so it can never enter the hull
+			if side > 0. {
+				return None;
+			}
+			continue;
+		}
+
+		let distance = -side / towards;
+
+		if towards < 0. {
+			// Entering the plane, the last entry is where the hull is hit
+			if hit.is_none_or(|hit| distance > hit.distance) {
+				hit = Some(HullHit {
+					distance,
+					normal: plane.normal,
+					plane_idx,
+				});
+			}
+		} else {
+			exit = exit.min(distance);
+		}
+	}
+
+	// If the entry is behind the origin, we started inside
+	hit.filter(|hit| hit.distance >= 0. && hit.distance <= exit)
+}
+
+/// Returns the axis-aligned bounds of `hull`, or `None` if it has no vertices.
+///
+/// Computing these means polygonizing the hull, so when casting against the same hull repeatedly, compute them once and pass them to [`expanded_planes`].
+pub(crate) fn hull_bounds(hull: &(impl ConvexHull + ?Sized)) -> Option<(DVec3, DVec3)> {
+	hull.calculate_vertices()
+		.map(|(vertex, _)| (vertex, vertex))
+		.reduce(|(min, max), (vertex, _)| (min.min(vertex), max.max(vertex)))
+}
+
+/// Returns the planes of `hull` pushed out by an axis-aligned box of `half_extents`, along with axial bevel planes made from `bounds` (see [`hull_bounds`]),
+/// so that casting a point against them is the same as casting the box against `hull`.
+pub(crate) fn expanded_planes(
+	hull: &(impl ConvexHull + ?Sized),
+	bounds: Option<(DVec3, DVec3)>,
+	half_extents: DVec3,
+) -> Vec<(Option<usize>, BrushPlane)> {
+	let mut planes = hull
+		.planes()
+		.enumerate()
+		.map(|(plane_idx, plane)| {
+			(
+				Some(plane_idx),
+				BrushPlane {
+					normal: plane.normal,
+					distance: plane.distance - plane.normal.abs().dot(half_extents),
+				},
+			)
+		})
+		.collect_vec();
+
+	// Without bevels, boxes would catch on the hull's edges and corners as if they stuck out
+	if let Some((min, max)) = bounds {
+		let (min, max) = (min - half_extents, max + half_extents);
+
+		for (axis, (min, max)) in [DVec3::X, DVec3::Y, DVec3::Z]
+			.into_iter()
+			.zip([(min.x, max.x), (min.y, max.y), (min.z, max.z)])
+		{
+			planes.push((
+				None,
+				BrushPlane {
+					normal: axis,
+					distance: -max,
+				},
+			));
+			planes.push((
+				None,
+				BrushPlane {
+					normal: -axis,
+					distance: min,
+				},
+			));
+		}
+	}
+
+	planes
+}
+
+impl Brush {
+	/// Returns the surface that `hit` hit, if it was a cast against this brush.
+	pub fn hit_surface(&self, hit: &HullHit) -> Option<&BrushSurface> {
+		hit.plane_idx.and_then(|plane_idx| self.surfaces.get(plane_idx))
+	}
+}
+
+#[test]
+fn hull_casts() {
+	let brush = Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane { normal, distance: -1. },
+				texture: default(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	};
+
+	let hit = brush.cast_ray(dvec3(-5., 0., 0.), DVec3::X, 100.).unwrap();
+	assert_eq!(hit.distance, 4.);
+	assert_eq!(hit.normal, DVec3::NEG_X);
+	assert_eq!(brush.hit_surface(&hit).unwrap().plane.normal, DVec3::NEG_X);
+
+	// Too short, pointing away, starting inside, and missing to the side
+	assert!(brush.cast_ray(dvec3(-5., 0., 0.), DVec3::X, 3.).is_none());
+	assert!(brush.cast_ray(dvec3(-5., 0., 0.), DVec3::NEG_X, 100.).is_none());
+	assert!(brush.cast_ray(DVec3::ZERO, DVec3::X, 100.).is_none());
+	assert!(brush.cast_ray(dvec3(-5., 2., 0.), DVec3::X, 100.).is_none());
+
+	let hit = brush.cast_segment(dvec3(0., 5., 0.), dvec3(0., -5., 0.)).unwrap();
+	assert_eq!(hit.distance, 0.4);
+	assert_eq!(hit.normal, DVec3::Y);
+
+	// The ray would miss, but the box is big enough to hit
+	let hit = brush.cast_aabb(dvec3(-5., 2., 0.), DVec3::splat(1.5), DVec3::X, 100.).unwrap();
+	assert_eq!(hit.distance, 2.5);
+	assert_eq!(hit.normal, DVec3::NEG_X);
+
+	// Already overlapping
+	assert!(brush.cast_aabb(dvec3(-2., 0., 0.), DVec3::splat(1.5), DVec3::X, 100.).is_none());
+}
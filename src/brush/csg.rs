@@ -0,0 +1,343 @@
+//! Constructive solid geometry between [`Brush`]es, used to remove faces that can never be seen, and to edit brushes at runtime.
+
+use super::*;
+
+/// How far a point can be from a plane while still being considered on it.
+const PLANE_EPSILON: f64 = 0.00001;
+
+impl Brush {
+	/// Splits this brush along the plane of `along`, returning the parts behind and in front of it, or `None` for a side the brush isn't on.
+	///
+	/// The new face of the back part uses `along`, and the new face of the front part uses `along` [inverted](BrushSurface::inverted).
+	pub fn split(&self, along: &BrushSurface) -> (Option<Brush>, Option<Brush>) {
+		let clip = |surface: BrushSurface| {
+			let mut brush = self.clone();
+			brush.surfaces.push(surface);
+			brush.remove_redundant_surfaces();
+			brush.is_solid().then_some(brush)
+		};
+
+		(clip(along.clone()), clip(along.clone().inverted()))
+	}
+
+	/// Returns the part of this brush that is also inside `other`, or `None` if they don't overlap.
+	///
+	/// Faces keep the surfaces of whichever brush they came from, preferring this one for coplanar faces.
+	pub fn intersect(&self, other: &Brush) -> Option<Brush> {
+		let mut brush = Brush {
+			surfaces: self.surfaces.iter().chain(&other.surfaces).cloned().collect(),
+		};
+		brush.remove_redundant_surfaces();
+		brush.is_solid().then_some(brush)
+	}
+
+	/// Carves `other` out of this brush, returning the convex fragments left over. If they don't overlap, this is just a copy of this brush.
+	///
+	/// The faces of the hole use the surfaces of `other`, [inverted](BrushSurface::inverted).
+	pub fn subtract(&self, other: &Brush) -> Vec<Brush> {
+		// Splitting would still cut this brush along the planes of `other` that pass through it
+		if self.intersect(other).is_none() {
+			return vec![self.clone()];
+		}
+
+		let mut fragments = Vec::new();
+		let mut remaining = self.clone();
+
+		for surface in &other.surfaces {
+			let (back, front) = remaining.split(surface);
+			fragments.extend(front);
+
+			match back {
+				Some(back) => remaining = back,
+				// Only reachable through precision issues, as the brushes overlap
+				None => return fragments,
+			}
+		}
+
+		// `remaining` is entirely inside `other`, and so is removed
+		fragments
+	}
+
+	/// Removes surfaces that don't make up a face of the brush, such as ones outside of it after cutting, and duplicate surfaces on the same plane.
+	pub fn remove_redundant_surfaces(&mut self) {
+		let planes = self.planes().collect_vec();
+		let mut keep = (0..planes.len())
+			.map(|plane_idx| clip_plane_winding(plane_idx, &planes).is_some())
+			.collect_vec();
+
+		for (a, b) in (0..planes.len()).tuple_combinations() {
+			if keep[a]
+				&& keep[b] && planes[a].normal.almost_eq(planes[b].normal, 1e-9)
+				&& (planes[a].distance - planes[b].distance).abs() < PLANE_EPSILON
+			{
+				keep[b] = false;
+			}
+		}
+
+		let mut keep = keep.into_iter();
+		self.surfaces.retain(|_| keep.next().unwrap_or(false));
+	}
+
+	/// Returns `true` if this brush encloses any space, a convex polyhedron needs at least 4 faces.
+	fn is_solid(&self) -> bool {
+		self.polygonize().filter(|polygon| polygon.vertices().len() >= 3).count() >= 4
+	}
+}
+
+/// Polygonizes `brushes`, clipping away the parts of faces that are inside or flush against other brushes in the list, similar to `qbsp`'s CSG stage.
+///
+/// Only [occluding](TrenchBroomConfig::is_brush_occluding) brushes clip other faces, so that faces can still be seen through water, glass, and the like.
+/// When two brushes have coplanar faces pointing the same way, the face of the later brush is kept.
+pub fn polygonize_without_hidden_faces<'a>(brushes: &'a [Brush], config: &TrenchBroomConfig) -> Vec<BrushSurfacePolygon<'a>> {
+	let polygons = brushes.iter().map(|brush| brush.polygonize().collect_vec()).collect_vec();
+	let bounds = polygons.iter().map(|brush_polygons| polygon_bounds(brush_polygons)).collect_vec();
+	let occluding = brushes.iter().map(|brush| config.is_brush_occluding(brush)).collect_vec();
+
+	let mut output = Vec::new();
+
+	for (brush_idx, brush_polygons) in polygons.into_iter().enumerate() {
+		for polygon in brush_polygons {
+			let mut fragments = vec![polygon.vertices().clone()];
+
+			for (other_idx, other) in brushes.iter().enumerate() {
+				if other_idx == brush_idx || !occluding[other_idx] || fragments.is_empty() || !bounds_overlap(bounds[brush_idx], bounds[other_idx]) {
+					continue;
+				}
+
+				fragments = fragments
+					.into_iter()
+					.flat_map(|fragment| clip_outside(fragment, &polygon.surface.plane, other, other_idx > brush_idx))
+					.collect();
+			}
+
+			output.extend(
+				fragments
+					.into_iter()
+					.map(|fragment| BrushSurfacePolygon::from_ordered(polygon.surface, fragment)),
+			);
+		}
+	}
+
+	output
+}
+
+/// Returns the parts of convex polygon `fragment` (lying on `plane`) outside of `other`.
+///
+/// Parts flush against a face of `other` facing the opposite direction are hidden. If they face the same direction, they are only hidden if `other_wins_coplanar`.
+fn clip_outside(fragment: Vec<DVec3>, plane: &BrushPlane, other: &Brush, other_wins_coplanar: bool) -> Vec<Vec<DVec3>> {
+	let mut outside = Vec::new();
+	let mut inside = fragment.clone();
+
+	for other_plane in other.planes() {
+		if inside.iter().all(|vertex| other_plane.point_side(*vertex).abs() < PLANE_EPSILON) {
+			if other_plane.normal.dot(plane.normal) > 0. && !other_wins_coplanar {
+				return vec![fragment];
+			}
+			// Otherwise it's on the boundary of `other`, which counts as inside
+			continue;
+		}
+
+		let (front, back) = split_polygon(&inside, other_plane);
+		outside.extend(front);
+
+		match back {
+			Some(back) => inside = back,
+			// Entirely outside of this plane, and so entirely outside of `other`
+			None => return outside,
+		}
+	}
+
+	// Whatever is left is hidden inside `other`
+	outside
+}
+
+/// Splits convex polygon `vertices` into the parts in front of and behind `plane`.
+fn split_polygon(vertices: &[DVec3], plane: &BrushPlane) -> (Option<Vec<DVec3>>, Option<Vec<DVec3>>) {
+	let sides = vertices.iter().map(|vertex| plane.point_side(*vertex)).collect_vec();
+
+	if sides.iter().all(|side| *side < PLANE_EPSILON) {
+		return (None, Some(vertices.to_vec()));
+	}
+	if sides.iter().all(|side| *side > -PLANE_EPSILON) {
+		return (Some(vertices.to_vec()), None);
+	}
+
+	let mut front = Vec::new();
+	let mut back = Vec::new();
+
+	for i in 0..vertices.len() {
+		let j = (i + 1) % vertices.len();
+		let (a, b) = (vertices[i], vertices[j]);
+		let (side_a, side_b) = (sides[i], sides[j]);
+
+		if side_a > -PLANE_EPSILON {
+			front.push(a);
+		}
+		if side_a < PLANE_EPSILON {
+			back.push(a);
+		}
+
+		if (side_a > PLANE_EPSILON && side_b < -PLANE_EPSILON) || (side_a < -PLANE_EPSILON && side_b > PLANE_EPSILON) {
+			let split = a + (b - a) * (side_a / (side_a - side_b));
+			front.push(split);
+			back.push(split);
+		}
+	}
+
+	// Splitting right next to a vertex can create duplicates, which BrushSurfacePolygon doesn't like
+	let valid = |mut polygon: Vec<DVec3>| {
+		polygon.dedup_by(|a, b| a.almost_eq(*b, BrushSurfacePolygon::VERTEX_PRECISION_MARGIN));
+		while polygon.len() > 1 && polygon[0].almost_eq(polygon[polygon.len() - 1], BrushSurfacePolygon::VERTEX_PRECISION_MARGIN) {
+			polygon.pop();
+		}
+		(polygon.len() >= 3).then_some(polygon)
+	};
+	(valid(front), valid(back))
+}
+
+fn polygon_bounds(polygons: &[BrushSurfacePolygon]) -> Option<(DVec3, DVec3)> {
+	polygons
+		.iter()
+		.flat_map(BrushSurfacePolygon::vertices)
+		.map(|vertex| (*vertex, *vertex))
+		.reduce(|(min, max), (vertex, _)| (min.min(vertex), max.max(vertex)))
+}
+
+fn bounds_overlap(a: Option<(DVec3, DVec3)>, b: Option<(DVec3, DVec3)>) -> bool {
+	let (Some((a_min, a_max)), Some((b_min, b_max))) = (a, b) else { return false };
+	// Touching counts, as that's where most hidden faces are
+	(a_min - PLANE_EPSILON).cmple(b_max).all() && (b_min - PLANE_EPSILON).cmple(a_max).all()
+}
+
+#[cfg(test)]
+fn cuboid(min: DVec3, max: DVec3) -> Brush {
+	Brush {
+		surfaces: [
+			(DVec3::X, max.x),
+			(DVec3::NEG_X, -min.x),
+			(DVec3::Y, max.y),
+			(DVec3::NEG_Y, -min.y),
+			(DVec3::Z, max.z),
+			(DVec3::NEG_Z, -min.z),
+		]
+		.map(|(normal, distance)| BrushSurface {
+			plane: BrushPlane { normal, distance: -distance },
+			texture: default(),
+			uv: default(),
+			attributes: default(),
+		})
+		.into(),
+	}
+}
+
+#[test]
+fn hidden_face_removal() {
+	let area = |polygons: &[BrushSurfacePolygon]| {
+		polygons
+			.iter()
+			.map(|polygon| {
+				let vertices = polygon.vertices();
+				(1..vertices.len() - 1)
+					.map(|i| (vertices[i] - vertices[0]).cross(vertices[i + 1] - vertices[0]).length() / 2.)
+					.sum::<f64>()
+			})
+			.sum::<f64>()
+	};
+
+	// Two unit cubes on top of each other, their touching faces are hidden
+	let stacked = [cuboid(DVec3::ZERO, DVec3::ONE), cuboid(DVec3::Y, dvec3(1., 2., 1.))];
+	let polygons = polygonize_without_hidden_faces(&stacked, &default());
+	assert_eq!(polygons.len(), 10);
+	assert!((area(&polygons) - 10.).abs() < 0.0001);
+
+	// A cube half sticking out of another, the parts inside are hidden
+	let overlapping = [cuboid(DVec3::ZERO, DVec3::splat(2.)), cuboid(DVec3::ONE, DVec3::splat(3.))];
+	let polygons = polygonize_without_hidden_faces(&overlapping, &default());
+	assert!((area(&polygons) - (24. + 24. - 6.)).abs() < 0.0001);
+
+	// Water on top of a wall, the wall can be seen through the water, but the bottom of the water is hidden
+	let mut water = cuboid(DVec3::Y, dvec3(1., 2., 1.));
+	for surface in &mut water.surfaces {
+		surface.texture = "*water".s();
+	}
+	let wall_and_water = [cuboid(DVec3::ZERO, DVec3::ONE), water];
+	let polygons = polygonize_without_hidden_faces(&wall_and_water, &default());
+	assert_eq!(polygons.iter().filter(|polygon| polygon.surface.texture.is_empty()).count(), 6);
+	assert_eq!(polygons.iter().filter(|polygon| polygon.surface.texture == "*water").count(), 5);
+	assert!(
+		polygons
+			.iter()
+			.any(|polygon| polygon.surface.texture.is_empty() && polygon.surface.plane.normal == DVec3::Y)
+	);
+}
+
+#[test]
+fn brush_csg_operations() {
+	let bounds = |brush: &Brush| {
+		brush
+			.calculate_vertices()
+			.map(|(vertex, _)| (vertex, vertex))
+			.reduce(|(min, max), (vertex, _)| (min.min(vertex), max.max(vertex)))
+			.unwrap()
+	};
+
+	let cube = cuboid(DVec3::ZERO, DVec3::splat(4.));
+	let splitter = BrushSurface {
+		plane: BrushPlane {
+			normal: DVec3::X,
+			distance: -1.,
+		},
+		texture: "split".s(),
+		uv: default(),
+		attributes: default(),
+	};
+
+	let (Some(back), Some(front)) = cube.split(&splitter) else { panic!("split should produce 2 halves") };
+	assert_eq!(bounds(&back), (DVec3::ZERO, dvec3(1., 4., 4.)));
+	assert_eq!(bounds(&front), (dvec3(1., 0., 0.), DVec3::splat(4.)));
+	assert_eq!(back.surfaces.len(), 6);
+	assert!(
+		back.surfaces
+			.iter()
+			.any(|surface| surface.texture == "split" && surface.plane.normal == DVec3::X)
+	);
+	assert!(
+		front
+			.surfaces
+			.iter()
+			.any(|surface| surface.texture == "split" && surface.plane.normal == DVec3::NEG_X)
+	);
+
+	let far_splitter = BrushSurface {
+		plane: BrushPlane {
+			normal: DVec3::X,
+			distance: -10.,
+		},
+		..splitter
+	};
+	assert!(matches!(cube.split(&far_splitter), (Some(_), None)));
+
+	// A hole straight through the cube
+	let hole = cuboid(dvec3(1., 1., -1.), dvec3(3., 3., 5.));
+	assert!(cube.intersects(&hole));
+	assert_eq!(bounds(&cube.intersect(&hole).unwrap()), (dvec3(1., 1., 0.), dvec3(3., 3., 4.)));
+
+	let fragments = cube.subtract(&hole);
+	assert_eq!(fragments.len(), 4);
+	for fragment in &fragments {
+		assert_eq!(fragment.surfaces.len(), 6);
+		// Only touching the hole
+		assert!(fragment.intersect(&hole).is_none());
+	}
+
+	let far_away = cuboid(DVec3::splat(10.), DVec3::splat(11.));
+	assert!(!cube.intersects(&far_away));
+	assert!(cube.intersect(&far_away).is_none());
+	assert_eq!(cube.subtract(&far_away).len(), 1);
+
+	// The planes of this one pass through the cube, but it shouldn't be cut along them
+	let beside = cuboid(dvec3(6., 1., 1.), dvec3(8., 3., 3.));
+	let [copy] = cube.subtract(&beside).try_into().unwrap();
+	assert_eq!(copy.surfaces.len(), 6);
+	assert_eq!(bounds(&copy), (DVec3::ZERO, DVec3::splat(4.)));
+}
@@ -0,0 +1,775 @@
+//! Contains Brush definitions, math, and mesh generation.
+
+flat! {
+	cast;
+	csg;
+	t_junctions;
+	trace;
+}
+
+use crate::*;
+use bevy_mesh::{Indices, PrimitiveTopology};
+use util::{AlmostEqual, BevyTrenchbroomCoordinateConversions, ConvertZeroToOne};
+
+/// Represents an infinitely large plane in 3d space, used for defining convex hulls like [`Brush`]es.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BrushPlane {
+	pub normal: DVec3,
+	pub distance: f64,
+}
+
+impl BrushPlane {
+	/// Converts a triangle into a [`BrushPlane`]. The direction of the plane is based on the order of the vertices of the triangle.
+	pub fn from_triangle(tri: [DVec3; 3]) -> Self {
+		let normal = (tri[2] - tri[0]).cross(tri[1] - tri[0]).normalize();
+		Self {
+			normal,
+			distance: -normal.dot(tri[0]),
+		}
+	}
+
+	/// Returns this plane transformed by `transform`.
+	pub fn transformed(&self, transform: DAffine3) -> Self {
+		let point_on_plane = transform.transform_point3(self.normal * -self.distance);
+		let normal = (transform.matrix3.inverse().transpose() * self.normal).normalize();
+		Self {
+			normal,
+			distance: -normal.dot(point_on_plane),
+		}
+	}
+
+	/// Calculates what side of the plane a point is on.
+	///
+	/// `>0` = Front Side. `<0` = Back Side. `0` = On Plane
+	pub fn point_side(&self, point: DVec3) -> f64 {
+		self.normal.dot(point) + self.distance
+	}
+
+	/// Projects `point` onto this plane, and returns the 2d position of it.
+	#[deprecated = "this doesn't match how Quake projects textures, use `BrushUV::standard_axes` instead"]
+	pub fn project(&self, point: DVec3) -> DVec2 {
+		let x_normal = self.normal.cross(DVec3::Y);
+		// If the x normal is 0, then the normal vector is pointing straight up, and we can use `DVec3::X` instead
+		let x_normal = if x_normal == DVec3::ZERO { DVec3::X } else { x_normal };
+
+		let y_normal = x_normal.cross(self.normal);
+
+		dvec2(x_normal.dot(point), y_normal.dot(point))
+	}
+
+	/// Attempts to calculate the intersection point between 3 planes, returns `None` if there is no intersection, or the planes are parallel.
+	pub fn calculate_intersection_point(planes: [&BrushPlane; 3]) -> Option<DVec3> {
+		let [p1, p2, p3] = planes;
+		let m1 = dvec3(p1.normal.x, p2.normal.x, p3.normal.x);
+		let m2 = dvec3(p1.normal.y, p2.normal.y, p3.normal.y);
+		let m3 = dvec3(p1.normal.z, p2.normal.z, p3.normal.z);
+		let d = -dvec3(p1.distance, p2.distance, p3.distance);
+
+		let u = m2.cross(m3);
+		let v = m1.cross(d);
+
+		let denom = m1.dot(u);
+
+		// Check for parallel planes or if planes do not intersect
+		if denom.abs() < f64::EPSILON {
+			return None;
+		}
+
+		Some(dvec3(d.dot(u), m3.dot(v), -m2.dot(v)) / denom)
+	}
+}
+impl std::ops::Neg for BrushPlane {
+	type Output = Self;
+	fn neg(self) -> Self::Output {
+		Self {
+			normal: -self.normal,
+			distance: -self.distance,
+		}
+	}
+}
+
+/// Brush face UV coordinates.
+#[derive(Reflect, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BrushUV {
+	pub offset: Vec2,
+	pub rotation: f32,
+	pub scale: Vec2,
+
+	/// Describes the X and Y texture-space axes, if the map is using the `Valve220` format.
+	pub axes: Option<[DVec3; 2]>,
+}
+
+impl BrushUV {
+	/// The base texture axes used for Quake-style axial projection, in TrenchBroom space.
+	/// Each row contains the plane normal, then the X and Y texture axes.
+	const QUAKE_BASE_AXES: [[DVec3; 3]; 6] = [
+		[DVec3::Z, DVec3::X, DVec3::NEG_Y],     // Floor
+		[DVec3::NEG_Z, DVec3::X, DVec3::NEG_Y], // Ceiling
+		[DVec3::X, DVec3::Y, DVec3::NEG_Z],     // West wall
+		[DVec3::NEG_X, DVec3::Y, DVec3::NEG_Z], // East wall
+		[DVec3::Y, DVec3::X, DVec3::NEG_Z],     // South wall
+		[DVec3::NEG_Y, DVec3::X, DVec3::NEG_Z], // North wall
+	];
+
+	/// Calculates the X and Y texture axes for maps not using the `Valve220` format, the same way Quake's `qbsp` does.
+	///
+	/// The axes are picked from the world axis closest to `normal`, and rotated by [`Self::rotation`]. Both `normal` and the output are in TrenchBroom space, and scale is not applied.
+	pub fn standard_axes(&self, normal: DVec3) -> [DVec3; 2] {
+		let mut best_axis = 0;
+		let mut best_dot = 0.;
+
+		for (i, [axis_normal, _, _]) in Self::QUAKE_BASE_AXES.iter().enumerate() {
+			let dot = normal.dot(*axis_normal);
+			// Must be greater, not greater or equal, to match qbsp's tie-breaking
+			if dot > best_dot {
+				best_dot = dot;
+				best_axis = i;
+			}
+		}
+
+		let [_, mut x_axis, mut y_axis] = Self::QUAKE_BASE_AXES[best_axis];
+
+		// Exact values for right angles, so that axis-aligned textures don't end up with precision errors
+		let (sin, cos) = match self.rotation as f64 {
+			0. => (0., 1.),
+			90. => (1., 0.),
+			180. => (0., -1.),
+			270. => (-1., 0.),
+			rotation => rotation.to_radians().sin_cos(),
+		};
+
+		// The component indices to rotate within, this is the first non-zero component of each axis
+		let sv = (0..3).find(|&i| x_axis[i] != 0.).unwrap_or(0);
+		let tv = (0..3).find(|&i| y_axis[i] != 0.).unwrap_or(0);
+
+		for axis in [&mut x_axis, &mut y_axis] {
+			let s = cos * axis[sv] - sin * axis[tv];
+			let t = sin * axis[sv] + cos * axis[tv];
+			axis[sv] = s;
+			axis[tv] = t;
+		}
+
+		[x_axis, y_axis]
+	}
+}
+
+/// Quake 2 style face attributes, only loaded from [`Quake2`](config::MapFileFormat::Quake2) and [`Quake2Valve`](config::MapFileFormat::Quake2Valve) maps, otherwise defaulted.
+///
+/// The bits of the flags correspond to the indices of [`TrenchBroomConfig::content_flags`] and [`TrenchBroomConfig::surface_flags`].
+/// See [`TrenchBroomConfig::content_flag`] and [`TrenchBroomConfig::surface_flag`] for looking them up by name.
+///
+/// Map geometry entities have this component inserted with the attributes of the faces they were created from.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[reflect(Component, Default, Debug, PartialEq)]
+pub struct SurfaceAttributes {
+	/// Flags "generally affecting the behavior of the brush containing the face".
+	pub content_flags: u32,
+	/// Flags affecting only this face.
+	pub surface_flags: u32,
+	/// Game-defined value, in Quake 2 this is used for light emission and the strength of `warp` and `flowing` surfaces.
+	pub surface_value: i32,
+}
+impl SurfaceAttributes {
+	/// Returns `true` if any of the bits in `mask` are set in [`Self::content_flags`].
+	#[inline]
+	pub fn has_content_flags(&self, mask: u32) -> bool {
+		self.content_flags & mask != 0
+	}
+
+	/// Returns `true` if any of the bits in `mask` are set in [`Self::surface_flags`].
+	#[inline]
+	pub fn has_surface_flags(&self, mask: u32) -> bool {
+		self.surface_flags & mask != 0
+	}
+}
+
+/// A surface of a brush, includes the plane the surface is along, the material of the surface, and the UV coordinates that the material follows.
+#[derive(Reflect, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BrushSurface {
+	pub plane: BrushPlane,
+	pub texture: String,
+	pub uv: BrushUV,
+	pub attributes: SurfaceAttributes,
+}
+impl BrushSurface {
+	/// Returns this BrushSurface with it's plane facing the opposite direction.
+	pub fn inverted(self) -> Self {
+		Self { plane: -self.plane, ..self }
+	}
+}
+
+/// A convex hull with material data attached.
+#[derive(Reflect, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Brush {
+	pub surfaces: Vec<BrushSurface>,
+}
+
+impl Brush {
+	/// Converts a brush from [`quake_util`] to a bevy_trenchbroom brush.
+	pub(crate) fn from_quake_util(brush: &quake_util::qmap::Brush, config: &TrenchBroomConfig) -> Self {
+		Self {
+			surfaces: brush
+				.iter()
+				.map(|surface| BrushSurface {
+					plane: BrushPlane::from_triangle(surface.half_space.map(|half_space| config.to_bevy_space_f64(DVec3::from(half_space)))),
+					texture: surface.texture.to_string_lossy().to_string(),
+					uv: BrushUV {
+						offset: DVec2::from(surface.alignment.offset).as_vec2(),
+						rotation: surface.alignment.rotation as f32,
+						scale: DVec2::from(surface.alignment.scale).as_vec2(),
+						axes: surface
+							.alignment
+							.axes
+							.map(|axes| axes.map(|axes_vec| config.to_bevy_space_f64(DVec3::from(axes_vec)))),
+					},
+					attributes: SurfaceAttributes {
+						content_flags: surface.q2ext.content_flags as u32,
+						surface_flags: surface.q2ext.surface_flags as u32,
+						surface_value: surface.q2ext.surface_value as i32,
+					},
+				})
+				.collect(),
+		}
+	}
+
+	/// Transforms this brush by the Bevy-space `transform`, keeping textures locked to the surfaces like TrenchBroom's texture lock.
+	///
+	/// Surfaces without texture axes (such as from the Standard format) are given explicit axes to allow this.
+	pub fn transform(&mut self, transform: DAffine3, config: &TrenchBroomConfig) {
+		// Normals need to be transformed by the inverse transpose to stay perpendicular to the surface under non-uniform scale.
+		// The same goes for texture axes, as they are dotted with positions.
+		let normal_matrix = transform.matrix3.inverse().transpose();
+		let scale_squared = config.scale as f64 * config.scale as f64;
+
+		for surface in &mut self.surfaces {
+			let axes = surface.uv.axes.unwrap_or_else(|| {
+				surface
+					.uv
+					.standard_axes(surface.plane.normal.bevy_to_trenchbroom())
+					.map(|axis| config.to_bevy_space_f64(axis))
+			});
+			let axes = axes.map(|axis| normal_matrix * axis);
+
+			// Moving the surface also moves the texture, so we move the offset back
+			let shift = dvec2(axes[0].dot(transform.translation), axes[1].dot(transform.translation)) * scale_squared;
+			surface.uv.offset -= shift.as_vec2() / surface.uv.scale.convert_zero_to_one();
+			surface.uv.axes = Some(axes);
+
+			surface.plane = surface.plane.transformed(transform);
+		}
+	}
+
+	/// Cuts the brush along the specified surface, adding said surface to the brush, and removing all other surfaces in front of it.
+	///
+	/// NOTE: If `along` is outside of the brush, it can make this brush invalid, if you are using untrusted data, check with [`Brush::contains_plane`].
+	pub fn cut(&mut self, along: BrushSurface) {
+		self.surfaces.push(along);
+		// Surfaces fully in front of `along` no longer have a face, which also catches ones meeting at corners of more than 3 planes
+		self.remove_redundant_surfaces();
+	}
+
+	/// Calculates the polygonal faces making up the brush, by clipping a huge polygon on each surface's plane by every other plane.
+	///
+	/// Returns an iterator of polygonal faces where each face includes ordered vertices, indices, and a reference to the surface the face was calculated from.
+	///
+	/// If you want a map of intersections to the surfaces causing them, see [`Self::calculate_vertices`]
+	pub fn polygonize(&self) -> impl Iterator<Item = BrushSurfacePolygon> {
+		let planes = self.planes().collect_vec();
+
+		(0..planes.len()).filter_map(move |surface_index| {
+			let winding = clip_plane_winding(surface_index, &planes)?;
+			Some(BrushSurfacePolygon::from_ordered(
+				&self.surfaces[surface_index],
+				winding.into_iter().map(|vertex| vertex.position).collect(),
+			))
+		})
+	}
+}
+
+/// The half-width of the polygon each face starts as before clipping, larger than any sane map.
+const WINDING_SIZE: f64 = 1_000_000.;
+/// How far a point can be from a plane while still being considered on it when clipping.
+const WINDING_EPSILON: f64 = 0.00001;
+
+/// A vertex of a polygon being clipped, along with the plane that the edge going to the next vertex lies on.
+#[derive(Debug, Clone, Copy)]
+struct WindingVertex {
+	position: DVec3,
+	/// `None` for the edges of the initial polygon.
+	edge_plane: Option<usize>,
+}
+
+/// Calculates the polygon of the plane at `plane_idx` within the convex hull made by `planes`, or `None` if it's fully clipped away.
+fn clip_plane_winding(plane_idx: usize, planes: &[&BrushPlane]) -> Option<Vec<WindingVertex>> {
+	let plane = planes[plane_idx];
+	let (u, v) = plane.normal.any_orthonormal_pair();
+	let center = plane.normal * -plane.distance;
+
+	let mut winding = [-u - v, u - v, u + v, -u + v]
+		.map(|corner| WindingVertex {
+			position: center + corner * WINDING_SIZE,
+			edge_plane: None,
+		})
+		.to_vec();
+
+	for (clip_idx, clip_plane) in planes.iter().enumerate() {
+		if clip_idx == plane_idx {
+			continue;
+		}
+		// Duplicated planes would each leave the other's winding untouched, so only the first one makes a face
+		if clip_plane.normal.abs_diff_eq(plane.normal, WINDING_EPSILON) && (clip_plane.distance - plane.distance).abs() < WINDING_EPSILON {
+			if clip_idx < plane_idx {
+				return None;
+			}
+			continue;
+		}
+
+		let sides = winding.iter().map(|vertex| clip_plane.point_side(vertex.position)).collect_vec();
+		if sides.iter().all(|side| *side < WINDING_EPSILON) {
+			continue;
+		}
+		if sides.iter().all(|side| *side > -WINDING_EPSILON) {
+			return None;
+		}
+
+		let mut clipped = Vec::with_capacity(winding.len() + 1);
+
+		for i in 0..winding.len() {
+			let j = (i + 1) % winding.len();
+			let (a, b) = (winding[i], winding[j]);
+			let (side_a, side_b) = (sides[i], sides[j]);
+			let split = || a.position + (b.position - a.position) * (side_a / (side_a - side_b));
+
+			if side_a < WINDING_EPSILON {
+				if side_b > WINDING_EPSILON && side_a < -WINDING_EPSILON {
+					// Leaving the hull, the edge from the split point follows the clipping plane
+					clipped.push(a);
+					clipped.push(WindingVertex {
+						position: split(),
+						edge_plane: Some(clip_idx),
+					});
+				} else if side_b > WINDING_EPSILON {
+					clipped.push(WindingVertex {
+						edge_plane: Some(clip_idx),
+						..a
+					});
+				} else {
+					clipped.push(a);
+				}
+			} else if side_b < -WINDING_EPSILON {
+				// Entering the hull, the edge from the split point follows the original edge
+				clipped.push(WindingVertex {
+					position: split(),
+					edge_plane: a.edge_plane,
+				});
+			}
+		}
+
+		winding = clipped;
+		if winding.len() < 3 {
+			return None;
+		}
+	}
+
+	// Clipping huge polygons loses a bit of precision, and each face would lose it differently.
+	// Recalculating corners from the planes meeting there makes them exact (where possible) and consistent between faces.
+	let positions = (0..winding.len())
+		.map(|i| {
+			let previous = winding[(i + winding.len() - 1) % winding.len()];
+			let vertex = winding[i];
+			let (Some(previous_plane), Some(next_plane)) = (previous.edge_plane, vertex.edge_plane) else {
+				return vertex.position;
+			};
+			BrushPlane::calculate_intersection_point([plane, planes[previous_plane], planes[next_plane]])
+				.filter(|intersection| intersection.distance(vertex.position) < WINDING_EPSILON)
+				.unwrap_or(vertex.position)
+		})
+		.collect_vec();
+	for (vertex, position) in winding.iter_mut().zip(positions) {
+		vertex.position = position;
+	}
+
+	Some(winding)
+}
+
+/// A 3D convex hull made of [`BrushPlane`]s (half-spaces).
+pub trait ConvexHull {
+	fn planes(&self) -> impl Iterator<Item = &BrushPlane> + Clone;
+
+	/// Returns `true` if `point` is not on the outside of the brush, else `false`.
+	fn contains_point(&self, point: DVec3) -> bool {
+		// I don't use exactly 0 here just in case the floating-point precision is a bit off
+		self.planes().all(|plane| plane.point_side(point) < 0.000001)
+	}
+
+	/// Calculates the corners of the hull, returns an iterator that maps each corner's position to the indexes of 3 of the planes meeting there.
+	///
+	/// Each corner is only returned once, even if more than 3 planes meet there.
+	///
+	/// If you want a map of the surfaces to the intersections they cause for a [`Brush`], see [`Brush::polygonize`].
+	fn calculate_vertices(&self) -> impl Iterator<Item = (DVec3, [usize; 3])> {
+		let planes = self.planes().collect_vec();
+		let mut vertices: Vec<(DVec3, [usize; 3])> = Vec::new();
+
+		for plane_idx in 0..planes.len() {
+			let Some(winding) = clip_plane_winding(plane_idx, &planes) else { continue };
+
+			for (i, vertex) in winding.iter().enumerate() {
+				let previous = winding[(i + winding.len() - 1) % winding.len()];
+				// Edges left over from the initial polygon mean the hull isn't closed, so there's no real corner here
+				let (Some(previous_plane), Some(next_plane)) = (previous.edge_plane, vertex.edge_plane) else { continue };
+
+				if vertices
+					.iter()
+					.any(|(position, _)| position.almost_eq(vertex.position, BrushSurfacePolygon::VERTEX_PRECISION_MARGIN))
+				{
+					continue;
+				}
+
+				vertices.push((vertex.position, [plane_idx, previous_plane, next_plane]));
+			}
+		}
+
+		vertices.into_iter()
+	}
+
+	/// Casts a ray from `origin` along `direction`, returning where it enters the hull within `max_distance` (as a multiple of `direction`'s length).
+	///
+	/// Rays starting inside the hull don't hit it.
+	fn cast_ray(&self, origin: DVec3, direction: DVec3, max_distance: f64) -> Option<HullHit> {
+		cast_through_planes(
+			self.planes().enumerate().map(|(plane_idx, plane)| (Some(plane_idx), *plane)),
+			origin,
+			direction,
+			max_distance,
+		)
+	}
+
+	/// Casts a line segment from `start` to `end` against the hull, [`HullHit::distance`] being how far along the segment the hit is from 0 to 1.
+	fn cast_segment(&self, start: DVec3, end: DVec3) -> Option<HullHit> {
+		self.cast_ray(start, end - start, 1.)
+	}
+
+	/// Sweeps an axis-aligned box with `half_extents` centered on `origin` along `direction`, returning where it first touches the hull within `max_distance`.
+	///
+	/// Boxes starting inside the hull don't hit it.
+	fn cast_aabb(&self, origin: DVec3, half_extents: DVec3, direction: DVec3, max_distance: f64) -> Option<HullHit> {
+		cast_through_planes(expanded_planes(self, hull_bounds(self), half_extents), origin, direction, max_distance)
+	}
+
+	/// Sweeps an axis-aligned box with `half_extents` from `start` to `end` like Quake's `trace`, stopping `epsilon` short of whatever plane it hits.
+	///
+	/// Unlike [`cast_aabb`](Self::cast_aabb), this reports boxes starting inside the hull, see [`HullTrace::start_solid`].
+	fn trace(&self, start: DVec3, end: DVec3, half_extents: DVec3, epsilon: f64) -> HullTrace {
+		trace_hull(self, hull_bounds(self), start, end, half_extents, epsilon)
+	}
+
+	/// Returns `true` if this hull and `other` overlap or touch, else `false`.
+	fn intersects(&self, other: &impl ConvexHull) -> bool {
+		let planes = self.planes().chain(other.planes()).collect_vec();
+		(0..planes.len()).any(|plane_idx| clip_plane_winding(plane_idx, &planes).is_some())
+	}
+
+	/// Returns `true` if `plane` is intersecting the hull, or directly on one of the existing planes, else `false`.
+	fn contains_plane(&self, plane: &BrushPlane) -> bool {
+		!self
+			.calculate_vertices()
+			.map(|(vertex, _)| matches!(plane.point_side(vertex), 0.0..))
+			.all_equal()
+			|| self.planes().any(|lhs_plane| lhs_plane == plane)
+	}
+
+	/// Computes the average of all de-duplicated intersections.
+	fn center(&self) -> DVec3 {
+		let set = self
+			.calculate_vertices()
+			.map(|(pos, _)| pos.to_array().map(float_ord::FloatOrd))
+			.collect::<HashSet<_>>();
+
+		let set_len = set.len();
+		set.into_iter().map(|arr| DVec3::from_array(arr.map(|f| f.0))).sum::<DVec3>() / set_len as f64
+	}
+}
+
+impl ConvexHull for Brush {
+	#[inline]
+	fn planes(&self) -> impl Iterator<Item = &BrushPlane> + Clone {
+		self.surfaces.iter().map(|surface| &surface.plane)
+	}
+}
+
+/// A polygonal face calculated from a [`BrushSurface`], mainly used for rendering.
+#[derive(Debug, Clone)]
+pub struct BrushSurfacePolygon<'w> {
+	pub surface: &'w BrushSurface,
+	vertices: Vec<DVec3>,
+	indices: Vec<u32>,
+}
+
+impl<'w> BrushSurfacePolygon<'w> {
+	/// The margin that vertices can be off by, but are still treated as one.
+	pub const VERTEX_PRECISION_MARGIN: f64 = 0.0001;
+
+	/// Creates a new surface polygon, sorts and deduplicates the vertices, and calculates indices.
+	pub fn new(surface: &'w BrushSurface, mut vertices: Vec<DVec3>) -> Self {
+		let mut indices = Vec::new();
+
+		// Calculate 2d vertices
+
+		if vertices.len() > 2 {
+			// Make sure there aren't duplicates at the beginning, this is needed for proper sorting
+			while vertices[0].almost_eq(vertices[1], Self::VERTEX_PRECISION_MARGIN) {
+				vertices.remove(1);
+			}
+
+			// Sort vertices
+			let vert_0 = vertices[0];
+			let vert_1 = vertices[1];
+			let starting_vector = vert_1 - vert_0;
+
+			{
+				// We can't sort the first vertex, so here we skip it
+				let (_first, rest) = vertices.split_first_mut().unwrap();
+				rest.sort_unstable_by_key(|vertex| {
+					if *vertex == vert_1 {
+						return float_ord::FloatOrd(0.0);
+					}
+					// Make sure duplicates of vert_0 are at the start of the list for deduplication
+					if vertex.almost_eq(vert_0, Self::VERTEX_PRECISION_MARGIN) {
+						return float_ord::FloatOrd(f64::NEG_INFINITY);
+					}
+					let vertex_vector = *vertex - vert_0;
+
+					float_ord::FloatOrd(
+						vertex_vector
+							.cross(starting_vector)
+							.dot(surface.plane.normal)
+							.atan2(starting_vector.dot(vertex_vector)),
+					)
+				});
+			}
+
+			vertices.dedup_by(|a, b| a.almost_eq(*b, Self::VERTEX_PRECISION_MARGIN));
+
+			// Calculate indices
+			for i in 1..vertices.len() - 1 {
+				indices.extend([0, i + 1, i].map(|x| x as u32));
+			}
+		}
+
+		Self { surface, vertices, indices }
+	}
+
+	/// Creates a new surface polygon from vertices that are already in order around the polygon, such as from clipping.
+	///
+	/// Unlike [`Self::new`], this handles collinear vertices. The vertices are reversed if they wind the wrong way around the surface's normal.
+	pub fn from_ordered(surface: &'w BrushSurface, mut vertices: Vec<DVec3>) -> Self {
+		vertices.dedup_by(|a, b| a.almost_eq(*b, Self::VERTEX_PRECISION_MARGIN));
+		while vertices.len() > 1 && vertices[0].almost_eq(vertices[vertices.len() - 1], Self::VERTEX_PRECISION_MARGIN) {
+			vertices.pop();
+		}
+
+		// Newell's method, this is the sum of the cross products around the polygon, pointing in the direction the vertices wind counter-clockwise around
+		let winding_normal = (0..vertices.len())
+			.map(|i| vertices[i].cross(vertices[(i + 1) % vertices.len()]))
+			.sum::<DVec3>();
+		// Like `new`, vertices are clockwise around the normal
+		if winding_normal.dot(surface.plane.normal) > 0. {
+			vertices.reverse();
+		}
+
+		let indices = (1..vertices.len().saturating_sub(1))
+			.flat_map(|i| [0, i + 1, i])
+			.map(|x| x as u32)
+			.collect();
+
+		Self { surface, vertices, indices }
+	}
+
+	pub fn vertices(&self) -> &Vec<DVec3> {
+		&self.vertices
+	}
+	pub fn indices(&self) -> &Vec<u32> {
+		&self.indices
+	}
+}
+
+/// Combines a bunch of [`BrushSurfacePolygon`]s into a full mesh.
+///
+/// It is assumed all faces have the same material with the specified `texture_size`.
+pub fn generate_mesh_from_brush_polygons(polygons: &[BrushSurfacePolygon], config: &TrenchBroomConfig, texture_size: UVec2) -> Mesh {
+	let texture_size = texture_size.as_vec2();
+
+	let mut vertices: Vec<DVec3> = default();
+	let mut normals: Vec<DVec3> = default();
+	let mut uvs: Vec<Vec2> = default();
+	let mut indices: Vec<u32> = default();
+
+	// Combine the attributes of all the polygons
+	for polygon in polygons {
+		indices.extend(polygon.indices.iter().map(|x| vertices.len() as u32 + *x));
+		vertices.extend(&polygon.vertices);
+		normals.extend(repeat_n(polygon.surface.plane.normal, polygon.vertices.len()));
+		uvs.extend(polygon.vertices.iter().map(|vertex| {
+			// Texture-space position in texels, the same units TrenchBroom uses for offsets
+			let mut uv = match polygon.surface.uv.axes {
+				// Valve axes are stored in Bevy space, which is divided by the scale, so we need to scale back twice to get TrenchBroom units
+				Some([x_axis, y_axis]) => vec2(x_axis.dot(*vertex) as f32, y_axis.dot(*vertex) as f32) * config.scale * config.scale,
+				None => {
+					let [x_axis, y_axis] = polygon.surface.uv.standard_axes(polygon.surface.plane.normal.bevy_to_trenchbroom());
+					let vertex = config.from_bevy_space_f64(*vertex);
+					vec2(x_axis.dot(vertex) as f32, y_axis.dot(vertex) as f32)
+				}
+			};
+
+			uv /= polygon.surface.uv.scale.convert_zero_to_one();
+			uv += polygon.surface.uv.offset;
+
+			uv / texture_size
+		}));
+	}
+
+	assert_eq!(vertices.len(), normals.len());
+
+	// Convert attributes to single precision for creating the mesh
+	let [vertices, normals] = [vertices, normals].map(|x| x.into_iter().map(|x| x.as_vec3()).collect::<Vec<Vec3>>());
+
+	let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, config.brush_mesh_asset_usages);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+	if !uvs.is_empty() {
+		mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+	}
+	mesh.insert_indices(Indices::U32(indices));
+
+	if !polygons.is_empty() {
+		if let Err(err) = mesh.generate_tangents() {
+			error!("Failed to generate tangents for mesh with texture {}: {err}", polygons[0].surface.texture);
+		}
+	}
+
+	mesh
+}
+
+#[test]
+fn contains_point() {
+	let mut brush = Brush::default();
+	let normals = [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z];
+
+	for normal in normals {
+		brush.surfaces.push(BrushSurface {
+			plane: BrushPlane { normal, distance: -16. },
+			texture: default(),
+			uv: default(),
+			attributes: default(),
+		});
+	}
+
+	assert!(brush.contains_point(DVec3::ZERO));
+	for normal in normals {
+		assert!(brush.contains_point(normal));
+	}
+	assert!(!brush.contains_point(dvec3(32., 0., 0.)));
+	assert!(!brush.contains_point(dvec3(0., 32., 0.)));
+	assert!(!brush.contains_point(dvec3(0., 0., 32.)));
+}
+
+#[test]
+fn triangle_conversion() {
+	let tri_1 = [dvec3(-16., 16., -32.), dvec3(-16., 16., 16.), dvec3(-16., -16., 16.)];
+	let tri_2 = [dvec3(16., 16., 16.), dvec3(16., 16., -32.), dvec3(16., -16., -16.)];
+
+	let plane_1 = BrushPlane::from_triangle(tri_1);
+	let plane_2 = BrushPlane::from_triangle(tri_2);
+
+	assert_eq!(plane_1.normal, dvec3(-1., 0., 0.));
+	assert_eq!(plane_2.normal, dvec3(1., 0., 0.));
+	assert_eq!(plane_1.distance, -16.);
+	assert_eq!(plane_2.distance, -16.);
+}
+
+#[test]
+fn standard_texture_axes() {
+	let uv = BrushUV {
+		rotation: 90.,
+		scale: Vec2::ONE,
+		..default()
+	};
+
+	assert_eq!(uv.standard_axes(DVec3::Z), [DVec3::Y, DVec3::X]);
+	assert_eq!(BrushUV::default().standard_axes(DVec3::Z), [DVec3::X, DVec3::NEG_Y]);
+	// Slanted planes pick the closest axis
+	assert_eq!(BrushUV::default().standard_axes(dvec3(0.8, 0., 0.6)), [DVec3::Y, DVec3::NEG_Z]);
+}
+
+#[test]
+fn winding_polygonization() {
+	// A square pyramid, 4 planes meet at the top
+	let normals = [dvec3(1., 1., 0.), dvec3(-1., 1., 0.), dvec3(0., 1., 1.), dvec3(0., 1., -1.)].map(DVec3::normalize);
+	let mut brush = Brush::default();
+	for plane in normals
+		.map(|normal| BrushPlane { normal, distance: -normal.y })
+		.into_iter()
+		.chain([BrushPlane {
+			normal: DVec3::NEG_Y,
+			distance: 0.,
+		}]) {
+		brush.surfaces.push(BrushSurface {
+			plane,
+			texture: default(),
+			uv: default(),
+			attributes: default(),
+		});
+	}
+
+	assert_eq!(brush.calculate_vertices().count(), 5);
+
+	let polygons = brush.polygonize().collect_vec();
+	assert_eq!(polygons.len(), 5);
+	assert_eq!(
+		polygons.iter().map(|polygon| polygon.vertices().len()).sorted().collect_vec(),
+		[3, 3, 3, 3, 4]
+	);
+
+	for polygon in &polygons {
+		// Clockwise around the normal, so that triangles are counter-clockwise when viewed from the front
+		let [a, b, c] = [0, 1, 2].map(|i| polygon.vertices()[polygon.indices()[i] as usize]);
+		assert!((b - a).cross(c - a).dot(polygon.surface.plane.normal) > 0.);
+	}
+	// Cutting off the tip keeps all 4 planes meeting at the top, not just the 3 listed for that corner
+	let mut tip = brush.clone();
+	tip.cut(BrushSurface {
+		plane: BrushPlane {
+			normal: DVec3::NEG_Y,
+			distance: 0.9,
+		},
+		texture: default(),
+		uv: default(),
+		attributes: default(),
+	});
+	assert_eq!(tip.surfaces.len(), 5);
+	assert_eq!(tip.calculate_vertices().count(), 5);
+	assert!(tip.contains_point(dvec3(0., 0.95, 0.)));
+	assert!(!tip.contains_point(dvec3(0., 0.5, 0.)));
+}
+
+#[test]
+fn duplicated_planes() {
+	let mut brush = Brush::default();
+	let normals = [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z, DVec3::Z];
+
+	for normal in normals {
+		brush.surfaces.push(BrushSurface {
+			plane: BrushPlane { normal, distance: -16. },
+			texture: default(),
+			uv: default(),
+			attributes: default(),
+		});
+	}
+
+	let polygons = brush.polygonize().collect_vec();
+	assert_eq!(polygons.len(), 6);
+	assert_eq!(polygons.iter().filter(|polygon| polygon.surface.plane.normal == DVec3::Z).count(), 1);
+	assert!(polygons.iter().all(|polygon| polygon.vertices().len() == 4));
+	assert_eq!(brush.calculate_vertices().count(), 8);
+}
@@ -0,0 +1,95 @@
+//! Repair of T-junctions between [`BrushSurfacePolygon`]s.
+
+use super::*;
+
+/// Inserts vertices into the edges of `polygons` wherever a vertex of another polygon lies on them, so that neighboring polygons share edges exactly.
+///
+/// This removes the cracks that can appear where a large face meets multiple smaller ones.
+/// Polygons that had vertices inserted are triangulated around their center (which is added as their last vertex) to avoid creating degenerate triangles.
+pub fn fix_t_junctions(polygons: &mut [BrushSurfacePolygon]) {
+	let margin = BrushSurfacePolygon::VERTEX_PRECISION_MARGIN;
+
+	// Sorted along the X axis so that we only have to check vertices in range of each edge
+	let mut points = polygons.iter().flat_map(|polygon| polygon.vertices.iter().copied()).collect_vec();
+	points.sort_unstable_by(|a, b| a.x.total_cmp(&b.x));
+
+	for polygon in polygons.iter_mut() {
+		let vertices_len = polygon.vertices.len();
+		if vertices_len < 3 {
+			continue;
+		}
+
+		let mut new_vertices = Vec::with_capacity(vertices_len);
+
+		for i in 0..vertices_len {
+			let a = polygon.vertices[i];
+			let b = polygon.vertices[(i + 1) % vertices_len];
+			new_vertices.push(a);
+
+			let edge = b - a;
+			let edge_length_squared = edge.length_squared();
+			if edge_length_squared < margin * margin {
+				continue;
+			}
+
+			let start = points.partition_point(|point| point.x < a.x.min(b.x) - margin);
+			let max_x = a.x.max(b.x) + margin;
+
+			let mut on_edge = points[start..]
+				.iter()
+				.take_while(|point| point.x <= max_x)
+				.filter(|point| !point.almost_eq(a, margin) && !point.almost_eq(b, margin))
+				.filter_map(|point| {
+					let t = (*point - a).dot(edge) / edge_length_squared;
+					(t > 0. && t < 1. && (a + edge * t).distance(*point) < margin).then_some((t, *point))
+				})
+				.collect_vec();
+
+			on_edge.sort_unstable_by(|(a, _), (b, _)| a.total_cmp(b));
+			on_edge.dedup_by(|(_, a), (_, b)| a.almost_eq(*b, margin));
+
+			new_vertices.extend(on_edge.into_iter().map(|(_, point)| point));
+		}
+
+		if new_vertices.len() == vertices_len {
+			continue;
+		}
+
+		let new_len = new_vertices.len();
+		let center = new_vertices.iter().sum::<DVec3>() / new_len as f64;
+		new_vertices.push(center);
+
+		polygon.indices = (0..new_len).flat_map(|i| [new_len, (i + 1) % new_len, i]).map(|x| x as u32).collect();
+		polygon.vertices = new_vertices;
+	}
+}
+
+#[test]
+fn t_junction_repair() {
+	let surface = BrushSurface {
+		plane: BrushPlane {
+			normal: DVec3::Y,
+			distance: 0.,
+		},
+		texture: default(),
+		uv: default(),
+		attributes: default(),
+	};
+
+	// A large quad next to two smaller ones, whose shared vertex lies on the large quad's edge
+	let mut polygons = [
+		BrushSurfacePolygon::new(&surface, vec![dvec3(0., 0., 0.), dvec3(2., 0., 0.), dvec3(2., 0., 2.), dvec3(0., 0., 2.)]),
+		BrushSurfacePolygon::new(&surface, vec![dvec3(2., 0., 0.), dvec3(3., 0., 0.), dvec3(3., 0., 1.), dvec3(2., 0., 1.)]),
+		BrushSurfacePolygon::new(&surface, vec![dvec3(2., 0., 1.), dvec3(3., 0., 1.), dvec3(3., 0., 2.), dvec3(2., 0., 2.)]),
+	];
+
+	fix_t_junctions(&mut polygons);
+
+	// 4 corners, the inserted vertex, and the center
+	assert_eq!(polygons[0].vertices().len(), 6);
+	assert!(polygons[0].vertices().contains(&dvec3(2., 0., 1.)));
+	assert_eq!(polygons[0].indices().len(), 5 * 3);
+	// The smaller quads already share all their vertices
+	assert_eq!(polygons[1].vertices().len(), 4);
+	assert_eq!(polygons[2].vertices().len(), 4);
+}
@@ -0,0 +1,199 @@
+//! Quake-style swept box traces against [`ConvexHull`]s, for writing character controllers without a physics engine.
+
+use super::*;
+
+/// Quake's `DIST_EPSILON` in TrenchBroom units. Traces stop this far short of the planes they hit,
+/// so that the box isn't left touching the hull, which would make the next trace start inside it.
+///
+/// Divide by [`TrenchBroomConfig::scale`] to use with Bevy-space brushes.
+pub const QUAKE_TRACE_EPSILON: f64 = 0.03125;
+
+/// The result of sweeping a box from a start to an end position, like Quake's `trace_t`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HullTrace {
+	/// How far the box got before hitting something, from 0 to 1.
+	pub fraction: f64,
+	/// Where the center of the box ended up.
+	pub end: DVec3,
+	/// The plane that was hit, if any. This is the unexpanded plane of the hull, not offset by the box.
+	pub plane: Option<BrushPlane>,
+	/// The index of [`plane`](Self::plane) in [`ConvexHull::planes`], or `None` if nothing or a bevel plane was hit.
+	pub plane_idx: Option<usize>,
+	/// If the box started inside the hull.
+	pub start_solid: bool,
+	/// If the box was inside the hull the whole way, in which case [`fraction`](Self::fraction) is 0.
+	pub all_solid: bool,
+}
+impl HullTrace {
+	/// A trace that didn't hit anything.
+	pub fn unobstructed(end: DVec3) -> Self {
+		Self {
+			fraction: 1.,
+			end,
+			plane: None,
+			plane_idx: None,
+			start_solid: false,
+			all_solid: false,
+		}
+	}
+
+	/// Returns `true` if the trace was stopped before reaching its end.
+	pub fn hit(&self) -> bool {
+		self.fraction < 1.
+	}
+}
+
+/// Traces a box of `half_extents` from `start` to `end` through `hull` with the precomputed `bounds` of [`hull_bounds`], stopping `epsilon` short of whatever plane it hits.
+///
+/// This is Quake 2's `CM_ClipBoxToBrush`, using the planes from [`expanded_planes`] so that bevels keep the box from catching on edges.
+pub(crate) fn trace_hull(
+	hull: &(impl ConvexHull + ?Sized),
+	bounds: Option<(DVec3, DVec3)>,
+	start: DVec3,
+	end: DVec3,
+	half_extents: DVec3,
+	epsilon: f64,
+) -> HullTrace {
+	let mut trace = HullTrace::unobstructed(end);
+
+	let mut enter_fraction = -1_f64;
+	let mut leave_fraction = 1_f64;
+	let mut clip_plane: Option<(Option<usize>, BrushPlane)> = None;
+	let mut starts_out = false;
+	let mut gets_out = false;
+
+	for (plane_idx, plane) in expanded_planes(hull, bounds, half_extents) {
+		let start_side = plane.point_side(start);
+		let end_side = plane.point_side(end);
+
+		if start_side > 0. {
+			starts_out = true;
+		}
+		if end_side > 0. {
+			gets_out = true;
+		}
+
+		// Completely in front of this plane, so never inside the hull
+		if start_side > 0. && end_side >= start_side {
+			return trace;
+		}
+		// Completely behind this plane
+		if start_side <= 0. && end_side <= 0. {
+			continue;
+		}
+
+		if start_side > end_side {
+			// Entering
+			let fraction = (start_side - epsilon) / (start_side - end_side);
+			if fraction > enter_fraction {
+				enter_fraction = fraction;
+				clip_plane = Some((plane_idx, plane));
+			}
+		} else {
+			// Leaving
+			let fraction = (start_side + epsilon) / (start_side - end_side);
+			leave_fraction = leave_fraction.min(fraction);
+		}
+	}
+
+	if !starts_out {
+		trace.start_solid = true;
+		if !gets_out {
+			trace.all_solid = true;
+			trace.fraction = 0.;
+			trace.end = start;
+		}
+		return trace;
+	}
+
+	if enter_fraction < leave_fraction && enter_fraction > -1. {
+		if let Some((plane_idx, plane)) = clip_plane {
+			trace.fraction = enter_fraction.max(0.);
+			trace.end = start.lerp(end, trace.fraction);
+			trace.plane_idx = plane_idx;
+			// Undo the expansion from `expanded_planes`
+			trace.plane = Some(BrushPlane {
+				normal: plane.normal,
+				distance: plane.distance + plane.normal.abs().dot(half_extents),
+			});
+		}
+	}
+
+	trace
+}
+
+impl Brush {
+	/// Returns the surface that `trace` hit, if it was a trace against this brush.
+	pub fn trace_surface(&self, trace: &HullTrace) -> Option<&BrushSurface> {
+		trace.plane_idx.and_then(|plane_idx| self.surfaces.get(plane_idx))
+	}
+}
+
+#[test]
+fn hull_traces() {
+	let brush = Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane { normal, distance: -1. },
+				texture: default(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	};
+	let half_extents = DVec3::splat(0.5);
+
+	// Stops short of the brush by the epsilon
+	let trace = brush.trace(dvec3(-5., 0., 0.), dvec3(5., 0., 0.), half_extents, 0.1);
+	assert!(trace.hit());
+	assert!(!trace.start_solid);
+	assert!(trace.end.abs_diff_eq(dvec3(-1.6, 0., 0.), 1e-9));
+	assert_eq!(
+		trace.plane,
+		Some(BrushPlane {
+			normal: DVec3::NEG_X,
+			distance: -1.
+		})
+	);
+	assert_eq!(brush.trace_surface(&trace).unwrap().plane.normal, DVec3::NEG_X);
+
+	// Traces starting from where the last one stopped don't start solid
+	let trace = brush.trace(trace.end, dvec3(-1.6, 5., 0.), half_extents, 0.1);
+	assert!(!trace.hit());
+	assert!(!trace.start_solid);
+
+	// Passing by the sharp edge of a diamond would hit the expanded planes if not for the bevels
+	let diamond = Brush {
+		surfaces: [
+			dvec3(1., 1., 0.),
+			dvec3(1., -1., 0.),
+			dvec3(-1., 1., 0.),
+			dvec3(-1., -1., 0.),
+			DVec3::Z,
+			DVec3::NEG_Z,
+		]
+		.map(|normal| BrushSurface {
+			plane: BrushPlane {
+				normal: normal.normalize(),
+				distance: -1. / normal.length(),
+			},
+			texture: default(),
+			uv: default(),
+			attributes: default(),
+		})
+		.into(),
+	};
+	assert!(!diamond.trace(dvec3(1.7, -3., 0.), dvec3(1.7, 3., 0.), half_extents, 0.).hit());
+	assert!(diamond.trace(dvec3(1.4, -3., 0.), dvec3(1.4, 3., 0.), half_extents, 0.).hit());
+
+	// Moving out of the brush
+	let trace = brush.trace(DVec3::ZERO, dvec3(5., 0., 0.), half_extents, 0.1);
+	assert!(trace.start_solid);
+	assert!(!trace.all_solid);
+	assert_eq!(trace.fraction, 1.);
+
+	let trace = brush.trace(DVec3::ZERO, dvec3(0.5, 0., 0.), half_extents, 0.1);
+	assert!(trace.all_solid);
+	assert_eq!(trace.fraction, 0.);
+	assert_eq!(trace.end, DVec3::ZERO);
+}
@@ -0,0 +1,397 @@
+//! Parsing of GoldSrc (Half-Life) textures, which carry their own palettes, from BSP30 texture lumps and WAD3 files.
+
+use crate::*;
+
+/// The version number at the start of GoldSrc BSP files.
+pub const BSP30_VERSION: u32 = 30;
+/// The version number of Quake BSP files, which BSP30 shares everything but textures and lighting with.
+const BSP29_VERSION: u32 = 29;
+/// Indices of lumps in BSP30's lump directory, which follows the version number.
+const BSP30_TEXTURE_LUMP: usize = 2;
+const BSP30_FACE_LUMP: usize = 7;
+const BSP30_LIGHTING_LUMP: usize = 8;
+/// Size of a face in BSP29 and BSP30, and the offset of its lightmap offset.
+const FACE_SIZE: usize = 20;
+const FACE_LIGHT_OFFSET: usize = 16;
+/// Lump type of miptex textures in WAD3 files.
+const WAD3_MIPTEX_TYPE: u8 = 0x43;
+
+/// Returns `true` if `bsp` starts with the GoldSrc BSP version number.
+pub fn is_bsp30(bsp: &[u8]) -> bool {
+	read_u32(bsp, 0).is_ok_and(|version| version == BSP30_VERSION)
+}
+
+/// A GoldSrc texture, with its own palette instead of a shared one like in Quake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldSrcMipTex {
+	pub name: String,
+	pub width: u32,
+	pub height: u32,
+	/// Palette indices of the full-size mip level. Empty if the texture is stored in an external WAD.
+	pub pixels: Vec<u8>,
+	pub palette: Vec<[u8; 3]>,
+}
+impl GoldSrcMipTex {
+	/// Parses a miptex, with offsets relative to the start of `bytes`.
+	pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+		let name = read_name(bytes, 0)?;
+		let width = read_u32(bytes, 16)?;
+		let height = read_u32(bytes, 20)?;
+		let offsets = [0, 1, 2, 3].map(|i| read_u32(bytes, 24 + i * 4).map(|offset| offset as usize));
+		let [pixels_offset, _, _, last_mip_offset] = offsets;
+		let (pixels_offset, last_mip_offset) = (pixels_offset?, last_mip_offset?);
+
+		// Textures that live in WADs only have their header in the BSP
+		if pixels_offset == 0 {
+			return Ok(Self {
+				name,
+				width,
+				height,
+				pixels: Vec::new(),
+				palette: Vec::new(),
+			});
+		}
+
+		let pixel_count = width
+			.checked_mul(height)
+			.ok_or_else(|| anyhow!("texture size {width}x{height} too large"))?;
+		let pixels = read_bytes(bytes, pixels_offset, pixel_count as usize)?.to_vec();
+
+		// The palette follows the smallest mip level, which is an eighth of the size on each axis
+		let palette_offset = last_mip_offset + ((width / 8) * (height / 8)) as usize;
+		let color_count = read_bytes(bytes, palette_offset, 2).map(|count| u16::from_le_bytes([count[0], count[1]]) as usize)?;
+		let palette = read_bytes(bytes, palette_offset + 2, color_count * 3)?
+			.chunks_exact(3)
+			.map(|color| [color[0], color[1], color[2]])
+			.collect();
+
+		Ok(Self {
+			name,
+			width,
+			height,
+			pixels,
+			palette,
+		})
+	}
+
+	/// Whether this texture's data is stored in an external WAD.
+	pub fn is_external(&self) -> bool {
+		self.pixels.is_empty()
+	}
+
+	/// Converts this texture's pixels to RGBA8. If `cutout` is `true`, pixels with the index value `255` are made transparent.
+	pub fn to_rgba(&self, cutout: bool) -> Vec<u8> {
+		self.pixels
+			.iter()
+			.copied()
+			.flat_map(|pixel| {
+				if cutout && pixel == 255 {
+					[0; 4]
+				} else {
+					let [r, g, b] = self.palette.get(pixel as usize).copied().unwrap_or_default();
+					[r, g, b, 255]
+				}
+			})
+			.collect()
+	}
+}
+
+/// Parses the textures of a BSP30 file's texture lump. Textures stored in WADs only have their headers, see [`GoldSrcMipTex::is_external`].
+pub fn parse_bsp30_textures(bsp: &[u8]) -> anyhow::Result<Vec<GoldSrcMipTex>> {
+	let lump_offset = read_u32(bsp, 4 + BSP30_TEXTURE_LUMP * 8)? as usize;
+	let lump_len = read_u32(bsp, 4 + BSP30_TEXTURE_LUMP * 8 + 4)? as usize;
+	let lump = read_bytes(bsp, lump_offset, lump_len)?;
+
+	let texture_count = read_u32(lump, 0)? as usize;
+	let mut textures = Vec::with_capacity(texture_count);
+
+	for texture_idx in 0..texture_count {
+		let offset = read_u32(lump, 4 + texture_idx * 4)?;
+		// Missing textures have an offset of -1
+		if offset as i32 == -1 {
+			continue;
+		}
+		let texture = lump
+			.get(offset as usize..)
+			.ok_or_else(|| anyhow!("texture {texture_idx} out of bounds"))
+			.and_then(GoldSrcMipTex::parse)
+			.map_err(|err| anyhow!("Parsing texture {texture_idx}: {err}"))?;
+		textures.push(texture);
+	}
+
+	Ok(textures)
+}
+
+/// Converts a BSP30 file into a BSP29 file and the `.lit` file of its colored lighting, so that it can be parsed like a Quake BSP.
+///
+/// BSP30 lighting is RGB, with faces' lightmap offsets in bytes, so the BSP29 gets the average of each luxel, and its faces' offsets are divided by 3.
+/// Every other lump has the same layout in both formats, as the palettes of textures come after the data Quake reads.
+pub fn bsp30_to_bsp29(bsp: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+	if !is_bsp30(bsp) {
+		return Err(anyhow!("not a BSP30 file"));
+	}
+	let lump_range = |lump_idx: usize| -> anyhow::Result<std::ops::Range<usize>> {
+		let offset = read_u32(bsp, 4 + lump_idx * 8)? as usize;
+		let len = read_u32(bsp, 4 + lump_idx * 8 + 4)? as usize;
+		read_bytes(bsp, offset, len)?;
+		Ok(offset..offset + len)
+	};
+	let faces = lump_range(BSP30_FACE_LUMP)?;
+	let lighting = lump_range(BSP30_LIGHTING_LUMP)?;
+	if faces.len() % FACE_SIZE != 0 {
+		return Err(anyhow!("face lump size {} isn't a multiple of {FACE_SIZE}", faces.len()));
+	}
+
+	let mut converted = bsp.to_vec();
+	converted[0..4].copy_from_slice(&BSP29_VERSION.to_le_bytes());
+
+	// Mono lighting is a third of the size, so it fits where the RGB lighting was
+	let mono_lighting = bsp[lighting.clone()]
+		.chunks_exact(3)
+		.map(|color| ((color[0] as u32 + color[1] as u32 + color[2] as u32) / 3) as u8)
+		.collect_vec();
+	converted[lighting.start..lighting.start + mono_lighting.len()].copy_from_slice(&mono_lighting);
+	converted[4 + BSP30_LIGHTING_LUMP * 8 + 4..4 + BSP30_LIGHTING_LUMP * 8 + 8].copy_from_slice(&(mono_lighting.len() as u32).to_le_bytes());
+
+	for face_offset in faces.step_by(FACE_SIZE) {
+		let light_offset = face_offset + FACE_LIGHT_OFFSET;
+		let offset = read_u32(&converted, light_offset)?;
+		// Faces without lighting have an offset of -1
+		if offset as i32 == -1 {
+			continue;
+		}
+		if offset % 3 != 0 {
+			return Err(anyhow!("face lightmap offset {offset} isn't a multiple of 3"));
+		}
+		converted[light_offset..light_offset + 4].copy_from_slice(&(offset / 3).to_le_bytes());
+	}
+
+	let mut lit = b"QLIT".to_vec();
+	lit.extend(1_u32.to_le_bytes());
+	lit.extend(&bsp[lighting]);
+
+	Ok((converted, lit))
+}
+
+/// A WAD3 texture archive, as referenced by the `wad` worldspawn key of GoldSrc maps.
+#[derive(Debug, Clone, Default)]
+pub struct Wad3 {
+	/// Textures by their name in lowercase, as GoldSrc texture names are case-insensitive.
+	pub textures: HashMap<String, GoldSrcMipTex>,
+}
+impl Wad3 {
+	pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+		if !bytes.starts_with(b"WAD3") {
+			return Err(anyhow!("not a WAD3 file"));
+		}
+		let entry_count = read_u32(bytes, 4)? as usize;
+		let directory_offset = read_u32(bytes, 8)? as usize;
+
+		let mut textures = HashMap::default();
+
+		for entry_idx in 0..entry_count {
+			let entry = read_bytes(bytes, directory_offset + entry_idx * 32, 32)?;
+			let (ty, compression) = (entry[12], entry[13]);
+			if ty != WAD3_MIPTEX_TYPE || compression != 0 {
+				continue;
+			}
+
+			let texture = read_bytes(bytes, read_u32(entry, 0)? as usize, read_u32(entry, 4)? as usize)
+				.and_then(GoldSrcMipTex::parse)
+				.map_err(|err| anyhow!("Parsing WAD entry {entry_idx}: {err}"))?;
+			textures.insert(texture.name.to_lowercase(), texture);
+		}
+
+		Ok(Self { textures })
+	}
+
+	/// Gets a texture by name, ignoring case.
+	pub fn get(&self, name: &str) -> Option<&GoldSrcMipTex> {
+		self.textures.get(&name.to_lowercase())
+	}
+}
+
+/// Returns `true` if `texture` is a GoldSrc sky texture. GoldSrc draws skies with the `skyname` skybox, so these only hold a placeholder and shouldn't be rendered.
+pub fn is_sky_texture(texture: &str) -> bool {
+	texture.get(..3).is_some_and(|prefix| prefix.eq_ignore_ascii_case("sky"))
+}
+
+/// Returns the file names of the WADs in a `wad` worldspawn key, which are separated by `;`, and often absolute paths on the mapper's machine.
+pub fn wad_file_names(wad_key: &str) -> impl Iterator<Item = &str> {
+	wad_key
+		.split(';')
+		.filter_map(|path| path.rsplit(['/', '\\']).next())
+		.map(str::trim)
+		.filter(|name| !name.is_empty())
+}
+
+fn read_bytes(bytes: &[u8], offset: usize, len: usize) -> anyhow::Result<&[u8]> {
+	bytes
+		.get(offset..offset.saturating_add(len))
+		.ok_or_else(|| anyhow!("{len} bytes at offset {offset} out of bounds of {}", bytes.len()))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> anyhow::Result<u32> {
+	read_bytes(bytes, offset, 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads a null-terminated name of up to 16 bytes.
+fn read_name(bytes: &[u8], offset: usize) -> anyhow::Result<String> {
+	let name = read_bytes(bytes, offset, 16)?;
+	let len = name.iter().position(|byte| *byte == 0).unwrap_or(name.len());
+	Ok(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+/// An 8x8 miptex with 2 colors, or only its header if not `embedded`.
+#[cfg(test)]
+fn test_miptex(name: &str, embedded: bool) -> Vec<u8> {
+	let mut bytes = vec![0; 16];
+	bytes[..name.len()].copy_from_slice(name.as_bytes());
+	bytes.extend(8_u32.to_le_bytes());
+	bytes.extend(8_u32.to_le_bytes());
+	if !embedded {
+		bytes.extend([0; 16]);
+		return bytes;
+	}
+	// Mip levels of 64, 16, 4 and 1 pixels
+	for offset in [40_u32, 104, 120, 124] {
+		bytes.extend(offset.to_le_bytes());
+	}
+	bytes.extend((0..64).map(|i| if i == 0 { 255 } else { 1 }));
+	bytes.extend([0; 16 + 4 + 1]);
+	bytes.extend(2_u16.to_le_bytes());
+	bytes.extend([10, 20, 30, 40, 50, 60]);
+	bytes
+}
+
+#[test]
+fn goldsrc_textures() {
+	let texture = GoldSrcMipTex::parse(&test_miptex("{fence", true)).unwrap();
+	assert_eq!(texture.name, "{fence");
+	assert_eq!((texture.width, texture.height), (8, 8));
+	assert_eq!(texture.palette, vec![[10, 20, 30], [40, 50, 60]]);
+	let rgba = texture.to_rgba(true);
+	assert_eq!(rgba.len(), 64 * 4);
+	assert_eq!(rgba[0..8], [0, 0, 0, 0, 40, 50, 60, 255]);
+	assert_eq!(texture.to_rgba(false)[0..4], [0, 0, 0, 255]);
+	assert!(GoldSrcMipTex::parse(&test_miptex("sky", false)).unwrap().is_external());
+
+	// BSP30 with a texture lump containing an embedded texture, a missing one, and an external one
+	let mut bsp = BSP30_VERSION.to_le_bytes().to_vec();
+	bsp.extend([0; 15 * 8]);
+	let lump_offset = bsp.len() as u32;
+	let embedded = test_miptex("!water", true);
+	let mut lump = 3_u32.to_le_bytes().to_vec();
+	for offset in [16, u32::MAX, 16 + embedded.len() as u32] {
+		lump.extend(offset.to_le_bytes());
+	}
+	lump.extend(embedded);
+	lump.extend(test_miptex("sky", false));
+	bsp[4 + 16..4 + 24].copy_from_slice(&[lump_offset.to_le_bytes(), (lump.len() as u32).to_le_bytes()].concat());
+	bsp.extend(lump);
+
+	assert!(is_bsp30(&bsp));
+	let textures = parse_bsp30_textures(&bsp).unwrap();
+	assert_eq!(textures.iter().map(|texture| texture.name.as_str()).collect_vec(), ["!water", "sky"]);
+	assert!(!textures[0].is_external());
+	assert!(textures[1].is_external());
+
+	// WAD3 with the external texture
+	let sky = test_miptex("SKY", true);
+	let mut wad = b"WAD3".to_vec();
+	wad.extend(1_u32.to_le_bytes());
+	wad.extend((12 + sky.len() as u32).to_le_bytes());
+	wad.extend(&sky);
+	wad.extend(12_u32.to_le_bytes());
+	wad.extend((sky.len() as u32).to_le_bytes());
+	wad.extend((sky.len() as u32).to_le_bytes());
+	wad.extend([WAD3_MIPTEX_TYPE, 0, 0, 0]);
+	wad.extend(&sky[..16]);
+
+	let wad = Wad3::parse(&wad).unwrap();
+	assert_eq!(wad.get("sky").map(|texture| texture.pixels.len()), Some(64));
+
+	assert_eq!(
+		wad_file_names(r"\half-life\valve\halflife.wad;C:\maps\custom.wad;").collect_vec(),
+		["halflife.wad", "custom.wad"]
+	);
+}
+
+#[test]
+fn goldsrc_sky_textures() {
+	assert!(is_sky_texture("sky"));
+	assert!(is_sky_texture("SKY"));
+	assert!(is_sky_texture("sky_blue"));
+	assert!(!is_sky_texture("sk"));
+	assert!(!is_sky_texture("c1a0_sky"));
+	assert!(!is_sky_texture("!water"));
+}
+
+#[test]
+fn bsp30_loading() {
+	fn le(values: &[i32]) -> Vec<u8> {
+		values.iter().flat_map(|value| value.to_le_bytes()).collect()
+	}
+	fn le16(values: &[i16]) -> Vec<u8> {
+		values.iter().flat_map(|value| value.to_le_bytes()).collect()
+	}
+	fn lef(values: &[f32]) -> Vec<u8> {
+		values.iter().flat_map(|value| value.to_le_bytes()).collect()
+	}
+
+	// A single 16x16 face on the floor of one empty leaf
+	let mut textures = le(&[1, 8]);
+	textures.extend(test_miptex("floor", true));
+	let lighting = (0..4).flat_map(|luxel| [30 * luxel, 60, 90]).collect_vec();
+	let lumps: [Vec<u8>; 15] = [
+		b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec(),
+		[lef(&[0., 0., 1., 0.]), le(&[2])].concat(),
+		textures,
+		lef(&[0., 0., 0., 16., 0., 0., 16., 16., 0., 0., 16., 0.]),
+		Vec::new(),
+		[le(&[0]), le16(&[-2, -1, 0, 0, 0, 16, 16, 16]), le16(&[0, 1])].concat(),
+		[lef(&[1., 0., 0., 0., 0., 1., 0., 0.]), le(&[0, 0])].concat(),
+		[le16(&[0, 0]), le(&[0]), le16(&[4, 0]), vec![0, 255, 255, 255], le(&[0])].concat(),
+		lighting.clone(),
+		[le(&[0]), le16(&[-1, -2])].concat(),
+		[
+			le(&[-2, -1]),
+			le16(&[0; 8]),
+			vec![0; 4],
+			le(&[-1, -1]),
+			le16(&[0, 0, 0, 16, 16, 16, 0, 1]),
+			vec![0; 4],
+		]
+		.concat(),
+		le16(&[0]),
+		le16(&[0, 0, 0, 1, 1, 2, 2, 3, 3, 0]),
+		le(&[1, 2, 3, 4]),
+		[lef(&[0., 0., -1., 16., 16., 16., 0., 0., 0.]), le(&[0, 0, 0, 0, 1, 0, 1])].concat(),
+	];
+
+	let mut bsp = BSP30_VERSION.to_le_bytes().to_vec();
+	let mut offset = 4 + lumps.len() * 8;
+	for lump in &lumps {
+		bsp.extend(le(&[offset as i32, lump.len() as i32]));
+		offset += lump.len();
+	}
+	bsp.extend(lumps.concat());
+
+	let (converted, lit) = bsp30_to_bsp29(&bsp).unwrap();
+	assert_eq!(read_u32(&converted, 0).unwrap(), BSP29_VERSION);
+	assert_eq!(lit[8..], lighting);
+
+	let data = BspData::parse(BspParseInput {
+		bsp: &converted,
+		lit: Some(&lit),
+		settings: TrenchBroomConfig::default().bsp_parse_settings,
+	})
+	.unwrap();
+	assert_eq!(data.models.len(), 1);
+	assert_eq!(data.leaves.len(), 2);
+	assert_eq!(data.faces.len(), 1);
+	assert!(data.lighting.is_some());
+
+	// Only BSP30 files can be converted
+	assert!(bsp30_to_bsp29(&converted).is_err());
+}
@@ -0,0 +1,441 @@
+//! BSP trees of models' hulls in Bevy space, for collision on BSPs that weren't compiled with the `BRUSHLIST` BSPX lump, and point queries.
+
+use qbsp::data::bsp::{BspClipNodeRef, BspLeafContents, BspNodeRef};
+
+use super::*;
+
+/// A BSP tree of one of a model's hulls.
+///
+/// Hull 0 is made from nodes and leaves, and is the exact shape of the model. Hulls 1 and 2 are made from clip nodes,
+/// and are expanded by the size of the player and of large monsters respectively, so that Quake can collide boxes as points.
+#[derive(Debug, Clone)]
+pub struct BspHull {
+	/// Every node in the BSP of this hull's type, not just the ones in this model.
+	pub nodes: Vec<BspHullNode>,
+	pub root: BspHullChild,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BspHullNode {
+	/// The plane splitting this node's space, where the front side is where [`BrushPlane::point_side`] is positive.
+	pub plane: BrushPlane,
+	/// The front and back children respectively.
+	pub children: [BspHullChild; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BspHullChild {
+	Node(usize),
+	Leaf {
+		contents: BspLeafContents,
+		/// The index of the leaf in the BSP, only available in hull 0, as clip nodes don't have leaves.
+		leaf_idx: Option<usize>,
+	},
+}
+
+impl BspHull {
+	/// Reads hull `hull_idx` of model `model_idx` from `data`, returning [`None`] if either doesn't exist.
+	pub fn new(data: &BspData, model_idx: usize, hull_idx: usize, config: &TrenchBroomConfig) -> Option<Self> {
+		let model = data.models.get(model_idx)?;
+
+		let convert_plane = |plane_idx: u32| -> Option<BrushPlane> {
+			let plane = data.planes.get(plane_idx as usize)?;
+			Some(BrushPlane {
+				normal: plane.normal.as_dvec3().trenchbroom_to_bevy(),
+				distance: -plane.dist as f64 / config.scale as f64,
+			})
+		};
+
+		match hull_idx {
+			0 => Some(Self {
+				nodes: data
+					.nodes
+					.iter()
+					.map(|node| {
+						Some(BspHullNode {
+							plane: convert_plane(node.plane_idx)?,
+							children: [node.front, node.back].map(|child| match child {
+								BspNodeRef::Node(node_idx) => BspHullChild::Node(node_idx as usize),
+								BspNodeRef::Leaf(leaf_idx) => BspHullChild::Leaf {
+									// Quake treats leaves that don't exist as solid
+									contents: data
+										.leaves
+										.get(leaf_idx as usize)
+										.map(|leaf| leaf.contents)
+										.unwrap_or(BspLeafContents::Solid),
+									leaf_idx: Some(leaf_idx as usize),
+								},
+							}),
+						})
+					})
+					.collect::<Option<_>>()?,
+				root: BspHullChild::Node(model.head_bsp_node as usize),
+			}),
+			1 | 2 => Some(Self {
+				nodes: data
+					.clip_nodes
+					.iter()
+					.map(|node| {
+						Some(BspHullNode {
+							plane: convert_plane(node.plane_idx)?,
+							children: [node.front, node.back].map(|child| match child {
+								BspClipNodeRef::Node(node_idx) => BspHullChild::Node(node_idx as usize),
+								BspClipNodeRef::Leaf(contents) => BspHullChild::Leaf { contents, leaf_idx: None },
+							}),
+						})
+					})
+					.collect::<Option<_>>()?,
+				root: BspHullChild::Node(match hull_idx {
+					1 => model.first_clip_node,
+					_ => model.second_clip_node,
+				} as usize),
+			}),
+			_ => None,
+		}
+	}
+
+	/// Returns a convex brush for every solid or sky leaf of this hull, clipped to the box from `min` to `max` as solid leaves on the outside of the map are unbounded.
+	/// Like in Quake, sky is treated as solid, so that it can't be walked through.
+	///
+	/// This usually produces many more, smaller brushes than the ones the map was made of.
+	/// Clip brushes only exist in hulls 1 and 2, which are expanded by the size of their boxes, so brushes from hull 0 don't include them.
+	pub fn solid_brushes(&self, min: DVec3, max: DVec3) -> Vec<BspBrush> {
+		#[rustfmt::skip]
+		let bounds = vec![
+			BrushPlane { normal: DVec3::X,     distance: -max.x },
+			BrushPlane { normal: DVec3::NEG_X, distance:  min.x },
+			BrushPlane { normal: DVec3::Y,     distance: -max.y },
+			BrushPlane { normal: DVec3::NEG_Y, distance:  min.y },
+			BrushPlane { normal: DVec3::Z,     distance: -max.z },
+			BrushPlane { normal: DVec3::NEG_Z, distance:  min.z },
+		];
+
+		let mut brushes = Vec::new();
+		let mut stack = vec![(self.root, bounds)];
+
+		while let Some((child, planes)) = stack.pop() {
+			match child {
+				BspHullChild::Node(node_idx) => {
+					let Some(node) = self.nodes.get(node_idx) else { continue };
+
+					let mut front_planes = planes.clone();
+					front_planes.push(BrushPlane {
+						normal: -node.plane.normal,
+						distance: -node.plane.distance,
+					});
+					let mut back_planes = planes;
+					back_planes.push(node.plane);
+
+					stack.push((node.children[0], front_planes));
+					stack.push((node.children[1], back_planes));
+				}
+				BspHullChild::Leaf { contents, .. } => {
+					if !matches!(contents, BspLeafContents::Solid | BspLeafContents::Sky) {
+						continue;
+					}
+
+					let brush = BspBrush {
+						planes,
+						contents: contents as i32,
+						textures: Vec::new(),
+					};
+					// Skip leaves entirely outside of the bounds, or too thin to make a collider from
+					let Some((leaf_min, leaf_max)) = brush
+						.calculate_vertices()
+						.map(|(vertex, _)| (vertex, vertex))
+						.reduce(|(min, max), (vertex, _)| (min.min(vertex), max.max(vertex)))
+					else {
+						continue;
+					};
+					if (leaf_max - leaf_min).min_element() > 1e-5 {
+						brushes.push(brush);
+					}
+				}
+			}
+		}
+
+		brushes
+	}
+}
+
+impl BspHull {
+	/// Returns the leaf of this hull containing the Bevy-space `point`, being a [`BspHullChild::Leaf`].
+	///
+	/// If the tree is malformed (a node is missing, or the nodes loop), this is a solid leaf, like leaves that don't exist.
+	pub fn point_leaf(&self, point: DVec3) -> BspHullChild {
+		let malformed = BspHullChild::Leaf {
+			contents: BspLeafContents::Solid,
+			leaf_idx: None,
+		};
+		let mut child = self.root;
+
+		// A valid path can't visit more nodes than there are
+		for _ in 0..=self.nodes.len() {
+			let BspHullChild::Node(node_idx) = child else { return child };
+			let Some(node) = self.nodes.get(node_idx) else { return malformed };
+			// Like Quake, points directly on the plane are in front of it
+			child = node.children[if node.plane.point_side(point) >= 0. { 0 } else { 1 }];
+		}
+
+		malformed
+	}
+
+	/// Returns the contents of the leaf containing the Bevy-space `point`.
+	///
+	/// For hulls 1 and 2, this is the contents at the center of a box of their size.
+	pub fn point_contents(&self, point: DVec3) -> BspLeafContents {
+		match self.point_leaf(point) {
+			BspHullChild::Leaf { contents, .. } => contents,
+			BspHullChild::Node(_) => unreachable!(),
+		}
+	}
+
+	/// Returns the indices of the non-solid leaves that the convex polygon with `vertices` and `normal` touches, sorted.
+	///
+	/// Polygons lying on a node's plane go to the side they face, as that's the side they can be seen from.
+	/// Straddling polygons are sent down both sides without being clipped, so this may include a few leaves they don't actually reach.
+	pub fn polygon_leaves(&self, vertices: &[DVec3], normal: DVec3) -> Vec<usize> {
+		const EPSILON: f64 = 1e-4;
+
+		let mut leaves = Vec::new();
+		let mut stack = vec![self.root];
+
+		while let Some(child) = stack.pop() {
+			match child {
+				BspHullChild::Node(node_idx) => {
+					let Some(node) = self.nodes.get(node_idx) else { continue };
+					let (min, max) = vertices
+						.iter()
+						.map(|vertex| node.plane.point_side(*vertex))
+						.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), side| (min.min(side), max.max(side)));
+
+					let front = max > EPSILON || (min >= -EPSILON && normal.dot(node.plane.normal) >= 0.);
+					let back = min < -EPSILON || (max <= EPSILON && normal.dot(node.plane.normal) < 0.);
+					if front {
+						stack.push(node.children[0]);
+					}
+					if back {
+						stack.push(node.children[1]);
+					}
+				}
+				BspHullChild::Leaf {
+					contents,
+					leaf_idx: Some(leaf_idx),
+				} if contents != BspLeafContents::Solid => leaves.push(leaf_idx),
+				BspHullChild::Leaf { .. } => {}
+			}
+		}
+
+		leaves.sort_unstable();
+		leaves.dedup();
+		leaves
+	}
+}
+
+impl BspHull {
+	/// Returns the indices of the non-solid leaves that the axis-aligned box from `min` to `max` touches, sorted.
+	pub fn box_leaves(&self, min: DVec3, max: DVec3) -> Vec<usize> {
+		let (center, extents) = ((min + max) / 2., (max - min) / 2.);
+
+		let mut leaves = Vec::new();
+		let mut stack = vec![self.root];
+		// A valid tree can't visit more nodes than there are, this keeps malformed ones from looping forever
+		let mut budget = self.nodes.len();
+
+		while let Some(child) = stack.pop() {
+			match child {
+				BspHullChild::Node(node_idx) => {
+					let Some(node) = self.nodes.get(node_idx) else { continue };
+					let Some(remaining) = budget.checked_sub(1) else { break };
+					budget = remaining;
+
+					let side = node.plane.point_side(center);
+					let radius = extents.dot(node.plane.normal.abs());
+					if side + radius >= 0. {
+						stack.push(node.children[0]);
+					}
+					if side - radius < 0. {
+						stack.push(node.children[1]);
+					}
+				}
+				BspHullChild::Leaf {
+					contents,
+					leaf_idx: Some(leaf_idx),
+				} if contents != BspLeafContents::Solid => leaves.push(leaf_idx),
+				BspHullChild::Leaf { .. } => {}
+			}
+		}
+
+		leaves.sort_unstable();
+		leaves.dedup();
+		leaves
+	}
+}
+
+impl Bsp {
+	/// Returns the index of the leaf in [`BspData::leaves`] containing the Bevy-space `point`, walking the nodes of the world model.
+	///
+	/// Returns [`None`] if the BSP tree is malformed, such as if a node is missing or the nodes loop.
+	pub fn point_leaf(&self, point: Vec3, config: &TrenchBroomConfig) -> Option<usize> {
+		let point = config.from_bevy_space(point);
+		let mut node_idx = self.data.models.first()?.head_bsp_node as usize;
+
+		// A valid path can't visit more nodes than there are
+		for _ in 0..self.data.nodes.len() {
+			let node = self.data.nodes.get(node_idx)?;
+			let plane = self.data.planes.get(node.plane_idx as usize)?;
+
+			match if plane.normal.dot(point) - plane.dist >= 0. {
+				node.front
+			} else {
+				node.back
+			} {
+				BspNodeRef::Node(child_idx) => node_idx = child_idx as usize,
+				BspNodeRef::Leaf(leaf_idx) => return Some(leaf_idx as usize),
+			}
+		}
+
+		None
+	}
+
+	/// Returns what the Bevy-space `point` is inside of, such as empty space, solid, water, slime, lava or sky.
+	///
+	/// Useful for swimming, damage volumes, or underwater effects. Points outside of the map are solid.
+	pub fn point_contents(&self, point: Vec3, config: &TrenchBroomConfig) -> BspLeafContents {
+		self.point_leaf(point, config)
+			.and_then(|leaf_idx| self.data.leaves.get(leaf_idx))
+			.map(|leaf| leaf.contents)
+			.unwrap_or(BspLeafContents::Solid)
+	}
+}
+
+#[test]
+fn hull_solid_brushes() {
+	let solid = BspHullChild::Leaf {
+		contents: BspLeafContents::Solid,
+		leaf_idx: None,
+	};
+	let sky = BspHullChild::Leaf {
+		contents: BspLeafContents::Sky,
+		leaf_idx: None,
+	};
+	let empty = BspHullChild::Leaf {
+		contents: BspLeafContents::Empty,
+		leaf_idx: None,
+	};
+
+	// Solid where x > 0.5, and sky where x < 0.5 and y > 0
+	let hull = BspHull {
+		nodes: vec![
+			BspHullNode {
+				plane: BrushPlane {
+					normal: DVec3::X,
+					distance: -0.5,
+				},
+				children: [solid, BspHullChild::Node(1)],
+			},
+			BspHullNode {
+				plane: BrushPlane {
+					normal: DVec3::Y,
+					distance: 0.,
+				},
+				children: [sky, empty],
+			},
+		],
+		root: BspHullChild::Node(0),
+	};
+
+	let brushes = hull.solid_brushes(DVec3::splat(-1.), DVec3::splat(1.));
+	assert_eq!(brushes.len(), 2);
+	assert!(brushes.iter().any(|brush| brush.contains_point(dvec3(0.75, -0.5, 0.))));
+	assert!(brushes.iter().any(|brush| brush.contains_point(dvec3(-0.5, 0.5, 0.))));
+	assert!(!brushes.iter().any(|brush| brush.contains_point(dvec3(-0.5, -0.5, 0.))));
+	assert!(!brushes.iter().any(|brush| brush.contains_point(dvec3(2., 0., 0.))));
+}
+
+#[test]
+fn hull_point_contents() {
+	let hull = BspHull {
+		nodes: vec![BspHullNode {
+			plane: BrushPlane {
+				normal: DVec3::Y,
+				distance: 0.,
+			},
+			children: [
+				BspHullChild::Leaf {
+					contents: BspLeafContents::Empty,
+					leaf_idx: Some(1),
+				},
+				BspHullChild::Leaf {
+					contents: BspLeafContents::Water,
+					leaf_idx: Some(2),
+				},
+			],
+		}],
+		root: BspHullChild::Node(0),
+	};
+
+	assert_eq!(hull.point_contents(dvec3(0., 1., 0.)), BspLeafContents::Empty);
+	assert_eq!(hull.point_contents(dvec3(0., 0., 0.)), BspLeafContents::Empty);
+	assert_eq!(hull.point_contents(dvec3(5., -1., 0.)), BspLeafContents::Water);
+
+	// Floor on the plane facing up, a wall straddling it
+	let floor = [dvec3(0., 0., 0.), dvec3(1., 0., 0.), dvec3(1., 0., 1.)];
+	assert_eq!(hull.polygon_leaves(&floor, DVec3::Y), vec![1]);
+	assert_eq!(hull.polygon_leaves(&floor, DVec3::NEG_Y), vec![2]);
+	let wall = [dvec3(0., -1., 0.), dvec3(1., -1., 0.), dvec3(1., 1., 0.)];
+	assert_eq!(hull.polygon_leaves(&wall, DVec3::Z), vec![1, 2]);
+
+	assert_eq!(hull.box_leaves(dvec3(0., -1., 0.), DVec3::ONE), vec![1, 2]);
+	assert_eq!(hull.box_leaves(dvec3(0., 0.5, 0.), DVec3::ONE), vec![1]);
+}
+
+#[test]
+fn hull_malformed_point_leaf() {
+	// Points back to itself forever
+	let hull = BspHull {
+		nodes: vec![BspHullNode {
+			plane: BrushPlane {
+				normal: DVec3::Y,
+				distance: 0.,
+			},
+			children: [BspHullChild::Node(0); 2],
+		}],
+		root: BspHullChild::Node(0),
+	};
+
+	assert_eq!(hull.point_contents(DVec3::ZERO), BspLeafContents::Solid);
+}
+
+#[test]
+fn bsp_point_contents() {
+	let config = TrenchBroomConfig::default();
+	let data = BspData::parse(BspParseInput {
+		bsp: include_bytes!("../../assets/maps/example.bsp"),
+		lit: None,
+		settings: config.bsp_parse_settings.clone(),
+	})
+	.unwrap();
+	let bsp = Bsp {
+		scene: default(),
+		embedded_textures: default(),
+		#[cfg(feature = "client")]
+		lightmap: None,
+		#[cfg(feature = "client")]
+		irradiance_volume: None,
+		models: Vec::new(),
+		pvs: None,
+		data,
+		entities: default(),
+	};
+
+	// Where a light is in example.map
+	let light = config.to_bevy_space(vec3(-36., 28., 40.));
+	assert!(bsp.point_leaf(light, &config).is_some());
+	assert_eq!(bsp.point_contents(light, &config), BspLeafContents::Empty);
+	// Way outside of the map
+	assert_eq!(
+		bsp.point_contents(config.to_bevy_space(Vec3::splat(100_000.)), &config),
+		BspLeafContents::Solid
+	);
+}
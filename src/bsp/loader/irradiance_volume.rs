@@ -11,7 +11,7 @@ use ndshape::{RuntimeShape, Shape};
 use qbsp::data::bspx::LightGridCell;
 
 pub fn load_irradiance_volume(ctx: &mut BspLoadCtx, world: &mut World) -> anyhow::Result<Option<Handle<AnimatedLighting>>> {
-	let config = &ctx.loader.tb_server.config;
+	let config = &ctx.tb_server.config;
 
 	if config.no_bsp_lighting {
 		return Ok(None);
@@ -18,7 +18,7 @@ pub struct BspLightmap {
 
 impl BspLightmap {
 	pub fn compute(ctx: &mut BspLoadCtx) -> anyhow::Result<Option<Self>> {
-		let config = &ctx.loader.tb_server.config;
+		let config = &ctx.tb_server.config;
 
 		if config.no_bsp_lighting {
 			return Ok(None);
@@ -57,6 +57,30 @@ impl BspLightmap {
 
 				let mut i = 0;
 				let input = atlas.data.slots.map(|image| {
+					let (data, format) = match ctx.hdr_lightmap {
+						Some(hdr_lightmap) => (
+							image
+								.pixels()
+								.zip(hdr_lightmap.luxel_index_slots[i].pixels())
+								.flat_map(|(pixel, index)| {
+									// Pixels that aren't from a luxel (like ones filled in for faces without lighting) use the normal lightmap
+									let color = match luxel_index(index).and_then(|luxel_idx| hdr_lightmap.luxels.get(luxel_idx)) {
+										Some(luxel) => decode_e5bgr9(*luxel),
+										None => [pixel[0], pixel[1], pixel[2]].map(|channel| channel as f32 / 255.),
+									};
+									// Treated as sRGB like normal lightmaps below so that they look the same, just without clipping
+									encode_rgb9e5(color.map(Srgba::gamma_function)).to_le_bytes()
+								})
+								.collect(),
+							TextureFormat::Rgb9e5Ufloat,
+						),
+						None => (
+							image.pixels().flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255]).collect(),
+							// Without Srgb all the colors are washed out, so i'm guessing ericw-tools outputs sRGB, though i can't find it documented anywhere.
+							TextureFormat::Rgba8UnormSrgb,
+						),
+					};
+
 					let handle = ctx.load_context.add_labeled_asset(
 						format!("LightmapInput{i}"),
 						Image::new(
@@ -66,9 +90,8 @@ impl BspLightmap {
 								..default()
 							},
 							TextureDimension::D2,
-							image.pixels().flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255]).collect(),
-							// Without Srgb all the colors are washed out, so i'm guessing ericw-tools outputs sRGB, though i can't find it documented anywhere.
-							TextureFormat::Rgba8UnormSrgb,
+							data,
+							format,
 							config.bsp_textures_asset_usages,
 						),
 					);
@@ -112,3 +135,176 @@ impl BspLightmap {
 		}
 	}
 }
+
+/// HDR lighting, along with where each of its luxels is in the lightmap atlas.
+pub struct HdrLightmap {
+	/// One E5BGR9 value per luxel.
+	luxels: Vec<u32>,
+	/// The atlas slots packed with the index of each luxel plus one as its color, see [`luxel_index`].
+	luxel_index_slots: [image::RgbImage; 4],
+}
+impl HdrLightmap {
+	/// Reads HDR lighting from a HDR `.lit` file if there is one, otherwise from the `LIGHTING_E5BGR9` BSPX lump of `bsp`.
+	///
+	/// To find where luxels are placed in the atlas, it's packed again with each luxel's index as its lighting.
+	/// This relies on qbsp placing lightmaps regardless of their contents, and swaps out the lighting of `data` in the meantime instead of cloning all of it.
+	pub fn new(data: &mut BspData, bsp: &[u8], hdr_lit: Option<&[u8]>, config: &TrenchBroomConfig) -> Option<Self> {
+		if config.no_bsp_lighting {
+			return None;
+		}
+
+		let luxels = hdr_lit
+			.or_else(|| read_bspx_lump(bsp, "LIGHTING_E5BGR9"))?
+			.chunks_exact(4)
+			.map(|luxel| u32::from_le_bytes([luxel[0], luxel[1], luxel[2], luxel[3]]))
+			.collect_vec();
+		let luxel_index_slots = compute_luxel_index_slots(data, luxels.len(), config.compute_lightmap_settings)?;
+
+		Some(Self { luxels, luxel_index_slots })
+	}
+}
+
+/// Computes the lightmap atlas with the index of each luxel plus one encoded in its color, leaving the lighting of `data` as it was.
+fn compute_luxel_index_slots(data: &mut BspData, luxel_count: usize, settings: ComputeLightmapSettings) -> Option<[image::RgbImage; 4]> {
+	// Indices have to fit in 24 bits
+	if luxel_count >= 1 << 24 {
+		return None;
+	}
+
+	let lighting = mem::replace(
+		&mut data.lighting,
+		Some(BspLighting::Colored(
+			(1..=luxel_count as u32)
+				.map(|luxel_idx| {
+					let [r, g, b, _] = luxel_idx.to_le_bytes();
+					[r, g, b]
+				})
+				.collect(),
+		)),
+	);
+
+	// Faces without lighting are left black, so that they aren't mistaken for luxels
+	let settings = ComputeLightmapSettings {
+		special_lighting_color: [0; 3],
+		..settings
+	};
+	let slots = data
+		.compute_lightmap_atlas(PerSlotLightmapPacker::new(settings))
+		.ok()
+		.map(|atlas| atlas.data.slots);
+
+	data.lighting = lighting;
+	slots
+}
+
+/// Returns the index of the luxel that a pixel of [`compute_luxel_index_slots`] is from, or [`None`] if the pixel is black.
+fn luxel_index(pixel: &image::Rgb<u8>) -> Option<usize> {
+	(u32::from_le_bytes([pixel[0], pixel[1], pixel[2], 0]) as usize).checked_sub(1)
+}
+
+/// Finds a lump in the BSPX directory of `bsp`, which follows the BSP's last lump, aligned to 4 bytes.
+fn read_bspx_lump<'a>(bsp: &'a [u8], name: &str) -> Option<&'a [u8]> {
+	let read_u32 = |offset: usize| Some(u32::from_le_bytes(bsp.get(offset..offset + 4)?.try_into().ok()?) as usize);
+
+	// Quake BSPs have 15 lumps after the version
+	let lumps_end = (0..15)
+		.filter_map(|lump_idx| Some(read_u32(4 + lump_idx * 8)? + read_u32(8 + lump_idx * 8)?))
+		.max()?;
+	let directory = lumps_end.next_multiple_of(4);
+	if bsp.get(directory..directory + 4)? != b"BSPX" {
+		return None;
+	}
+
+	(0..read_u32(directory + 4)?).find_map(|entry_idx| {
+		let entry = directory + 8 + entry_idx * 32;
+		let entry_name = bsp.get(entry..entry + 24)?;
+		let entry_name = &entry_name[..entry_name.iter().position(|byte| *byte == 0).unwrap_or(24)];
+		if entry_name != name.as_bytes() {
+			return None;
+		}
+		let offset = read_u32(entry + 24)?;
+		bsp.get(offset..offset + read_u32(entry + 28)?)
+	})
+}
+
+/// Decodes a color in ericw-tools' E5BGR9 format, being 9-bit red, green and blue mantissas from the lowest bits up, followed by a 5-bit shared exponent.
+fn decode_e5bgr9(value: u32) -> [f32; 3] {
+	let scale = 2_f32.powi((value >> 27) as i32 - 15 - 9);
+	[0, 9, 18].map(|shift| ((value >> shift) & 0x1ff) as f32 * scale)
+}
+
+/// Encodes a color for [`TextureFormat::Rgb9e5Ufloat`], which has the same layout as E5BGR9.
+fn encode_rgb9e5(color: [f32; 3]) -> u32 {
+	const MAX: f32 = 511. / 512. * 65536.;
+
+	let color = color.map(|channel| if channel.is_nan() { 0. } else { channel.clamp(0., MAX) });
+	let max_channel = color[0].max(color[1]).max(color[2]);
+	if max_channel <= 0. {
+		return 0;
+	}
+
+	let mut exponent = max_channel.log2().floor().max(-16.) as i32 + 1 + 15;
+	let mut scale = 2_f32.powi(exponent - 15 - 9);
+	if (max_channel / scale).round() >= 512. {
+		exponent += 1;
+		scale *= 2.;
+	}
+
+	let [r, g, b] = color.map(|channel| ((channel / scale).round() as u32).min(0x1ff));
+	r | (g << 9) | (b << 18) | ((exponent as u32) << 27)
+}
+
+#[test]
+fn e5bgr9_round_trip() {
+	for color in [[0., 0., 0.], [1., 0.5, 0.25], [4., 100., 0.001], [0.2, 0.2, 0.2]] {
+		let decoded = decode_e5bgr9(encode_rgb9e5(color));
+		// Channels share an exponent, so precision is relative to the brightest one
+		let max_channel = color[0].max(color[1]).max(color[2]);
+		for (a, b) in color.into_iter().zip(decoded) {
+			assert!((a - b).abs() <= max_channel / 256. + 1e-6, "{color:?} decoded as {decoded:?}");
+		}
+	}
+
+	// 1.0 is mantissa 256 with exponent 16
+	assert_eq!(decode_e5bgr9(256 | (16 << 27)), [1., 0., 0.]);
+}
+
+#[test]
+fn luxel_index_layout() {
+	let bsp = include_bytes!("../../../assets/maps/example.bsp");
+	let config = TrenchBroomConfig::default();
+	let mut data = BspData::parse(BspParseInput {
+		bsp,
+		lit: None,
+		settings: config.bsp_parse_settings.clone(),
+	})
+	.unwrap();
+
+	// The example's colored lighting is in the RGBLIGHTING BSPX lump
+	let luxels = read_bspx_lump(bsp, "RGBLIGHTING")
+		.unwrap()
+		.chunks_exact(3)
+		.map(|luxel| [luxel[0], luxel[1], luxel[2]])
+		.collect_vec();
+	data.lighting = Some(BspLighting::Colored(luxels.clone()));
+
+	let slots = data
+		.compute_lightmap_atlas(PerSlotLightmapPacker::new(config.compute_lightmap_settings))
+		.unwrap()
+		.data
+		.slots;
+	let luxel_index_slots = compute_luxel_index_slots(&mut data, luxels.len(), config.compute_lightmap_settings).unwrap();
+	assert!(matches!(&data.lighting, Some(BspLighting::Colored(lighting)) if *lighting == luxels));
+
+	// Every pixel from a luxel in the index atlas has that luxel's color in the real one
+	let mut luxel_pixels = 0;
+	for (slot, index_slot) in slots.iter().zip(&luxel_index_slots) {
+		assert_eq!(slot.dimensions(), index_slot.dimensions());
+		for (pixel, index) in slot.pixels().zip(index_slot.pixels()) {
+			let Some(luxel_idx) = luxel_index(index) else { continue };
+			assert_eq!([pixel[0], pixel[1], pixel[2]], luxels[luxel_idx]);
+			luxel_pixels += 1;
+		}
+	}
+	assert!(luxel_pixels > 0);
+}
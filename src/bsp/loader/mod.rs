@@ -13,24 +13,39 @@ use bevy::{
 	tasks::ConditionalSendFuture,
 };
 use bsp::*;
+use config::MapLoadSettings;
 #[cfg(feature = "client")]
 use irradiance_volume::load_irradiance_volume;
 #[cfg(feature = "client")]
-use lightmap::BspLightmap;
+use lightmap::{BspLightmap, HdrLightmap};
 use models::{compute_models, finalize_models};
 use qmap::QuakeMapEntities;
 use scene::initialize_scene;
-use textures::EmbeddedTextures;
+use textures::{EmbeddedTextures, load_goldsrc_textures};
 
 use crate::*;
 
+/// Version of `.lit` files containing HDR lighting in the E5BGR9 format, written by ericw-tools' `light -hdr`.
+/// Deluxemaps (`.lux`) only hold light directions, so they have no HDR counterpart.
+const LIT_VERSION_E5BGR9: u32 = 0x0001_0001;
+
 pub(crate) struct BspLoadCtx<'a, 'lc: 'a> {
 	pub loader: &'a BspLoader,
+	/// The [`TrenchBroomServer`] with this load's [`MapLoadSettings`] applied, use this instead of the loader's.
+	pub tb_server: &'a TrenchBroomServer,
+	pub settings: &'a MapLoadSettings,
 	pub load_context: &'a mut LoadContext<'lc>,
 	pub asset_server: &'a AssetServer,
 	pub type_registry: &'a AppTypeRegistry,
 	pub data: &'a BspData,
 	pub entities: &'a QuakeMapEntities,
+	/// HDR lighting from a `.lit` file or BSPX lump, if there is any.
+	#[cfg(feature = "client")]
+	pub hdr_lightmap: Option<&'a HdrLightmap>,
+	/// If this is a GoldSrc BSP30 file, its textures, as [`BspData::textures`] don't include their palettes.
+	pub goldsrc_textures: Option<&'a [goldsrc::GoldSrcMipTex]>,
+	/// The PVS and a handle to it if [`TrenchBroomConfig::bsp_pvs_culling`] is enabled, to set up [`PvsCulling`](pvs::PvsCulling).
+	pub pvs: Option<(&'a pvs::BspPvs, Handle<pvs::BspPvs>)>,
 }
 
 pub struct BspLoader {
@@ -51,37 +66,75 @@ impl FromWorld for BspLoader {
 impl AssetLoader for BspLoader {
 	type Asset = Bsp;
 	type Error = anyhow::Error;
-	type Settings = ();
+	type Settings = MapLoadSettings;
 
 	fn load(
 		&self,
 		reader: &mut dyn bevy::asset::io::Reader,
-		_settings: &Self::Settings,
+		settings: &Self::Settings,
 		load_context: &mut LoadContext,
 	) -> impl ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
 		Box::pin(async move {
+			let tb_server = settings.apply(&self.tb_server);
+
 			let mut bytes = Vec::new();
 			reader.read_to_end(&mut bytes).await?;
 
-			let lit = load_context.read_asset_bytes(load_context.path().with_extension("lit")).await.ok();
+			let mut lit = load_context.read_asset_bytes(load_context.path().with_extension("lit")).await.ok();
+			// qbsp only reads Quake BSPs, so GoldSrc ones are converted, with their colored lighting as a `.lit` file
+			let converted_bsp = match goldsrc::is_bsp30(&bytes) {
+				true => {
+					let (bsp, bsp30_lit) = goldsrc::bsp30_to_bsp29(&bytes).map_err(|err| anyhow!("Converting BSP30: {err}"))?;
+					lit = Some(bsp30_lit);
+					Some(bsp)
+				}
+				false => None,
+			};
+			// qbsp doesn't understand HDR `.lit` files, they're read when computing lightmaps instead
+			#[cfg_attr(not(feature = "client"), allow(unused_variables))]
+			let (lit, hdr_lit) = match lit {
+				Some(lit) if lit.starts_with(b"QLIT") && lit.get(4..8) == Some(&LIT_VERSION_E5BGR9.to_le_bytes()[..]) => (None, Some(lit)),
+				lit => (lit, None),
+			};
 
-			let data = BspData::parse(BspParseInput {
-				bsp: &bytes,
+			#[cfg_attr(not(feature = "client"), allow(unused_mut))]
+			let mut data = BspData::parse(BspParseInput {
+				bsp: converted_bsp.as_deref().unwrap_or(&bytes),
 				lit: lit.as_deref(),
-				settings: self.tb_server.config.bsp_parse_settings.clone(),
+				settings: tb_server.config.bsp_parse_settings.clone(),
 			})?;
 
+			#[cfg(feature = "client")]
+			let hdr_lightmap = HdrLightmap::new(&mut data, &bytes, hdr_lit.as_deref().map(|lit| &lit[8..]), &tb_server.config);
+
 			let quake_util_map =
 				quake_util::qmap::parse(&mut io::Cursor::new(data.entities.as_bytes())).map_err(|err| anyhow!("Parsing entities: {err}"))?;
-			let entities = QuakeMapEntities::from_quake_util(quake_util_map, &self.tb_server.config);
+			let entities = QuakeMapEntities::from_quake_util(quake_util_map, &tb_server.config);
+
+			let goldsrc_textures = match converted_bsp.is_some() {
+				true => Some(load_goldsrc_textures(&bytes, load_context, &entities, &tb_server.config).await?),
+				false => None,
+			};
+
+			let pvs = match tb_server.config.bsp_pvs_culling {
+				true => pvs::BspPvs::new(&data, &tb_server.config),
+				false => None,
+			};
+			let pvs_handle = pvs.clone().map(|pvs| load_context.add_labeled_asset("Pvs".s(), pvs));
 
 			let mut ctx = BspLoadCtx {
 				loader: self,
+				tb_server: &tb_server,
+				settings,
 				load_context,
 				asset_server: &self.asset_server,
 				type_registry: &self.type_registry,
 				data: &data,
 				entities: &entities,
+				#[cfg(feature = "client")]
+				hdr_lightmap: hdr_lightmap.as_ref(),
+				goldsrc_textures: goldsrc_textures.as_deref(),
+				pvs: pvs.as_ref().zip(pvs_handle.clone()),
 			};
 
 			let embedded_textures = EmbeddedTextures::setup(&mut ctx).await?;
@@ -110,6 +163,7 @@ impl AssetLoader for BspLoader {
 				#[cfg(feature = "client")]
 				irradiance_volume,
 				models: bsp_models,
+				pvs: pvs_handle,
 
 				data,
 				entities,
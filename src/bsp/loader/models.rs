@@ -1,7 +1,7 @@
 use super::*;
 use crate::*;
-use bevy_mesh::{Indices, PrimitiveTopology};
-use bsp::*;
+use bevy_mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+use bsp::{hull::BspHull, *};
 
 #[derive(Default)]
 pub struct InternalModel {
@@ -16,6 +16,8 @@ pub struct InternalModelMesh {
 	pub mesh: Mesh,
 	/// Entity to apply [`Mesh3d`] to. Should probably only be one of these.
 	pub entity: Option<Entity>,
+	/// The leaves this mesh is in if it has been split up for [`PvsCulling`](pvs::PvsCulling).
+	pub pvs_leaves: Option<Vec<usize>>,
 }
 
 #[inline]
@@ -33,7 +35,7 @@ pub async fn compute_models<'a, 'lc: 'a>(
 	lightmap: &Option<Lightmap>,
 	embedded_textures: &EmbeddedTextures<'a>,
 ) -> Vec<InternalModel> {
-	let config = &ctx.loader.tb_server.config;
+	let config = &ctx.tb_server.config;
 	#[cfg(feature = "client")]
 	let lightmap_uvs = lightmap.as_ref().map(|lm| &lm.uv_map);
 	#[cfg(not(feature = "client"))]
@@ -93,6 +95,7 @@ pub async fn compute_models<'a, 'lc: 'a>(
 			model.meshes.push(InternalModelMesh {
 				texture: MapGeometryTexture {
 					material,
+					attributes: default(),
 					#[cfg(feature = "client")]
 					lightmap: lightmap.as_ref().map(|lm| lm.animated_lighting.clone()),
 					name: exported_mesh.texture,
@@ -100,6 +103,7 @@ pub async fn compute_models<'a, 'lc: 'a>(
 				},
 				mesh,
 				entity: None,
+				pvs_leaves: None,
 			});
 		}
 
@@ -110,86 +114,206 @@ pub async fn compute_models<'a, 'lc: 'a>(
 }
 
 pub fn finalize_models(ctx: &mut BspLoadCtx, models: Vec<InternalModel>, world: &mut World) -> anyhow::Result<Vec<BspModel>> {
-	let config = &ctx.loader.tb_server.config;
+	let config = &ctx.tb_server.config;
 
 	let brush_list = match ctx.data.bspx.parse_brush_list(&ctx.data.parse_ctx) {
 		Some(result) => result?,
-		None => Vec::new(),
+		None => {
+			if !config.bsp_leaf_brushes_fallback {
+				warn!(
+					"BSP {:?} has no BRUSHLIST lump and `bsp_leaf_brushes_fallback` is disabled, so its models won't have brushes for convex collision, traces or collision layers",
+					ctx.load_context.path()
+				);
+			}
+			Vec::new()
+		}
 	};
 
 	Ok(models
 		.into_iter()
 		.enumerate()
-		.map(|(model_idx, model)| BspModel {
-			meshes: model
-				.meshes
-				.into_iter()
-				.enumerate()
-				.map(|(mesh_idx, model_mesh)| {
-					let mesh_handle = ctx
-						.load_context
-						.add_labeled_asset(format!("Model{model_idx}Mesh{mesh_idx}"), model_mesh.mesh);
-
-					if let Some(mesh_entity) = model_mesh.entity {
-						world.entity_mut(mesh_entity).insert(Mesh3d(mesh_handle.clone()));
-					}
-
-					(model_mesh.texture.name, mesh_handle)
-				})
-				.collect(),
-
-			brushes: brush_list
-				.iter()
-				.find(|model_brushes| model_brushes.model_idx as usize == model_idx)
-				.map(|model_brushes| {
-					let brushes_asset = ctx.load_context.add_labeled_asset(
-						format!("Model{model_idx}Brushes"),
-						BspBrushesAsset {
-							brushes: model_brushes
-								.brushes
-								.iter()
-								.map(|model_brush| {
-									let min = config.to_bevy_space(model_brush.bound.min).as_dvec3();
-									let max = config.to_bevy_space(model_brush.bound.max).as_dvec3();
-									// Something about the conversion makes the asserts below fail, this ensures they don't.
-									let (min, max) = ((-min).min(-max), (-min).max(-max));
-									debug_assert!(min.x < max.x);
-									debug_assert!(min.y < max.y);
-									debug_assert!(min.z < max.z);
-
-									let mut brush = BspBrush::default();
-									brush.planes.reserve(4 + model_brush.planes.len());
-
-									#[rustfmt::skip]
-									brush.planes.extend([
-										BrushPlane { normal: DVec3::Y,     distance:  min.y },
-										BrushPlane { normal: DVec3::NEG_Y, distance: -max.y },
-										BrushPlane { normal: DVec3::X,     distance:  min.x },
-										BrushPlane { normal: DVec3::NEG_X, distance: -max.x },
-										BrushPlane { normal: DVec3::Z,     distance:  min.z },
-										BrushPlane { normal: DVec3::NEG_Z, distance: -max.z },
-									]);
-
-									brush.planes.extend(model_brush.planes.iter().map(|plane| {
-										// We need to invert it because brush math expects normals to point inwards
-										BrushPlane {
-											normal: plane.normal.as_dvec3().trenchbroom_to_bevy(),
-											distance: -plane.dist as f64 / config.scale as f64,
-										}
-									}));
-
-									brush
-								})
-								.collect(),
-						},
-					);
+		.map(|(model_idx, model)| {
+			let brushes = match brush_list.iter().find(|model_brushes| model_brushes.model_idx as usize == model_idx) {
+				Some(model_brushes) => Some(
+					model_brushes
+						.brushes
+						.iter()
+						.map(|model_brush| {
+							let min = config.to_bevy_space(model_brush.bound.min).as_dvec3();
+							let max = config.to_bevy_space(model_brush.bound.max).as_dvec3();
+							// Something about the conversion makes the asserts below fail, this ensures they don't.
+							let (min, max) = ((-min).min(-max), (-min).max(-max));
+							debug_assert!(min.x < max.x);
+							debug_assert!(min.y < max.y);
+							debug_assert!(min.z < max.z);
+
+							let mut brush = BspBrush {
+								contents: model_brush.contents as i32,
+								..default()
+							};
+							brush.planes.reserve(4 + model_brush.planes.len());
 
-					if let Some(entity) = model.entity {
-						world.entity_mut(entity).insert(Brushes::Bsp(brushes_asset.clone()));
-					}
+							#[rustfmt::skip]
+							brush.planes.extend([
+								BrushPlane { normal: DVec3::Y,     distance:  min.y },
+								BrushPlane { normal: DVec3::NEG_Y, distance: -max.y },
+								BrushPlane { normal: DVec3::X,     distance:  min.x },
+								BrushPlane { normal: DVec3::NEG_X, distance: -max.x },
+								BrushPlane { normal: DVec3::Z,     distance:  min.z },
+								BrushPlane { normal: DVec3::NEG_Z, distance: -max.z },
+							]);
 
-					brushes_asset
+							brush.planes.extend(model_brush.planes.iter().map(|plane| {
+								// We need to invert it because brush math expects normals to point inwards
+								BrushPlane {
+									normal: plane.normal.as_dvec3().trenchbroom_to_bevy(),
+									distance: -plane.dist as f64 / config.scale as f64,
+								}
+							}));
+
+							brush
+						})
+						.collect(),
+				),
+				// Without BRUSHLIST, we can still get convex pieces from the solid leaves of the model's BSP tree
+				None if config.bsp_leaf_brushes_fallback => BspHull::new(ctx.data, model_idx, 0, config).and_then(|hull| {
+					let model_data = &ctx.data.models[model_idx];
+					let (a, b) = (
+						config.to_bevy_space(model_data.bound.min).as_dvec3(),
+						config.to_bevy_space(model_data.bound.max).as_dvec3(),
+					);
+					let brushes = hull.solid_brushes(a.min(b), a.max(b));
+					(!brushes.is_empty()).then_some(brushes)
 				}),
+				None => None,
+			};
+
+			let brushes = brushes.map(|mut brushes| {
+				if !config.collision_layer_rules.is_empty() {
+					find_brush_face_textures(&mut brushes, &model.meshes);
+				}
+
+				let brushes_asset = ctx
+					.load_context
+					.add_labeled_asset(format!("Model{model_idx}Brushes"), BspBrushesAsset { brushes });
+
+				if let Some(entity) = model.entity {
+					world.entity_mut(entity).insert(Brushes::Bsp(brushes_asset.clone()));
+				}
+
+				brushes_asset
+			});
+
+			BspModel {
+				meshes: model
+					.meshes
+					.into_iter()
+					.enumerate()
+					.map(|(mesh_idx, model_mesh)| {
+						let mesh_handle = ctx
+							.load_context
+							.add_labeled_asset(format!("Model{model_idx}Mesh{mesh_idx}"), model_mesh.mesh);
+
+						if let Some(mesh_entity) = model_mesh.entity {
+							world.entity_mut(mesh_entity).insert(Mesh3d(mesh_handle.clone()));
+						}
+
+						(model_mesh.texture.name, mesh_handle)
+					})
+					.collect(),
+				brushes,
+			}
 		})
 		.collect())
 }
+
+/// Fills in the [`textures`](BspBrush::textures) of `brushes` with those of the triangles in `meshes` lying on their surfaces, as `BRUSHLIST` doesn't store any.
+fn find_brush_face_textures(brushes: &mut [BspBrush], meshes: &[InternalModelMesh]) {
+	const EPSILON: f64 = 1e-3;
+
+	// Sorted along the X axis, so that each brush only has to check the triangles within its bounds
+	let mut triangle_centers = meshes
+		.iter()
+		.enumerate()
+		.flat_map(|(mesh_idx, model_mesh)| {
+			let positions = model_mesh
+				.mesh
+				.attribute(Mesh::ATTRIBUTE_POSITION)
+				.and_then(VertexAttributeValues::as_float3)
+				.unwrap_or_default();
+			let indices = model_mesh.mesh.indices().map(|indices| indices.iter().collect_vec()).unwrap_or_default();
+
+			indices
+				.chunks_exact(3)
+				.filter_map(|triangle| {
+					let [a, b, c] =
+						[triangle[0], triangle[1], triangle[2]].map(|idx| positions.get(idx).map(|position| Vec3::from_array(*position).as_dvec3()));
+					Some(((a? + b? + c?) / 3., mesh_idx))
+				})
+				.collect_vec()
+		})
+		.collect_vec();
+	triangle_centers.sort_unstable_by(|(a, _), (b, _)| a.x.total_cmp(&b.x));
+
+	for brush in brushes {
+		let Some((min, max)) = brush
+			.calculate_vertices()
+			.map(|(vertex, _)| (vertex, vertex))
+			.reduce(|(min, max), (vertex, _)| (min.min(vertex), max.max(vertex)))
+		else {
+			continue;
+		};
+
+		let start = triangle_centers.partition_point(|(center, _)| center.x < min.x - EPSILON);
+		let end = triangle_centers.partition_point(|(center, _)| center.x <= max.x + EPSILON);
+
+		for (center, mesh_idx) in &triangle_centers[start..end] {
+			let on_surface = brush.planes.iter().all(|plane| plane.point_side(*center) < EPSILON)
+				&& brush.planes.iter().any(|plane| plane.point_side(*center).abs() < EPSILON);
+			let texture = &meshes[*mesh_idx].texture.name;
+
+			if on_surface && !brush.textures.contains(texture) {
+				brush.textures.push(texture.clone());
+			}
+		}
+	}
+}
+
+#[test]
+fn brush_face_textures() {
+	let mesh = |positions: [[f32; 3]; 3]| {
+		Mesh::new(PrimitiveTopology::TriangleList, default())
+			.with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions.to_vec())
+			.with_inserted_indices(Indices::U32(vec![0, 1, 2]))
+	};
+	let model_mesh = |texture: &str, positions| InternalModelMesh {
+		texture: MapGeometryTexture {
+			name: texture.s(),
+			material: default(),
+			attributes: default(),
+			#[cfg(feature = "client")]
+			lightmap: None,
+			flags: BspTexFlags::Normal,
+		},
+		mesh: mesh(positions),
+		entity: None,
+		pvs_leaves: None,
+	};
+
+	let meshes = [
+		// On the +X face of the brush
+		model_mesh("brick", [[1., -0.5, -0.5], [1., 0.5, -0.5], [1., 0., 0.5]]),
+		// Floating next to it
+		model_mesh("lava", [[3., -0.5, -0.5], [3., 0.5, -0.5], [3., 0., 0.5]]),
+	];
+
+	let mut brushes = [BspBrush {
+		planes: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushPlane { normal, distance: -1. })
+			.into(),
+		contents: -2,
+		textures: Vec::new(),
+	}];
+	find_brush_face_textures(&mut brushes, &meshes);
+	assert_eq!(brushes[0].textures, ["brick"]);
+}
@@ -4,19 +4,27 @@ use crate::{
 	geometry::MapGeometry,
 	*,
 };
-use bsp::*;
-use models::InternalModel;
+use bsp::{
+	hull::{BspHull, BspHullChild},
+	pvs::PvsCulling,
+	*,
+};
+use models::{InternalModel, InternalModelMesh};
 
 pub fn initialize_scene(ctx: &mut BspLoadCtx, models: &mut [InternalModel]) -> anyhow::Result<World> {
-	let config = &ctx.loader.tb_server.config;
+	let config = &ctx.tb_server.config;
 	let type_registry = ctx.type_registry.read();
 	let class_map = generate_class_map(&type_registry);
 
 	let mut world = World::new();
+	let world_faces = ctx.pvs.as_ref().map(|_| WorldFaces::new(ctx.data, config));
 
 	// Spawn entities into scene
 	for (map_entity_idx, map_entity) in ctx.entities.iter().enumerate() {
 		let Some(classname) = map_entity.properties.get("classname") else { continue };
+		if !ctx.settings.classname_filter.allows(classname) {
+			continue;
+		}
 		let Some(class) = class_map.get(classname.as_str()).copied() else {
 			if !config.suppress_invalid_entity_definitions {
 				error!("No class found for classname `{classname}` on entity {map_entity_idx}");
@@ -55,14 +63,66 @@ pub fn initialize_scene(ctx: &mut BspLoadCtx, models: &mut [InternalModel]) -> a
 				}
 				model.entity = Some(entity_id);
 
+				if let Some(chunk_size) = geometry_provider.chunk_size {
+					model.meshes = mem::take(&mut model.meshes)
+						.into_iter()
+						.flat_map(|model_mesh| {
+							let texture = model_mesh.texture;
+							geometry::split_mesh_into_cells(model_mesh.mesh, chunk_size)
+								.into_iter()
+								.map(move |mesh| InternalModelMesh {
+									texture: texture.clone(),
+									mesh,
+									entity: None,
+									pvs_leaves: None,
+								})
+						})
+						.collect();
+				}
+
+				// Only the world model's faces are in the world's leaves, other models are culled as a whole by the leaves they're in
+				match (model_idx, &ctx.pvs, &world_faces) {
+					(0, Some((pvs, _)), Some(world_faces)) => {
+						model.meshes = mem::take(&mut model.meshes)
+							.into_iter()
+							.flat_map(|model_mesh| split_mesh_by_leaves(model_mesh, pvs, world_faces))
+							.collect();
+					}
+					(_, Some((pvs, pvs_handle)), _) => {
+						if let Some(model_data) = ctx.data.models.get(model_idx) {
+							// Models with an origin are relative to it
+							let origin = world
+								.entity(entity_id)
+								.get::<Transform>()
+								.map(|transform| transform.translation)
+								.unwrap_or_default();
+							let (a, b) = (config.to_bevy_space(model_data.bound.min), config.to_bevy_space(model_data.bound.max));
+							let leaves = pvs.hull.box_leaves((a.min(b) + origin).as_dvec3(), (a.max(b) + origin).as_dvec3());
+							// Brush entities can move, so their leaves are found again from their bounds when they do
+							world
+								.entity_mut(entity_id)
+								.insert(PvsCulling::new(pvs_handle.clone(), Some(leaves)).with_bounds(a.min(b), a.max(b)));
+						}
+					}
+					_ => {}
+				}
+
 				let mut meshes = Vec::with_capacity(model.meshes.len());
 
 				for model_mesh in &mut model.meshes {
-					if config.auto_remove_textures.contains(&model_mesh.texture.name) {
+					if !ctx.settings.generate_meshes || !config.is_texture_rendered(&model_mesh.texture.name) {
+						continue;
+					}
+					// GoldSrc skies are skyboxes, so their faces would only show the placeholder texture
+					if ctx.goldsrc_textures.is_some() && goldsrc::is_sky_texture(&model_mesh.texture.name) {
 						continue;
 					}
 
-					let mesh_entity = world.spawn(Name::new(model_mesh.texture.name.clone())).id();
+					let mut mesh_entity = world.spawn(Name::new(model_mesh.texture.name.clone()));
+					if let (Some(leaves), Some((_, pvs_handle))) = (&model_mesh.pvs_leaves, &ctx.pvs) {
+						mesh_entity.insert(PvsCulling::new(pvs_handle.clone(), Some(leaves.clone())));
+					}
+					let mesh_entity = mesh_entity.id();
 
 					meshes.push(GeometryProviderMeshView {
 						entity: mesh_entity,
@@ -76,7 +136,7 @@ pub fn initialize_scene(ctx: &mut BspLoadCtx, models: &mut [InternalModel]) -> a
 				let mut view = GeometryProviderView {
 					world: &mut world,
 					entity: entity_id,
-					tb_server: &ctx.loader.tb_server,
+					tb_server: ctx.tb_server,
 					map_entity,
 					map_entity_idx,
 					class,
@@ -108,7 +168,220 @@ pub fn initialize_scene(ctx: &mut BspLoadCtx, models: &mut [InternalModel]) -> a
 			load_context: ctx.load_context,
 		})
 		.map_err(|err| anyhow!("spawning entity {map_entity_idx} ({classname}) with global spawner: {err}"))?;
+
+		// Point entities have no meshes of their own to split, so they're culled by their position instead
+		if let (QuakeClassType::Point, Some((_, pvs_handle))) = (class.info.ty, &ctx.pvs) {
+			let mut entity = world.entity_mut(entity_id);
+			if entity.contains::<SceneRoot>() || entity.contains::<Mesh3d>() {
+				entity.insert(PvsCulling::new(pvs_handle.clone(), None));
+			}
+		}
 	}
 
 	Ok(world)
 }
+
+/// The polygons of a BSP's faces in Bevy space, and which leaves have them in their mark surfaces, being the leaves they can be seen from.
+struct WorldFaces {
+	/// The vertices of each face, or [`None`] if it's malformed.
+	polygons: Vec<Option<Vec<DVec3>>>,
+	/// The faces in the mark surfaces of each leaf.
+	leaf_faces: Vec<Vec<usize>>,
+	/// The leaves with each face in their mark surfaces, sorted.
+	face_leaves: Vec<Vec<usize>>,
+}
+impl WorldFaces {
+	fn new(data: &BspData, config: &TrenchBroomConfig) -> Self {
+		let polygons = data
+			.faces
+			.iter()
+			.map(|face| {
+				(face.first_edge as usize..)
+					.take(face.num_edges as usize)
+					.map(|surface_edge_idx| {
+						let surface_edge = *data.surface_edges.get(surface_edge_idx)?;
+						let edge = data.edges.get(surface_edge.unsigned_abs() as usize)?;
+						// Negative surface edges go backwards
+						let vertex_idx = if surface_edge >= 0 { edge.a } else { edge.b };
+						let vertex = data.vertices.get(vertex_idx as usize)?;
+						Some(config.to_bevy_space(Vec3::from_array(vertex.to_array())).as_dvec3())
+					})
+					.collect::<Option<Vec<_>>>()
+					.filter(|polygon| polygon.len() >= 3)
+			})
+			.collect_vec();
+
+		let leaf_faces = data
+			.leaves
+			.iter()
+			.map(|leaf| {
+				(leaf.first_mark_surface as usize..)
+					.take(leaf.num_mark_surfaces as usize)
+					.filter_map(|mark_surface_idx| data.mark_surfaces.get(mark_surface_idx))
+					.map(|face_idx| *face_idx as usize)
+					.filter(|face_idx| *face_idx < polygons.len())
+					.collect_vec()
+			})
+			.collect_vec();
+
+		let mut face_leaves = vec![Vec::new(); polygons.len()];
+		for (leaf_idx, faces) in leaf_faces.iter().enumerate() {
+			for face_idx in faces {
+				face_leaves[*face_idx].push(leaf_idx);
+			}
+		}
+
+		Self {
+			polygons,
+			leaf_faces,
+			face_leaves,
+		}
+	}
+
+	/// Returns the leaves that the face `triangle` is part of can be seen from.
+	///
+	/// The face is found among the mark surfaces of the leaves right next to the triangle, so that this doesn't have to search every face.
+	/// If it can't be found, this is the leaves next to the triangle.
+	fn triangle_leaves(&self, triangle: [DVec3; 3], hull: &BspHull) -> Vec<usize> {
+		const EPSILON: f64 = 1e-3;
+
+		let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]).normalize_or_zero();
+		let center = (triangle[0] + triangle[1] + triangle[2]) / 3.;
+
+		let mut neighbor_leaves = Vec::new();
+		// Faces are usually only visible from their front, but we don't rely on the winding of the mesh
+		for side in [1., -1.] {
+			let BspHullChild::Leaf {
+				leaf_idx: Some(leaf_idx), ..
+			} = hull.point_leaf(center + normal * side * EPSILON)
+			else {
+				continue;
+			};
+
+			let face = self.leaf_faces.get(leaf_idx).into_iter().flatten().copied().find(|face_idx| {
+				self.polygons[*face_idx]
+					.as_ref()
+					.is_some_and(|polygon| polygon_contains_point(polygon, normal, center, EPSILON))
+			});
+			if let Some(face_idx) = face {
+				return self.face_leaves[face_idx].clone();
+			}
+
+			if leaf_idx != 0 {
+				neighbor_leaves.push(leaf_idx);
+			}
+		}
+
+		neighbor_leaves
+	}
+}
+
+/// Returns `true` if `point` is on the plane of the convex `polygon` with `normal` (in either direction) and inside its edges, within `epsilon`.
+fn polygon_contains_point(polygon: &[DVec3], normal: DVec3, point: DVec3, epsilon: f64) -> bool {
+	if (point - polygon[0]).dot(normal).abs() > epsilon {
+		return false;
+	}
+
+	let sides = polygon
+		.iter()
+		.zip(polygon.iter().cycle().skip(1))
+		.map(|(a, b)| (*b - *a).cross(point - *a).dot(normal))
+		.collect_vec();
+
+	sides.iter().all(|side| *side >= -epsilon) || sides.iter().all(|side| *side <= epsilon)
+}
+
+/// Splits `model_mesh` up by the leaves that can see the faces its triangles are from, for [`PvsCulling`].
+fn split_mesh_by_leaves(model_mesh: InternalModelMesh, pvs: &pvs::BspPvs, world_faces: &WorldFaces) -> Vec<InternalModelMesh> {
+	let Some(leaf_meshes) = geometry::split_mesh_by(&model_mesh.mesh, |triangle| {
+		world_faces.triangle_leaves(triangle.map(Vec3::as_dvec3), &pvs.hull)
+	}) else {
+		return vec![model_mesh];
+	};
+
+	leaf_meshes
+		.into_iter()
+		.map(|(leaves, mesh)| InternalModelMesh {
+			texture: model_mesh.texture.clone(),
+			mesh,
+			entity: None,
+			pvs_leaves: Some(leaves),
+		})
+		.collect()
+}
+
+#[test]
+fn mesh_leaf_splitting() {
+	use bevy_mesh::{Indices, PrimitiveTopology};
+
+	let hull = BspHull {
+		nodes: vec![bsp::hull::BspHullNode {
+			plane: BrushPlane {
+				normal: DVec3::X,
+				distance: 0.,
+			},
+			children: [1, 2].map(|leaf_idx| BspHullChild::Leaf {
+				contents: qbsp::data::bsp::BspLeafContents::Empty,
+				leaf_idx: Some(leaf_idx),
+			}),
+		}],
+		root: BspHullChild::Node(0),
+	};
+	let pvs = pvs::BspPvs {
+		hull,
+		vis_data: Vec::new(),
+		vis_offsets: Vec::new(),
+		row_len: 0,
+	};
+
+	// Two floors, one on each side of x = 0, where the one on the front side can also be seen from the back leaf
+	let floor = |min_x: f64| {
+		[
+			dvec3(min_x, 0., 0.),
+			dvec3(min_x + 0.5, 0., 0.),
+			dvec3(min_x + 0.5, 0., 1.),
+			dvec3(min_x, 0., 1.),
+		]
+		.to_vec()
+	};
+	let world_faces = WorldFaces {
+		polygons: vec![Some(floor(0.5)), Some(floor(-1.))],
+		leaf_faces: vec![vec![], vec![0], vec![0, 1]],
+		face_leaves: vec![vec![1, 2], vec![2]],
+	};
+
+	let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, default());
+	mesh.insert_attribute(
+		Mesh::ATTRIBUTE_POSITION,
+		vec![
+			[0.6, 0., 0.1],
+			[0.9, 0., 0.1],
+			[0.9, 0., 0.9],
+			[-0.9, 0., 0.1],
+			[-0.6, 0., 0.1],
+			[-0.6, 0., 0.9],
+		],
+	);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 1., 0.]; 6]);
+	mesh.insert_indices(Indices::U32((0..6).collect()));
+
+	let model_mesh = InternalModelMesh {
+		texture: MapGeometryTexture {
+			name: "floor".s(),
+			material: default(),
+			attributes: default(),
+			#[cfg(feature = "client")]
+			lightmap: None,
+			flags: BspTexFlags::Normal,
+		},
+		mesh,
+		entity: None,
+		pvs_leaves: None,
+	};
+
+	let leaves = split_mesh_by_leaves(model_mesh, &pvs, &world_faces)
+		.into_iter()
+		.map(|model_mesh| model_mesh.pvs_leaves.unwrap())
+		.collect_vec();
+	assert_eq!(leaves, [vec![1, 2], vec![2]]);
+}
@@ -1,5 +1,10 @@
-use bsp::*;
+use bevy::asset::LoadContext;
+use bsp::{
+	goldsrc::{GoldSrcMipTex, Wad3, parse_bsp30_textures, wad_file_names},
+	*,
+};
 use loader::BspLoadCtx;
+use qmap::QuakeMapEntities;
 use wgpu_types::{Extent3d, TextureDimension, TextureFormat};
 
 use crate::*;
@@ -11,33 +16,53 @@ pub struct EmbeddedTextures<'d> {
 
 impl<'d> EmbeddedTextures<'d> {
 	pub async fn setup<'a: 'd, 'lc>(ctx: &mut BspLoadCtx<'a, 'lc>) -> anyhow::Result<Self> {
-		let config = &ctx.loader.tb_server.config;
+		let config = &ctx.tb_server.config;
 
 		let palette = match ctx.load_context.read_asset_bytes(config.texture_pallette.as_path()).await.ok() {
 			Some(bytes) => Palette::parse(&bytes).map_err(|err| anyhow!("Parsing palette file {:?}: {err}", config.texture_pallette))?,
 			None => QUAKE_PALETTE.clone(),
 		};
 
-		let images: HashMap<&str, (Image, Handle<Image>)> = ctx
-			.data
-			.textures
-			.iter()
-			.flatten()
-			.filter(|texture| texture.data.is_some())
-			.map(|texture| {
-				let Some(data) = &texture.data else { unreachable!() };
-				let name = texture.header.name.as_str();
-
-				let is_cutout_texture = name.starts_with('{');
-
-				let mut image = Image::new(
-					Extent3d {
-						width: texture.header.width,
-						height: texture.header.height,
-						..default()
-					},
-					TextureDimension::D2,
-					data.iter()
+		let mut make_image = |name: &str, width: u32, height: u32, data: Vec<u8>| {
+			let mut image = Image::new(
+				Extent3d { width, height, ..default() },
+				TextureDimension::D2,
+				data,
+				TextureFormat::Rgba8UnormSrgb,
+				config.bsp_textures_asset_usages,
+			);
+			image.sampler = config.texture_sampler.clone();
+
+			let image_handle = ctx.load_context.get_label_handle(format!("{TEXTURE_PREFIX}{name}"));
+
+			(image, image_handle)
+		};
+
+		let images: HashMap<&str, (Image, Handle<Image>)> = match ctx.goldsrc_textures {
+			// GoldSrc textures carry their own palettes
+			Some(goldsrc_textures) => goldsrc_textures
+				.iter()
+				.filter(|texture| !texture.is_external())
+				.map(|texture| {
+					let is_cutout_texture = config.embedded_texture_cutouts && texture.name.starts_with('{');
+					let image = make_image(&texture.name, texture.width, texture.height, texture.to_rgba(is_cutout_texture));
+					(texture.name.as_str(), image)
+				})
+				.collect(),
+			None => ctx
+				.data
+				.textures
+				.iter()
+				.flatten()
+				.filter(|texture| texture.data.is_some())
+				.map(|texture| {
+					let Some(data) = &texture.data else { unreachable!() };
+					let name = texture.header.name.as_str();
+
+					let is_cutout_texture = name.starts_with('{');
+
+					let data = data
+						.iter()
 						.copied()
 						.flat_map(|pixel| {
 							if config.embedded_texture_cutouts && is_cutout_texture && pixel == 255 {
@@ -47,17 +72,12 @@ impl<'d> EmbeddedTextures<'d> {
 								[r, g, b, 255]
 							}
 						})
-						.collect(),
-					TextureFormat::Rgba8UnormSrgb,
-					config.bsp_textures_asset_usages,
-				);
-				image.sampler = config.texture_sampler.clone();
+						.collect();
 
-				let image_handle = ctx.load_context.get_label_handle(format!("{TEXTURE_PREFIX}{name}"));
-
-				(texture.header.name.as_str(), (image, image_handle))
-			})
-			.collect();
+					(name, make_image(name, texture.header.width, texture.header.height, data))
+				})
+				.collect(),
+		};
 
 		let mut textures: HashMap<String, BspEmbeddedTexture> = HashMap::with_capacity_and_hasher(images.len(), default());
 
@@ -79,6 +99,7 @@ impl<'d> EmbeddedTextures<'d> {
 
 				image_handle,
 				image,
+				goldsrc: ctx.goldsrc_textures.is_some(),
 			})
 			.await;
 
@@ -103,3 +124,47 @@ impl<'d> EmbeddedTextures<'d> {
 		self.textures
 	}
 }
+
+/// Parses the textures of a GoldSrc BSP30 file, filling in the ones stored externally from the WADs in the `wad` worldspawn key.
+///
+/// WADs are looked for by file name in [`TrenchBroomConfig::material_root`], as the key usually contains absolute paths from the mapper's machine.
+/// WADs that can't be read or parsed are skipped with a warning, and textures that can't be found in any WAD are left external,
+/// to be loaded as loose textures instead.
+pub async fn load_goldsrc_textures(
+	bsp: &[u8],
+	load_context: &mut LoadContext<'_>,
+	entities: &QuakeMapEntities,
+	config: &TrenchBroomConfig,
+) -> anyhow::Result<Vec<GoldSrcMipTex>> {
+	let mut textures = parse_bsp30_textures(bsp).map_err(|err| anyhow!("Parsing BSP30 textures: {err}"))?;
+	if !textures.iter().any(GoldSrcMipTex::is_external) {
+		return Ok(textures);
+	}
+
+	let wad_key = entities
+		.worldspawn()
+		.and_then(|worldspawn| worldspawn.properties.get("wad"))
+		.cloned()
+		.unwrap_or_default();
+
+	let mut wads = Vec::new();
+	for file_name in wad_file_names(&wad_key) {
+		let path = config.material_root.join(file_name);
+		match load_context.read_asset_bytes(path.as_path()).await {
+			Ok(bytes) => match Wad3::parse(&bytes) {
+				Ok(wad) => wads.push(wad),
+				Err(err) => warn!("Couldn't parse WAD {path:?} referenced by worldspawn: {err}"),
+			},
+			Err(err) => warn!("Couldn't read WAD {path:?} referenced by worldspawn: {err}"),
+		}
+	}
+
+	for texture in textures.iter_mut().filter(|texture| texture.is_external()) {
+		if let Some(wad_texture) = wads.iter().find_map(|wad| wad.get(&texture.name)) {
+			texture.pixels = wad_texture.pixels.clone();
+			texture.palette = wad_texture.palette.clone();
+		}
+	}
+
+	Ok(textures)
+}
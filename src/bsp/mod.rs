@@ -1,6 +1,9 @@
+pub mod goldsrc;
+pub mod hull;
 #[cfg(feature = "client")]
 pub mod lighting;
 pub mod loader;
+pub mod pvs;
 
 use brush::{BrushPlane, ConvexHull};
 use class::{ErasedQuakeClass, QuakeClassType};
@@ -9,6 +12,7 @@ use geometry::{Brushes, GeometryProviderMeshView, MapGeometryTexture};
 #[cfg(feature = "client")]
 use lighting::AnimatedLighting;
 use loader::BspLoader;
+use pvs::BspPvs;
 use qmap::{QuakeMapEntities, QuakeMapEntity};
 
 use crate::{util::BevyTrenchbroomCoordinateConversions, *};
@@ -27,6 +31,10 @@ impl Plugin for BspPlugin {
 		if !app.world().resource::<TrenchBroomServer>().config.no_bsp_lighting {
 			app.add_plugins(lighting::BspLightingPlugin);
 		}
+
+		if app.world().resource::<TrenchBroomServer>().config.bsp_pvs_culling {
+			app.add_plugins(pvs::BspPvsPlugin);
+		}
 	}
 }
 
@@ -45,6 +53,8 @@ pub struct Bsp {
 	pub irradiance_volume: Option<Handle<AnimatedLighting>>,
 	/// Models for brush entities (world geometry).
 	pub models: Vec<BspModel>,
+	/// The potentially visible set of the BSP's leaves, if [`TrenchBroomConfig::bsp_pvs_culling`] is enabled and the BSP has been vised.
+	pub pvs: Option<Handle<BspPvs>>,
 	/// The source data this BSP's assets was created from.
 	pub data: BspData,
 	/// The entities parsed from the map that was used to construct the scene.
@@ -58,6 +68,8 @@ pub struct BspModel {
 	pub meshes: Vec<(String, Handle<Mesh>)>,
 
 	/// If the BSP contains the `BRUSHLIST` BSPX lump, this will be [`Some`] containing a handle to the brushes for this model.
+	///
+	/// Otherwise, if [`TrenchBroomConfig::bsp_leaf_brushes_fallback`] is enabled (the default), this contains brushes made from the solid leaves of the model's BSP tree, see [`BspHull::solid_brushes`](hull::BspHull::solid_brushes).
 	pub brushes: Option<Handle<BspBrushesAsset>>,
 }
 
@@ -67,10 +79,16 @@ pub struct BspBrushesAsset {
 	pub brushes: Vec<BspBrush>,
 }
 
-/// Like a [`Brush`](crate::brush::Brush), but only contains the hull geometry, no texture information.
+/// Like a [`Brush`](crate::brush::Brush), but only contains the hull geometry and contents, no texture information.
 #[derive(Reflect, Debug, Clone, Default)]
 pub struct BspBrush {
 	pub planes: Vec<BrushPlane>,
+	/// The Quake `CONTENTS_*` value of the brush, such as `-2` for solid, or `-8` for clip brushes written by `ericw-tools`.
+	pub contents: i32,
+	/// The textures of the model's faces on the surface of this brush, used for [`TrenchBroomConfig::collision_layer_rules`].
+	///
+	/// Only found if there are any rules, and empty for brushes without visible faces such as clip brushes.
+	pub textures: Vec<String>,
 }
 
 impl ConvexHull for BspBrush {
@@ -0,0 +1,348 @@
+//! Culling of BSP geometry and entities with the potentially visible set (PVS) compiled by `vis`, see [`TrenchBroomConfig::bsp_pvs_culling`].
+
+use bevy::{render::view::VisibilitySystems, transform::TransformSystem};
+
+use qbsp::data::bsp::BspLeafContents;
+
+use super::{
+	hull::{BspHull, BspHullChild},
+	*,
+};
+
+pub struct BspPvsPlugin;
+impl Plugin for BspPvsPlugin {
+	fn build(&self, app: &mut App) {
+		#[rustfmt::skip]
+		app
+			.init_asset::<BspPvs>()
+			.register_type::<PvsCulling>()
+
+			.add_systems(PostUpdate, (Self::update_pvs_leaves, Self::cull_by_pvs)
+				.chain()
+				.after(TransformSystem::TransformPropagate)
+				.before(VisibilitySystems::VisibilityPropagate)
+			)
+		;
+	}
+}
+impl BspPvsPlugin {
+	/// Finds the leaves of entities with [`PvsCulling::bounds`] again when they move, like Quake relinking entities into the world.
+	pub fn update_pvs_leaves(
+		mut query: Query<(&mut PvsCulling, &Transform), Or<(Changed<Transform>, Added<PvsCulling>)>>,
+		pvs_assets: Res<Assets<BspPvs>>,
+	) {
+		for (mut culling, transform) in &mut query {
+			let Some((min, max)) = culling.bounds else { continue };
+			let Some(pvs) = pvs_assets.get(&culling.pvs) else { continue };
+
+			let corners = (0..8).map(|corner| {
+				let select = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+				transform.transform_point(Vec3::select(select, max, min))
+			});
+			let (min, max) = corners.fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), corner| {
+				(min.min(corner), max.max(corner))
+			});
+
+			culling.leaves = Some(pvs.hull.box_leaves(min.as_dvec3(), max.as_dvec3()));
+		}
+	}
+
+	/// Hides entities with [`PvsCulling`] that aren't in the potentially visible set of any active camera's leaf, and shows them again once they are.
+	pub fn cull_by_pvs(
+		cameras: Query<(&Camera, &GlobalTransform)>,
+		mut query: Query<(&mut PvsCulling, &Transform, Option<&ChildOf>, &mut Visibility)>,
+		parent_query: Query<&GlobalTransform>,
+		pvs_assets: Res<Assets<BspPvs>>,
+		// Cached per map, reused between frames to save on allocations
+		mut camera_rows: Local<HashMap<Entity, Vec<Option<PvsRow>>>>,
+	) {
+		camera_rows.clear();
+
+		for (mut culling, transform, child_of, mut visibility) in &mut query {
+			let Some(pvs) = pvs_assets.get(&culling.pvs) else { continue };
+			// Maps are spawned at the origin of their scene's root, which has the map's transform
+			let map = child_of.map(ChildOf::parent);
+
+			let rows = camera_rows.entry(map.unwrap_or(Entity::PLACEHOLDER)).or_insert_with(|| {
+				let to_map = map
+					.and_then(|map| parent_query.get(map).ok())
+					.map(|global_transform| global_transform.affine().inverse())
+					.unwrap_or_default();
+
+				cameras
+					.iter()
+					.filter(|(camera, _)| camera.is_active)
+					.map(|(_, camera_transform)| {
+						let position = to_map.transform_point3(camera_transform.translation());
+						pvs.leaf_at(position.as_dvec3()).and_then(|leaf_idx| pvs.visible_leaves(leaf_idx))
+					})
+					.collect()
+			});
+
+			let leaf;
+			let leaves = match &culling.leaves {
+				Some(leaves) => leaves.as_slice(),
+				None => {
+					leaf = pvs.leaf_at(transform.translation.as_dvec3());
+					leaf.as_slice()
+				}
+			};
+
+			// Cameras without a row (like ones outside the map) see everything, as do cameras when there are none
+			let visible = rows.is_empty()
+				|| leaves.is_empty()
+				|| rows
+					.iter()
+					.any(|row| row.as_ref().is_none_or(|row| leaves.iter().any(|leaf_idx| row.contains(*leaf_idx))));
+
+			match culling.culled {
+				Some(previous) if visible => {
+					culling.culled = None;
+					// If it was changed while culled, it's not ours to restore
+					if *visibility == Visibility::Hidden {
+						*visibility = previous;
+					}
+				}
+				// Entities that are already hidden stay that way, without being considered culled
+				None if !visible && *visibility != Visibility::Hidden => {
+					culling.culled = Some(*visibility);
+					*visibility = Visibility::Hidden;
+				}
+				_ => {}
+			}
+		}
+	}
+}
+
+/// Hides this entity via [`Visibility`] when it is outside the potentially visible set of every active camera.
+///
+/// The BSP loader adds this to the mesh entities of worldspawn, to other brush entities, and to point entities with a [`SceneRoot`] or [`Mesh3d`], when [`TrenchBroomConfig::bsp_pvs_culling`] is enabled.
+/// This entity's [`Transform`] should be relative to the map, like the entities of a BSP's scene are.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct PvsCulling {
+	pub pvs: Handle<BspPvs>,
+	/// The leaves this entity is in. If [`None`], the leaf is found from this entity's [`Transform`] every frame, for entities that can move.
+	///
+	/// Entities without any leaves are never culled.
+	pub leaves: Option<Vec<usize>>,
+	/// Bounds of this entity relative to its [`Transform`]. If set, [`Self::leaves`] are found from them whenever the entity moves,
+	/// so that brush entities like doors and platforms are culled by where they are, rather than where they were spawned.
+	pub bounds: Option<(Vec3, Vec3)>,
+	/// The [`Visibility`] this entity had before being hidden by culling, if it currently is. Only entities hidden by culling are made visible again,
+	/// so entities hidden some other way stay hidden.
+	culled: Option<Visibility>,
+}
+impl PvsCulling {
+	pub fn new(pvs: Handle<BspPvs>, leaves: Option<Vec<usize>>) -> Self {
+		Self {
+			pvs,
+			leaves,
+			bounds: None,
+			culled: None,
+		}
+	}
+
+	/// Sets [`Self::bounds`], to keep [`Self::leaves`] up to date as the entity moves.
+	pub fn with_bounds(mut self, min: Vec3, max: Vec3) -> Self {
+		self.bounds = Some((min, max));
+		self
+	}
+
+	/// Whether this entity is currently hidden by culling.
+	pub fn culled(&self) -> bool {
+		self.culled.is_some()
+	}
+}
+
+/// The potentially visible set of a BSP's leaves, along with the BSP tree of its world model to find which leaf a point is in.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct BspPvs {
+	/// Hull 0 of the world model, in Bevy space.
+	pub hull: BspHull,
+	/// Compressed rows of visibility data, one per leaf, where bit `n` is whether leaf `n + 1` is visible, as leaf 0 is the solid leaf outside the map.
+	pub vis_data: Vec<u8>,
+	/// Offsets into [`Self::vis_data`] of each leaf's row, or [`None`] if the leaf doesn't have one.
+	pub vis_offsets: Vec<Option<usize>>,
+	/// The length of each decompressed row in bytes, enough for a bit per leaf of the world model, excluding leaf 0.
+	pub row_len: usize,
+}
+impl BspPvs {
+	/// Reads the PVS from `data`, returning [`None`] if the BSP hasn't been vised.
+	pub fn new(data: &BspData, config: &TrenchBroomConfig) -> Option<Self> {
+		if data.visibility.is_empty() {
+			return None;
+		}
+
+		Some(Self {
+			hull: BspHull::new(data, 0, 0, config)?,
+			vis_data: data.visibility.clone(),
+			vis_offsets: data.leaves.iter().map(|leaf| usize::try_from(leaf.vis_offset).ok()).collect(),
+			// Like Quake, only the world model's leaves have bits, the leaves of other models come after them
+			row_len: (data.models.first()?.num_leaves as usize).div_ceil(8),
+		})
+	}
+
+	/// Returns the index of the non-solid leaf containing the Bevy-space `point`, relative to the map.
+	pub fn leaf_at(&self, point: DVec3) -> Option<usize> {
+		match self.hull.point_leaf(point) {
+			BspHullChild::Leaf {
+				contents,
+				leaf_idx: Some(leaf_idx),
+			} if contents != BspLeafContents::Solid && leaf_idx != 0 => Some(leaf_idx),
+			_ => None,
+		}
+	}
+
+	/// Decompresses the row of leaves visible from leaf `leaf_idx`, or returns [`None`] if it doesn't have one, in which case everything should be considered visible.
+	pub fn visible_leaves(&self, leaf_idx: usize) -> Option<PvsRow> {
+		let offset = (*self.vis_offsets.get(leaf_idx)?)?;
+		decompress_vis_row(self.vis_data.get(offset..)?, self.row_len)
+	}
+}
+
+/// A decompressed row of [`BspPvs`] visibility data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PvsRow(pub Vec<u8>);
+impl PvsRow {
+	/// Whether leaf `leaf_idx` is in this row. Leaf 0 never is.
+	pub fn contains(&self, leaf_idx: usize) -> bool {
+		let Some(bit) = leaf_idx.checked_sub(1) else { return false };
+		self.0.get(bit / 8).is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+	}
+}
+
+/// Decompresses a row of `row_len` bytes, where a 0 byte is followed by how many 0 bytes it stands for. Returns [`None`] if `data` ends early.
+fn decompress_vis_row(data: &[u8], row_len: usize) -> Option<PvsRow> {
+	let mut row = Vec::with_capacity(row_len);
+	let mut bytes = data.iter().copied();
+
+	while row.len() < row_len {
+		match bytes.next()? {
+			0 => row.resize(row.len() + bytes.next()? as usize, 0),
+			byte => row.push(byte),
+		}
+	}
+
+	row.truncate(row_len);
+	Some(PvsRow(row))
+}
+
+#[test]
+fn pvs_row_decompression() {
+	let row = decompress_vis_row(&[0b101, 0, 2, 0b1000_0000, 0xff], 4).unwrap();
+	assert_eq!(row, PvsRow(vec![0b101, 0, 0, 0b1000_0000]));
+	assert!(!row.contains(0));
+	assert!(row.contains(1));
+	assert!(!row.contains(2));
+	assert!(row.contains(3));
+	assert!(row.contains(32));
+	assert!(!row.contains(33));
+
+	assert_eq!(decompress_vis_row(&[0, 8], 4), Some(PvsRow(vec![0; 4])));
+	assert_eq!(decompress_vis_row(&[1, 0], 4), None);
+}
+
+#[test]
+fn pvs_culling() {
+	use bevy::ecs::system::RunSystemOnce;
+
+	// Leaf 1 is where y >= 0 and leaf 2 is where y < 0, and neither can see the other
+	let pvs = BspPvs {
+		hull: BspHull {
+			nodes: vec![super::hull::BspHullNode {
+				plane: BrushPlane {
+					normal: DVec3::Y,
+					distance: 0.,
+				},
+				children: [1, 2].map(|leaf_idx| BspHullChild::Leaf {
+					contents: BspLeafContents::Empty,
+					leaf_idx: Some(leaf_idx),
+				}),
+			}],
+			root: BspHullChild::Node(0),
+		},
+		vis_data: vec![0b01, 0b10],
+		vis_offsets: vec![None, Some(0), Some(1)],
+		row_len: 1,
+	};
+
+	let mut world = World::new();
+	world.init_resource::<Assets<BspPvs>>();
+	let pvs = world.resource_mut::<Assets<BspPvs>>().add(pvs);
+	let camera = world.spawn((Camera::default(), GlobalTransform::from_translation(Vec3::Y))).id();
+	let culled = world
+		.spawn((PvsCulling::new(pvs.clone(), Some(vec![2])), Transform::default(), Visibility::Visible))
+		.id();
+	let hidden = world
+		.spawn((PvsCulling::new(pvs, Some(vec![1])), Transform::default(), Visibility::Hidden))
+		.id();
+
+	world.run_system_once(BspPvsPlugin::cull_by_pvs).unwrap();
+	assert!(world.get::<PvsCulling>(culled).unwrap().culled());
+	assert_eq!(world.get::<Visibility>(culled), Some(&Visibility::Hidden));
+	assert!(!world.get::<PvsCulling>(hidden).unwrap().culled());
+
+	*world.get_mut::<GlobalTransform>(camera).unwrap() = GlobalTransform::from_translation(Vec3::NEG_Y);
+	world.run_system_once(BspPvsPlugin::cull_by_pvs).unwrap();
+	// Restored to what it was before culling
+	assert!(!world.get::<PvsCulling>(culled).unwrap().culled());
+	assert_eq!(world.get::<Visibility>(culled), Some(&Visibility::Visible));
+	// Hidden by the user, not culling, so it stays hidden
+	assert!(!world.get::<PvsCulling>(hidden).unwrap().culled());
+	assert_eq!(world.get::<Visibility>(hidden), Some(&Visibility::Hidden));
+}
+
+#[test]
+fn pvs_culling_moving_entities() {
+	use bevy::ecs::system::RunSystemOnce;
+
+	// Leaf 1 is where y >= 0 and leaf 2 is where y < 0, and neither can see the other
+	let pvs = BspPvs {
+		hull: BspHull {
+			nodes: vec![super::hull::BspHullNode {
+				plane: BrushPlane {
+					normal: DVec3::Y,
+					distance: 0.,
+				},
+				children: [1, 2].map(|leaf_idx| BspHullChild::Leaf {
+					contents: BspLeafContents::Empty,
+					leaf_idx: Some(leaf_idx),
+				}),
+			}],
+			root: BspHullChild::Node(0),
+		},
+		vis_data: vec![0b01, 0b10],
+		vis_offsets: vec![None, Some(0), Some(1)],
+		row_len: 1,
+	};
+
+	let mut world = World::new();
+	world.init_resource::<Assets<BspPvs>>();
+	let pvs = world.resource_mut::<Assets<BspPvs>>().add(pvs);
+	world.spawn((Camera::default(), GlobalTransform::from_translation(Vec3::Y * 10.)));
+	// A platform spawned below the camera's leaf, with its leaves found when loading
+	let platform = world
+		.spawn((
+			PvsCulling::new(pvs, Some(vec![2])).with_bounds(Vec3::splat(-1.), Vec3::splat(1.)),
+			Transform::from_xyz(0., -5., 0.),
+			Visibility::Visible,
+		))
+		.id();
+
+	let update = |world: &mut World| {
+		world.run_system_once(BspPvsPlugin::update_pvs_leaves).unwrap();
+		world.run_system_once(BspPvsPlugin::cull_by_pvs).unwrap();
+	};
+
+	update(&mut world);
+	assert!(world.get::<PvsCulling>(platform).unwrap().culled());
+
+	// Rising into the camera's leaf, it's found there rather than where it was spawned
+	world.get_mut::<Transform>(platform).unwrap().translation.y = 5.;
+	update(&mut world);
+	let culling = world.get::<PvsCulling>(platform).unwrap();
+	assert_eq!(culling.leaves, Some(vec![1]));
+	assert!(!culling.culled());
+	assert_eq!(world.get::<Visibility>(platform), Some(&Visibility::Visible));
+}
@@ -565,6 +565,9 @@ pub enum BspLightAttenuation {
 /// Note that you can set other entity keys on the “misc_external_map” to configure the final entity type.
 /// e.g. if you set “_external_map_classname” to “func_door”,
 /// you can also set a “targetname” key on the “misc_external_map”, or any other keys for “func_door”.
+///
+/// When loading `.map` files directly, [`QuakeMapLoader`](crate::qmap::loader::QuakeMapLoader) resolves these keys at load time too.
+/// Unlike qbsp, it also instantiates point entities of the external map, and can reference `.bsp` files, which are spawned as scenes.
 #[derive(BaseClass, Component, Reflect, Debug, Clone, SmartDefault, Serialize, Deserialize)]
 #[reflect(QuakeClass, Component, Default, Serialize, Deserialize)]
 #[classname("__bsp_external_map")]
@@ -589,4 +592,12 @@ pub struct BspExternalMap {
 	/// Scale factor for the prefab, defaults to 1. Either specify a single value or three scales, “x y z”.
 	#[default(Vec3::ONE)]
 	pub _external_map_scale: Vec3,
+
+	/// Prepended to the `targetname`, `target`, and `killtarget` of entities instantiated from the external map, so multiple instances don't trigger each other.
+	/// Only used by [`QuakeMapLoader`](crate::qmap::loader::QuakeMapLoader).
+	pub _external_map_prefix: Option<String>,
+
+	/// Appended to the `targetname`, `target`, and `killtarget` of entities instantiated from the external map.
+	/// Only used by [`QuakeMapLoader`](crate::qmap::loader::QuakeMapLoader).
+	pub _external_map_suffix: Option<String>,
 }
@@ -3,6 +3,8 @@
 flat! {
 	#[cfg(feature = "bsp")]
 	bsp;
+	#[cfg(any(feature = "rapier", feature = "avian"))]
+	physics;
 }
 
 use fgd::FgdType;
@@ -28,6 +30,9 @@ impl Plugin for BuiltinClassesPlugin {
 			.register_type::<Targetable>()
 		;
 
+		#[cfg(any(feature = "rapier", feature = "avian"))]
+		app.register_type::<PhysicsBody>();
+
 		#[cfg(feature = "client")]
 		#[rustfmt::skip]
 		app
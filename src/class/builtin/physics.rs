@@ -0,0 +1,138 @@
+//! Builtin base classes for use with a physics engine.
+
+#[cfg(feature = "avian")]
+use avian3d::prelude::*;
+#[cfg(feature = "rapier")]
+use bevy_rapier3d::prelude::*;
+use class::QuakeClassSpawnView;
+
+use crate::*;
+
+/// Physics properties settable from TrenchBroom, so that level designers can make physics props without a component per prop.
+///
+/// When spawned, inserts the matching components of whichever physics engine is enabled.
+/// This doesn't add a collider by itself, use it alongside [`GeometryProvider::convex_collider`](geometry::GeometryProvider::convex_collider) or similar.
+#[derive(BaseClass, Component, Reflect, Debug, Clone, SmartDefault, Serialize, Deserialize)]
+#[reflect(QuakeClass, Component, Default, Serialize, Deserialize)]
+#[classname("__physics_body")]
+#[spawn_hook(Self::insert_physics_components)]
+pub struct PhysicsBody {
+	/// How this body moves. `Static` never moves, `Dynamic` is moved by the physics engine, and `Kinematic` is moved by code.
+	pub rigidbody: PhysicsBodyType,
+
+	/// Mass in kilograms. If 0, it is calculated from the collider's volume.
+	pub mass: f32,
+
+	/// How much this body resists sliding against others, 0 being perfectly slippery. Default 0.5.
+	#[default(0.5)]
+	pub friction: f32,
+
+	/// How bouncy this body is, 0 not bouncing at all, and 1 bouncing without losing any energy.
+	pub restitution: f32,
+
+	/// Multiplier of how much gravity affects this body. Default 1.
+	#[default(1.)]
+	pub gravity_scale: f32,
+
+	/// Bit mask of the collision layers this body is in. Default 1.
+	#[default(1)]
+	pub collision_layers: u32,
+
+	/// Bit mask of the collision layers this body can collide with. Default is every layer.
+	#[default(u32::MAX)]
+	pub collision_mask: u32,
+}
+impl PhysicsBody {
+	/// Spawn hook that inserts the physics engine's components matching this body's properties.
+	pub fn insert_physics_components(view: &mut QuakeClassSpawnView) -> anyhow::Result<()> {
+		let Some(body) = view.entity.get::<Self>().cloned() else { return Ok(()) };
+
+		#[cfg(feature = "avian")]
+		{
+			view.entity.insert((
+				match body.rigidbody {
+					PhysicsBodyType::Static => RigidBody::Static,
+					PhysicsBodyType::Dynamic => RigidBody::Dynamic,
+					PhysicsBodyType::Kinematic => RigidBody::Kinematic,
+				},
+				Friction::new(body.friction),
+				Restitution::new(body.restitution),
+				GravityScale(body.gravity_scale),
+				crate::physics::collision_layers_component(body.collision_layers, body.collision_mask),
+			));
+
+			if body.mass > 0. {
+				view.entity.insert(Mass(body.mass));
+			}
+		}
+
+		#[cfg(feature = "rapier")]
+		{
+			view.entity.insert((
+				match body.rigidbody {
+					PhysicsBodyType::Static => RigidBody::Fixed,
+					PhysicsBodyType::Dynamic => RigidBody::Dynamic,
+					PhysicsBodyType::Kinematic => RigidBody::KinematicPositionBased,
+				},
+				Friction::coefficient(body.friction),
+				Restitution::coefficient(body.restitution),
+				GravityScale(body.gravity_scale),
+				crate::physics::collision_layers_component(body.collision_layers, body.collision_mask),
+			));
+
+			if body.mass > 0. {
+				view.entity.insert(ColliderMassProperties::Mass(body.mass));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(FgdType, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PhysicsBodyType {
+	#[default]
+	Static,
+	Dynamic,
+	Kinematic,
+}
+
+#[test]
+fn physics_body_properties() {
+	use crate::util::*;
+	use qmap::QuakeMapEntity;
+
+	let asset_server = create_test_asset_server();
+	let mut load_context = create_load_context(&asset_server, "".into(), false, false);
+	let mut world = World::new();
+	let mut entity = world.spawn_empty();
+
+	PhysicsBody::ERASED_CLASS
+		.apply_spawn_fn_recursive(&mut QuakeClassSpawnView {
+			config: &default(),
+			type_registry: &default(),
+			class_map: &default(),
+			src_entity: &QuakeMapEntity {
+				properties: [("rigidbody", "Dynamic"), ("mass", "5"), ("gravity_scale", "0.5")]
+					.map(|(key, value)| (key.s(), value.s()))
+					.into_iter()
+					.collect(),
+				..default()
+			},
+			class: PhysicsBody::ERASED_CLASS,
+			entity: &mut entity,
+			load_context: &mut load_context,
+		})
+		.unwrap();
+
+	let body = entity.get::<PhysicsBody>().unwrap();
+	assert_eq!(body.rigidbody, PhysicsBodyType::Dynamic);
+	assert_eq!(body.friction, 0.5);
+
+	assert!(matches!(entity.get::<RigidBody>(), Some(RigidBody::Dynamic)));
+	assert_eq!(entity.get::<GravityScale>().unwrap().0, 0.5);
+	#[cfg(feature = "avian")]
+	assert_eq!(entity.get::<Mass>().unwrap().0, 5.);
+	#[cfg(feature = "rapier")]
+	assert!(matches!(entity.get::<ColliderMassProperties>(), Some(ColliderMassProperties::Mass(5.))));
+}
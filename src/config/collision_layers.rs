@@ -0,0 +1,177 @@
+use brush::{Brush, SurfaceAttributes};
+#[cfg(feature = "bsp")]
+use bsp::BspBrush;
+
+use super::*;
+
+/// Puts colliders of brushes and meshes matching [`condition`](Self::condition) into specific collision layers, see [`TrenchBroomConfig::collision_layer_rules`].
+///
+/// Layers are plain bit masks so that they work with both physics engines, corresponding to avian's `CollisionLayers` and rapier's `CollisionGroups`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollisionLayerRule {
+	pub condition: CollisionLayerCondition,
+	/// Bit mask of the layers matching colliders are in.
+	pub memberships: u32,
+	/// Bit mask of the layers matching colliders can collide with.
+	pub filters: u32,
+}
+impl CollisionLayerRule {
+	pub fn new(condition: CollisionLayerCondition, memberships: u32, filters: u32) -> Self {
+		Self {
+			condition,
+			memberships,
+			filters,
+		}
+	}
+}
+
+/// What a [`CollisionLayerRule`] matches. Brushes match if any of their faces do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollisionLayerCondition {
+	/// Faces with this exact texture.
+	Texture(String),
+	/// Faces with any of the bits of this mask in their [`SurfaceAttributes::content_flags`]. Use [`TrenchBroomConfig::content_flag`] to get masks by name.
+	ContentFlags(u32),
+	/// Faces whose texture matches the pattern of the face tag in [`TrenchBroomConfig::face_tags`] with this name.
+	FaceTag(String),
+}
+
+impl TrenchBroomConfig {
+	/// Returns the first rule in [`Self::collision_layer_rules`] matching a face with `texture` and `attributes`.
+	pub fn face_collision_layers(&self, texture: &str, attributes: &SurfaceAttributes) -> Option<&CollisionLayerRule> {
+		self.collision_layer_rules
+			.iter()
+			.find(|rule| self.face_matches_condition(&rule.condition, texture, attributes))
+	}
+
+	/// Returns the first rule in [`Self::collision_layer_rules`] matching any face of `brush`.
+	pub fn brush_collision_layers(&self, brush: &Brush) -> Option<&CollisionLayerRule> {
+		self.collision_layer_rules.iter().find(|rule| {
+			brush
+				.surfaces
+				.iter()
+				.any(|surface| self.face_matches_condition(&rule.condition, &surface.texture, &surface.attributes))
+		})
+	}
+
+	/// Returns the first rule in [`Self::collision_layer_rules`] matching `brush`, by its [`textures`](BspBrush::textures) or its contents.
+	///
+	/// For [`CollisionLayerCondition::ContentFlags`], Quake contents are treated as the equivalent Quake 2 content flags, see [`quake_contents_to_flags`].
+	#[cfg(feature = "bsp")]
+	pub fn bsp_brush_collision_layers(&self, brush: &BspBrush) -> Option<&CollisionLayerRule> {
+		let attributes = SurfaceAttributes {
+			content_flags: quake_contents_to_flags(brush.contents),
+			..default()
+		};
+
+		self.collision_layer_rules.iter().find(|rule| match &rule.condition {
+			CollisionLayerCondition::ContentFlags(_) => self.face_matches_condition(&rule.condition, "", &attributes),
+			condition => brush
+				.textures
+				.iter()
+				.any(|texture| self.face_matches_condition(condition, texture, &attributes)),
+		})
+	}
+
+	fn face_matches_condition(&self, condition: &CollisionLayerCondition, texture: &str, attributes: &SurfaceAttributes) -> bool {
+		match condition {
+			CollisionLayerCondition::Texture(condition_texture) => condition_texture == texture,
+			CollisionLayerCondition::ContentFlags(mask) => attributes.has_content_flags(*mask),
+			CollisionLayerCondition::FaceTag(name) => self
+				.face_tags
+				.iter()
+				.any(|tag| tag.name == *name && matches_tag_pattern(&tag.pattern, texture)),
+		}
+	}
+}
+
+/// Converts a Quake `CONTENTS_*` value, such as in [`BspBrush::contents`], to the equivalent Quake 2 content flags.
+///
+/// Clip brushes (`-8` in `ericw-tools`' `BRUSHLIST`) become both `CONTENTS_PLAYERCLIP` and `CONTENTS_MONSTERCLIP`, and contents without an equivalent (such as sky) have no flags.
+pub fn quake_contents_to_flags(contents: i32) -> u32 {
+	match contents {
+		-2 => 1 << 0,                // CONTENTS_SOLID
+		-3 => 1 << 5,                // CONTENTS_WATER
+		-4 => 1 << 4,                // CONTENTS_SLIME
+		-5 => 1 << 3,                // CONTENTS_LAVA
+		-8 => (1 << 16) | (1 << 17), // CONTENTS_PLAYERCLIP | CONTENTS_MONSTERCLIP
+		_ => 0,
+	}
+}
+
+/// Returns `true` if `text` matches TrenchBroom tag `pattern`, where `*` matches any sequence of characters.
+fn matches_tag_pattern(pattern: &str, text: &str) -> bool {
+	let mut parts = pattern.split('*');
+	let first = parts.next().unwrap_or_default();
+	let Some(mut rest) = text.strip_prefix(first) else { return false };
+	let parts = parts.collect_vec();
+
+	let Some((last, middle)) = parts.split_last() else {
+		// No wildcards
+		return rest.is_empty();
+	};
+
+	for part in middle {
+		match rest.find(part) {
+			Some(idx) => rest = &rest[idx + part.len()..],
+			None => return false,
+		}
+	}
+
+	rest.ends_with(last)
+}
+
+#[test]
+fn collision_layer_rules() {
+	assert!(matches_tag_pattern("clip", "clip"));
+	assert!(!matches_tag_pattern("clip", "clip2"));
+	assert!(matches_tag_pattern("*clip", "monsterclip"));
+	assert!(matches_tag_pattern("sky*", "sky1"));
+	assert!(matches_tag_pattern("*_*_*", "a_b_c"));
+	assert!(!matches_tag_pattern("*_*_*", "a_b"));
+
+	let config = TrenchBroomConfig::default()
+		.face_tags([TrenchBroomTag::new("clips", "*clip")])
+		.collision_layer_rules([
+			CollisionLayerRule::new(CollisionLayerCondition::Texture("projectileclip".s()), 0b100, 0b001),
+			CollisionLayerRule::new(CollisionLayerCondition::FaceTag("clips".s()), 0b010, 0b001),
+			CollisionLayerRule::new(CollisionLayerCondition::ContentFlags(0b1000), 0b001, 0b111),
+		]);
+
+	let attributes = SurfaceAttributes::default();
+	let memberships = |texture: &str, attributes: &SurfaceAttributes| config.face_collision_layers(texture, attributes).map(|rule| rule.memberships);
+	assert_eq!(memberships("projectileclip", &attributes), Some(0b100));
+	assert_eq!(memberships("monsterclip", &attributes), Some(0b010));
+	assert_eq!(memberships("brick", &attributes), None);
+	assert_eq!(
+		memberships(
+			"brick",
+			&SurfaceAttributes {
+				content_flags: 0b1000,
+				..default()
+			}
+		),
+		Some(0b001)
+	);
+
+	#[cfg(feature = "bsp")]
+	{
+		let config = config.collision_layer_rules([
+			CollisionLayerRule::new(CollisionLayerCondition::FaceTag("clips".s()), 0b010, 0b001),
+			CollisionLayerRule::new(CollisionLayerCondition::ContentFlags(1 << 17), 0b100, 0b001),
+		]);
+		let bsp_memberships = |contents: i32, textures: &[&str]| {
+			config
+				.bsp_brush_collision_layers(&BspBrush {
+					planes: Vec::new(),
+					contents,
+					textures: textures.iter().map(|texture| texture.s()).collect(),
+				})
+				.map(|rule| rule.memberships)
+		};
+		assert_eq!(bsp_memberships(-2, &["brick", "monsterclip"]), Some(0b010));
+		assert_eq!(bsp_memberships(-2, &["brick"]), None);
+		// Clip brushes have no faces, but have their own contents
+		assert_eq!(bsp_memberships(-8, &[]), Some(0b100));
+	}
+}
@@ -56,6 +56,8 @@ pub struct EmbeddedTextureLoadView<'a, 'b> {
 	pub image_handle: &'a Handle<Image>,
 	/// The actual image data behind the texture.
 	pub image: &'a Image,
+	/// Whether the map is a GoldSrc BSP30 file, whose special textures differ from Quake's.
+	pub goldsrc: bool,
 }
 
 #[test]
@@ -0,0 +1,85 @@
+use super::*;
+
+/// Per-load settings for [`QuakeMapLoader`](crate::qmap::loader::QuakeMapLoader) and [`BspLoader`](crate::bsp::loader::BspLoader),
+/// usable with [`AssetServer::load_with_settings`] or `.meta` files.
+///
+/// Every [`Some`] field overrides its counterpart in the global [`TrenchBroomConfig`] for the duration of the load.
+#[derive(Serialize, Deserialize, Debug, Clone, SmartDefault)]
+pub struct MapLoadSettings {
+	/// Overrides [`TrenchBroomConfig::scale`].
+	pub scale: Option<f32>,
+	/// Overrides [`TrenchBroomConfig::auto_remove_textures`].
+	pub auto_remove_textures: Option<HashSet<String>>,
+	/// Overrides [`TrenchBroomConfig::suppress_invalid_entity_definitions`].
+	pub suppress_invalid_entity_definitions: Option<bool>,
+	/// Which entities to spawn by classname. (Default: [`ClassnameFilter::All`])
+	pub classname_filter: ClassnameFilter,
+	/// If `false`, no meshes or materials will be created for solid entities, though they will still have their [`Brushes`](crate::geometry::Brushes) and geometry providers applied.
+	/// Useful for collision-only maps, like on a server. (Default: `true`)
+	#[default(true)]
+	pub generate_meshes: bool,
+}
+
+impl MapLoadSettings {
+	/// Returns `tb_server` with the overrides of these settings applied. If nothing is overridden, this is a cheap clone.
+	pub fn apply(&self, tb_server: &TrenchBroomServer) -> TrenchBroomServer {
+		if self.scale.is_none() && self.auto_remove_textures.is_none() && self.suppress_invalid_entity_definitions.is_none() {
+			return tb_server.clone();
+		}
+
+		let mut config = tb_server.config.clone();
+
+		if let Some(scale) = self.scale {
+			config.scale = scale;
+		}
+		if let Some(auto_remove_textures) = &self.auto_remove_textures {
+			config.auto_remove_textures = auto_remove_textures.clone();
+		}
+		if let Some(suppress_invalid_entity_definitions) = self.suppress_invalid_entity_definitions {
+			config.suppress_invalid_entity_definitions = suppress_invalid_entity_definitions;
+		}
+
+		TrenchBroomServer::new(config)
+	}
+}
+
+/// Filters which entities get spawned when loading a map, see [`MapLoadSettings::classname_filter`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub enum ClassnameFilter {
+	/// Spawns every entity.
+	#[default]
+	All,
+	/// Only spawns entities with a classname in the set.
+	Allow(HashSet<String>),
+	/// Spawns every entity except ones with a classname in the set.
+	Deny(HashSet<String>),
+}
+
+impl ClassnameFilter {
+	/// Returns `true` if entities with `classname` should be spawned.
+	pub fn allows(&self, classname: &str) -> bool {
+		match self {
+			Self::All => true,
+			Self::Allow(set) => set.contains(classname),
+			Self::Deny(set) => !set.contains(classname),
+		}
+	}
+}
+
+#[test]
+fn load_settings_overrides() {
+	let tb_server = TrenchBroomServer::new(TrenchBroomConfig::default());
+
+	let settings = MapLoadSettings {
+		scale: Some(16.),
+		classname_filter: ClassnameFilter::Deny(["light".s()].into()),
+		..default()
+	};
+
+	let overridden = settings.apply(&tb_server);
+	assert_eq!(overridden.config.scale, 16.);
+	assert_eq!(overridden.config.auto_remove_textures, tb_server.config.auto_remove_textures);
+
+	assert!(settings.classname_filter.allows("worldspawn"));
+	assert!(!settings.classname_filter.allows("light"));
+}
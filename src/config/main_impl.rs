@@ -1,4 +1,4 @@
-use crate::{class::builtin::{read_rotation_from_entity, read_translation_from_entity}, util::AssetServerExistsExt};
+use crate::{brush::Brush, class::builtin::{read_rotation_from_entity, read_translation_from_entity}, util::AssetServerExistsExt};
 #[cfg(feature = "bsp")]
 use bsp::GENERIC_MATERIAL_PREFIX;
 
@@ -16,6 +16,60 @@ impl TrenchBroomConfig {
 		self
 	}
 
+	/// Inserts a new texture to remove from rendering, but keep for collision.
+	pub fn invisible_texture(mut self, texture: impl ToString) -> Self {
+		self.invisible_textures.insert(texture.to_string());
+		self
+	}
+
+	/// Inserts a new texture to render, but not collide with.
+	pub fn non_colliding_texture(mut self, texture: impl ToString) -> Self {
+		self.non_colliding_textures.insert(texture.to_string());
+		self
+	}
+
+	/// Returns `true` if faces with `texture` should have meshes, being in neither [`Self::auto_remove_textures`] nor [`Self::invisible_textures`].
+	pub fn is_texture_rendered(&self, texture: &str) -> bool {
+		!self.auto_remove_textures.contains(texture) && !self.invisible_textures.contains(texture)
+	}
+
+	/// Returns `true` if `brush` should have a collider, which is if any of its faces have a texture in neither [`Self::auto_remove_textures`] nor [`Self::non_colliding_textures`].
+	pub fn is_brush_colliding(&self, brush: &Brush) -> bool {
+		brush
+			.surfaces
+			.iter()
+			.any(|surface| !self.auto_remove_textures.contains(&surface.texture) && !self.non_colliding_textures.contains(&surface.texture))
+	}
+
+	/// Returns `true` if `brush` hides the faces of other brushes behind it when [removing hidden faces](crate::geometry::GeometryProvider::remove_hidden_faces), like solid brushes do in `qbsp`.
+	///
+	/// Brushes that can be seen through don't, which are ones with any face that isn't [rendered](Self::is_texture_rendered), has a liquid (`*` or `!`) or cutout (`{`) texture,
+	/// is `trans33` or `trans66` in [`Self::surface_flags`], or has content flags without `solid` in [`Self::content_flags`].
+	pub fn is_brush_occluding(&self, brush: &Brush) -> bool {
+		let solid = self.content_flag("solid");
+		let translucent = ["trans33", "trans66"]
+			.into_iter()
+			.filter_map(|name| self.surface_flag(name))
+			.fold(0, |mask, flag| mask | flag);
+
+		brush.surfaces.iter().all(|surface| {
+			self.is_texture_rendered(&surface.texture)
+				&& !surface.texture.starts_with(['*', '!', '{'])
+				&& !surface.attributes.has_surface_flags(translucent)
+				&& solid.is_none_or(|solid| surface.attributes.content_flags == 0 || surface.attributes.has_content_flags(solid))
+		})
+	}
+
+	/// Returns the bit mask of the surface flag named `name` in [`Self::surface_flags`], or [`None`] if there isn't one.
+	pub fn surface_flag(&self, name: &str) -> Option<u32> {
+		BitFlag::find_mask(&self.surface_flags, name)
+	}
+
+	/// Returns the bit mask of the content flag named `name` in [`Self::content_flags`], or [`None`] if there isn't one.
+	pub fn content_flag(&self, name: &str) -> Option<u32> {
+		BitFlag::find_mask(&self.content_flags, name)
+	}
+
 	/// Excludes "\*_normal", "\*_mr" (Metallic and roughness), "\*_emissive", and "\*_depth".
 	pub fn default_texture_exclusions() -> Vec<String> {
 		vec!["*_normal".into(), "*_mr".into(), "*_emissive".into(), "*_depth".into()]
@@ -1,5 +1,7 @@
 flat! {
+	collision_layers;
 	hooks;
+	load_settings;
 	main_impl;
 	#[cfg(feature = "client")]
 	set_sampler;
@@ -52,7 +54,7 @@ pub struct TrenchBroomConfig {
 	/// By default, the Bevy logo is used.
 	#[default(Some(include_bytes!("default_icon.png").into()))]
 	pub icon: Option<Cow<'static, [u8]>>,
-	/// Supported map file formats. Currently, only the loading of [`Valve`](MapFileFormat::Valve) is supported.
+	/// Supported map file formats. Currently, only the loading of [`Standard`](MapFileFormat::Standard), [`Valve`](MapFileFormat::Valve), [`Quake2`](MapFileFormat::Quake2), and [`Quake2Valve`](MapFileFormat::Quake2Valve) is supported.
 	///
 	/// (Default: [`MapFileFormat::Valve`])
 	#[default(vec![MapFileFormat::Valve])]
@@ -118,13 +120,15 @@ pub struct TrenchBroomConfig {
 
 	/// Game-defined flags per face.
 	///
-	/// TODO: Currently, these do not save in the map file, or are able to be loaded, as it requires [MapFileFormat::Quake2] or higher, which isn't supported yet.
+	/// These are only saved in the map file when using [`MapFileFormat::Quake2`] or [`MapFileFormat::Quake2Valve`],
+	/// and are loaded into [`SurfaceAttributes`](brush::SurfaceAttributes). Use [`Self::surface_flag`] to get the bit of a flag by name.
 	#[builder(into)]
 	pub surface_flags: Vec<BitFlag>,
 	/// Game-defined flags per face.
 	/// According to [TrenchBroom docs](https://trenchbroom.github.io/manual/latest/#game_configuration_files), unlike [`Self::surface_flags`], this is "generally affecting the behavior of the brush containing the face".
 	///
-	/// TODO: Currently, these do not save in the map file, or are able to be loaded, as it requires [MapFileFormat::Quake2] or higher, which isn't supported yet.
+	/// These are only saved in the map file when using [`MapFileFormat::Quake2`] or [`MapFileFormat::Quake2Valve`],
+	/// and are loaded into [`SurfaceAttributes`](brush::SurfaceAttributes). Use [`Self::content_flag`] to get the bit of a flag by name.
 	#[builder(into)]
 	pub content_flags: Vec<BitFlag>,
 
@@ -174,6 +178,22 @@ pub struct TrenchBroomConfig {
 	#[cfg(feature = "bsp")]
 	pub no_bsp_lighting: bool,
 
+	/// If a BSP doesn't have the `BRUSHLIST` BSPX lump, builds [`BspBrush`](bsp::BspBrush)es from the solid leaves of each model's BSP tree instead,
+	/// so that `ConvexCollision` still works on BSPs compiled without it. (Default: `true`)
+	///
+	/// This produces many more, smaller brushes than `BRUSHLIST` would. Clip brushes aren't part of the leaves, so they are lost, see [`BspHull::solid_brushes`](bsp::hull::BspHull::solid_brushes).
+	/// If disabled, a warning is logged for each BSP loaded without `BRUSHLIST`.
+	#[cfg(feature = "bsp")]
+	#[default(true)]
+	pub bsp_leaf_brushes_fallback: bool,
+
+	/// Whether to hide BSP geometry and entities outside the potentially visible set of the cameras' leaves, see [`PvsCulling`](bsp::pvs::PvsCulling). (Default: false)
+	///
+	/// This splits worldspawn's meshes up by the leaves their triangles are in, so it adds draw calls in open areas where most of the map is visible,
+	/// but can save many in dense indoor maps. BSPs that haven't been vised are unaffected.
+	#[cfg(feature = "bsp")]
+	pub bsp_pvs_culling: bool,
+
 	#[cfg(feature = "bsp")]
 	#[builder(skip)]
 	#[default(Hook(Arc::new(Self::default_load_embedded_texture)))]
@@ -200,7 +220,7 @@ pub struct TrenchBroomConfig {
 	#[default(Some(default))]
 	pub embedded_quake_sky_material: Option<fn() -> QuakeSkyMaterial>,
 
-	/// If [`Some`], embedded textures with names that start with `*` will use [`LiquidMaterial`], and will abide by the `water_alpha` worldspawn key.
+	/// If [`Some`], embedded textures with names that start with `*` (or `!` in GoldSrc BSPs) will use [`LiquidMaterial`], and will abide by the `water_alpha` worldspawn key.
 	///
 	/// (Default: `Some(QuakeSkyMaterial::default)`)
 	#[cfg(all(feature = "client", feature = "bsp"))]
@@ -214,11 +234,36 @@ pub struct TrenchBroomConfig {
 	#[default(true)]
 	pub embedded_texture_cutouts: bool,
 
-	/// Set of textures to skip meshes of on map load. (Default: `["clip", "skip", "__TB_empty"]`)
-	#[default(["clip".s(), "skip".s(), "__TB_empty".s()].into())]
+	/// Set of textures to remove from both rendering and collision on map load. (Default: `["__TB_empty"]`)
+	///
+	/// Faces with these textures don't get meshes, and brushes entirely made of these (or [`non_colliding_textures`](Self::non_colliding_textures)) don't get colliders.
+	#[default(["__TB_empty".s()].into())]
 	#[builder(into)]
 	pub auto_remove_textures: HashSet<String>,
 
+	/// Set of textures to skip meshes of on map load, while keeping their brushes for collision, such as player clips and triggers. (Default: `["clip", "skip", "trigger"]`)
+	#[default(["clip".s(), "skip".s(), "trigger".s()].into())]
+	#[builder(into)]
+	pub invisible_textures: HashSet<String>,
+
+	/// Set of textures that are rendered, but whose brushes don't collide, such as foliage. (Default: `[]`)
+	///
+	/// Since colliders are made per brush, a brush only stops colliding if all of its faces have one of these textures (or [`auto_remove_textures`](Self::auto_remove_textures)).
+	/// This only affects `ConvexCollision`, as `TrimeshCollision` uses rendered meshes.
+	#[builder(into)]
+	pub non_colliding_textures: HashSet<String>,
+
+	/// Rules putting the colliders of matching brushes and meshes into specific collision layers, such as for monster or projectile clips.
+	/// The first matching rule is used, and wins over the layers of the entity. Colliders without one are left in the entity's layers, such as from `PhysicsBody`,
+	/// or the physics engine's default layers if it has none. (Default: `[]`)
+	///
+	/// When an entity with `ConvexCollision` has brushes in different layers, the entity's collider holds the brushes without a rule if there are any,
+	/// and the others are put on child entities.
+	///
+	/// BSP brushes are matched by their contents and the textures of the faces on them, see [`Self::bsp_brush_collision_layers`].
+	#[builder(into)]
+	pub collision_layer_rules: Vec<CollisionLayerRule>,
+
 	/// If a brush is fully textured with the name of one of these when loading a `.map` file, it will set the transformation origin of the entity to which it belongs to the center of the brush, removing the origin brush after.
 	///
 	/// This allows, for example, your `func_rotate` entity to easily rotate around a specific point.
@@ -228,6 +273,25 @@ pub struct TrenchBroomConfig {
 	#[builder(into)]
 	pub origin_textures: HashSet<String>,
 
+	/// If `true`, solid entities made of identical brushes (such as the copies of a TrenchBroom linked group) will share the same mesh assets when loading `.map` files,
+	/// reducing load time and memory usage in maps with lots of repeated geometry.
+	///
+	/// Linked groups aren't folded into worldspawn, instead each copy is spawned as an entity with [`LinkedGroupBrushes`](crate::qmap::LinkedGroupBrushes) under the group's entity.
+	/// These get their meshes from worldspawn's geometry provider, but don't go through worldspawn's spawn function or the [`global_spawner`](Self::global_spawner), so there is still only one worldspawn.
+	/// Other entities are only shared if they have an `origin`, as their class might not read it into a [`Transform`] to move the shared geometry back into place.
+	/// Their [`BrushList`](geometry::BrushList)s are shared too, so they're relative to the entity rather than the map, see [`LocalBrushes`](geometry::LocalBrushes).
+	///
+	/// NOTE: Only translated copies are detected, and any geometry providers modifying meshes based on anything other than the geometry itself will only run for the first copy.
+	///
+	/// (Default: `false`)
+	pub share_identical_brush_entities: bool,
+
+	/// When loading `.map` files, [statically batched](crate::geometry::GeometryProvider::static_batch) meshes are split into cubic cells of this size in Bevy units,
+	/// so that frustum culling still has something to cull. If `None`, there will be one batch per material for the whole map.
+	///
+	/// (Default: `None`)
+	pub static_batch_cell_size: Option<f32>,
+
 	/// How lightmaps atlas' are computed when loading BSP files.
 	///
 	/// It's worth noting that `wgpu` has a [texture size limit of 2048](https://github.com/gfx-rs/wgpu/discussions/2952), which can be expanded via [`RenderPlugin`](bevy::render::RenderPlugin) if needed.
@@ -133,6 +133,16 @@ pub enum BitFlag {
 	/// Shows up in-editor with the specified name and optional description.
 	Used { name: String, description: Option<String> },
 }
+impl BitFlag {
+	/// Finds the flag named `name` in `flags`, returning the bit it represents.
+	pub fn find_mask(flags: &[BitFlag], name: &str) -> Option<u32> {
+		flags
+			.iter()
+			.take(u32::BITS as usize)
+			.position(|flag| matches!(flag, BitFlag::Used { name: flag_name, .. } if flag_name == name))
+			.map(|bit| 1 << bit)
+	}
+}
 impl From<BitFlag> for json::JsonValue {
 	fn from(value: BitFlag) -> Self {
 		match value {
@@ -0,0 +1,846 @@
+//! A bounding volume hierarchy over every brush in the world, for casts and point queries without a physics engine.
+
+use brush::{BrushPlane, BrushSurface, ConvexHull, HullHit, HullTrace, cast_through_planes, expanded_planes, trace_hull};
+#[cfg(feature = "bsp")]
+use bsp::BspBrush;
+use itertools::Either;
+
+use super::*;
+
+/// A brush stored in [`BrushBvh`], as it is in its entity's [`Brushes`].
+#[derive(Debug, Clone)]
+pub enum BvhBrush {
+	Brush(Brush),
+	#[cfg(feature = "bsp")]
+	Bsp(BspBrush),
+}
+impl BvhBrush {
+	/// Returns the surface at `plane_idx` if this is a textured [`Brush`].
+	pub fn surface(&self, plane_idx: usize) -> Option<&BrushSurface> {
+		match self {
+			Self::Brush(brush) => brush.surfaces.get(plane_idx),
+			#[cfg(feature = "bsp")]
+			Self::Bsp(_) => None,
+		}
+	}
+}
+impl ConvexHull for BvhBrush {
+	fn planes(&self) -> impl Iterator<Item = &BrushPlane> + Clone {
+		match self {
+			Self::Brush(brush) => Either::Left(brush.planes()),
+			#[cfg(feature = "bsp")]
+			Self::Bsp(brush) => Either::Right(brush.planes()),
+		}
+	}
+}
+
+/// The brushes of an entity in [`BrushBvh`] along with their bounds in the brushes' space, or [`None`] for brushes without any volume.
+/// Entities with the same [`Brushes::Shared`] or [`Brushes::Bsp`] asset share the same list.
+type BvhBrushes = Arc<[(BvhBrush, Option<(DVec3, DVec3)>)]>;
+
+fn bvh_brushes(brushes: impl IntoIterator<Item = BvhBrush>) -> BvhBrushes {
+	brushes
+		.into_iter()
+		.map(|brush| {
+			let bounds = bounds_of(brush.calculate_vertices().map(|(vertex, _)| vertex));
+			(brush, bounds)
+		})
+		.collect()
+}
+
+/// The asset a [`BvhBrushes`] was made from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum BvhAssetId {
+	List(AssetId<BrushList>),
+	#[cfg(feature = "bsp")]
+	Bsp(AssetId<BspBrushesAsset>),
+}
+
+/// A brush of an entity in [`BrushBvh`].
+#[derive(Debug, Clone)]
+pub struct BvhEntry {
+	pub entity: Entity,
+	/// The index of the brush in the entity's [`Brushes`].
+	pub brush_idx: usize,
+	brushes: BvhBrushes,
+	/// Transforms from the brush's space to world space, including the entity's [`local_offset`](Brushes::local_offset).
+	pub to_world: DAffine3,
+	/// Transforms from world space into the brush's space.
+	pub to_local: DAffine3,
+	/// World-space bounds.
+	pub min: DVec3,
+	pub max: DVec3,
+	/// Bounds in the brush's space, used for the bevel planes of box casts and traces.
+	pub local_min: DVec3,
+	pub local_max: DVec3,
+}
+impl BvhEntry {
+	pub fn brush(&self) -> &BvhBrush {
+		&self.brushes[self.brush_idx].0
+	}
+
+	/// Moves the entry to `to_world`, updating its world-space bounds from its local ones.
+	fn set_to_world(&mut self, to_world: DAffine3) {
+		let (local_min, local_max) = (self.local_min, self.local_max);
+		let corners = (0..8).map(|corner| {
+			let select = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+			to_world.transform_point3(DVec3::select(select, local_max, local_min))
+		});
+		(self.min, self.max) = bounds_of(corners).unwrap_or_default();
+		self.to_world = to_world;
+		self.to_local = to_world.inverse();
+	}
+}
+
+#[derive(Debug, Clone)]
+enum BvhNode {
+	Leaf {
+		min: DVec3,
+		max: DVec3,
+		entries: std::ops::Range<usize>,
+	},
+	Branch {
+		min: DVec3,
+		max: DVec3,
+		children: [usize; 2],
+	},
+}
+impl BvhNode {
+	fn bounds(&self) -> (DVec3, DVec3) {
+		match self {
+			Self::Leaf { min, max, .. } | Self::Branch { min, max, .. } => (*min, *max),
+		}
+	}
+}
+
+fn bounds_of(points: impl Iterator<Item = DVec3>) -> Option<(DVec3, DVec3)> {
+	points
+		.map(|point| (point, point))
+		.reduce(|(min, max), (point, _)| (min.min(point), max.max(point)))
+}
+
+/// A hit from casting against [`BrushBvh`], in world space.
+#[derive(Debug, Clone)]
+pub struct BrushHit<'a> {
+	pub entity: Entity,
+	pub brush_idx: usize,
+	/// How far along the cast the hit is, as a multiple of the cast direction's length.
+	pub distance: f32,
+	pub position: Vec3,
+	pub normal: Vec3,
+	/// The surface that was hit, if the brush has them. BSP brushes and bevel planes of box casts don't.
+	pub surface: Option<&'a BrushSurface>,
+}
+
+/// The result of [`BrushBvh::trace`], in world space.
+#[derive(Debug, Clone)]
+pub struct BrushTrace<'a> {
+	/// How far the box got before hitting something, from 0 to 1.
+	pub fraction: f32,
+	/// Where the center of the box ended up.
+	pub end: Vec3,
+	/// The plane that was hit, if any.
+	pub plane: Option<BrushPlane>,
+	/// The entity and index of the brush that was hit, if any.
+	pub brush: Option<(Entity, usize)>,
+	/// The surface that was hit, if the brush has them. BSP brushes and bevel planes don't.
+	pub surface: Option<&'a BrushSurface>,
+	/// If the box started inside a brush.
+	pub start_solid: bool,
+	/// If the box was inside a brush the whole way, in which case [`fraction`](Self::fraction) is 0.
+	pub all_solid: bool,
+}
+impl BrushTrace<'_> {
+	/// Returns `true` if the trace was stopped before reaching its end.
+	pub fn hit(&self) -> bool {
+		self.fraction < 1.
+	}
+}
+
+/// A bounding volume hierarchy over the brushes of every entity with [`Brushes`] and a [`GlobalTransform`],
+/// allowing ray casts and point queries against level geometry without `rapier` or `avian`.
+///
+/// This isn't added by default, `init_resource` it to enable it. The brushes of each entity are kept between updates,
+/// so moving brush entities only updates their bounds, and only adding, changing or removing brushes rebuilds the hierarchy.
+///
+/// Brushes are copied in once per asset, as [`Brushes::Shared`] and [`Brushes::Bsp`] assets may be unloaded, and entities with the same asset share the copy.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BrushBvh {
+	entries: Vec<BvhEntry>,
+	nodes: Vec<BvhNode>,
+	/// Brushes copied from assets, shared between the entities using them.
+	assets: HashMap<BvhAssetId, BvhBrushes>,
+	/// Each entity's brushes, and the transform from their space to world space.
+	entities: HashMap<Entity, (BvhBrushes, DAffine3)>,
+}
+
+impl BrushBvh {
+	/// How many entries each leaf can hold.
+	const LEAF_SIZE: usize = 4;
+
+	/// Rebuilds the hierarchy from every entity's brushes.
+	fn rebuild(&mut self) {
+		let mut entries = self
+			.entities
+			.iter()
+			.flat_map(|(entity, (brushes, to_world))| {
+				brushes.iter().enumerate().filter_map(|(brush_idx, (_, bounds))| {
+					let (local_min, local_max) = (*bounds)?;
+					let mut entry = BvhEntry {
+						entity: *entity,
+						brush_idx,
+						brushes: brushes.clone(),
+						to_world: DAffine3::IDENTITY,
+						to_local: DAffine3::IDENTITY,
+						min: DVec3::ZERO,
+						max: DVec3::ZERO,
+						local_min,
+						local_max,
+					};
+					entry.set_to_world(*to_world);
+					Some(entry)
+				})
+			})
+			.collect_vec();
+
+		self.nodes.clear();
+		if !entries.is_empty() {
+			Self::build_node(&mut self.nodes, &mut entries, 0);
+		}
+		self.entries = entries;
+
+		// Assets no entity uses anymore
+		self.assets.retain(|_, brushes| Arc::strong_count(brushes) > 1);
+	}
+
+	/// Moves the entries of entities whose transforms changed, then updates the bounds of every node without rebuilding the hierarchy.
+	fn refit(&mut self) {
+		for entry in &mut self.entries {
+			let Some((_, to_world)) = self.entities.get(&entry.entity) else { continue };
+			if *to_world != entry.to_world {
+				entry.set_to_world(*to_world);
+			}
+		}
+
+		// Children are always after their parents, so going backwards updates them first
+		for node_idx in (0..self.nodes.len()).rev() {
+			let bounds = match &self.nodes[node_idx] {
+				BvhNode::Leaf { entries, .. } => self.entries[entries.clone()]
+					.iter()
+					.map(|entry| (entry.min, entry.max))
+					.reduce(|(a_min, a_max), (b_min, b_max)| (a_min.min(b_min), a_max.max(b_max))),
+				BvhNode::Branch { children, .. } => {
+					let ((a_min, a_max), (b_min, b_max)) = (self.nodes[children[0]].bounds(), self.nodes[children[1]].bounds());
+					Some((a_min.min(b_min), a_max.max(b_max)))
+				}
+			};
+			let Some(bounds) = bounds else { continue };
+
+			match &mut self.nodes[node_idx] {
+				BvhNode::Leaf { min, max, .. } | BvhNode::Branch { min, max, .. } => (*min, *max) = bounds,
+			}
+		}
+	}
+
+	/// Builds the node containing `entries` (which start at `offset` in the full list), returning its index.
+	fn build_node(nodes: &mut Vec<BvhNode>, entries: &mut [BvhEntry], offset: usize) -> usize {
+		let (min, max) = entries
+			.iter()
+			.map(|entry| (entry.min, entry.max))
+			.reduce(|(a_min, a_max), (b_min, b_max)| (a_min.min(b_min), a_max.max(b_max)))
+			.unwrap_or_default();
+
+		let node_idx = nodes.len();
+
+		if entries.len() <= Self::LEAF_SIZE {
+			nodes.push(BvhNode::Leaf {
+				min,
+				max,
+				entries: offset..offset + entries.len(),
+			});
+			return node_idx;
+		}
+
+		// Split down the middle of the longest axis
+		let axis = (max - min).max_position();
+		entries.sort_unstable_by(|a, b| (a.min[axis] + a.max[axis]).total_cmp(&(b.min[axis] + b.max[axis])));
+		let (left, right) = entries.split_at_mut(entries.len() / 2);
+		let left_len = left.len();
+
+		// Placeholder until the children are built
+		nodes.push(BvhNode::Leaf { min, max, entries: 0..0 });
+		let children = [Self::build_node(nodes, left, offset), Self::build_node(nodes, right, offset + left_len)];
+		nodes[node_idx] = BvhNode::Branch { min, max, children };
+
+		node_idx
+	}
+
+	/// Copies in the brushes of `entity`, or removes it if its assets aren't loaded. Takes effect on the next [`rebuild`](Self::rebuild).
+	fn load_entity(
+		&mut self,
+		entity: Entity,
+		brushes: &Brushes,
+		to_world: DAffine3,
+		brush_lists: &Assets<BrushList>,
+		#[cfg(feature = "bsp")] bsp_brushes: &Assets<BspBrushesAsset>,
+	) {
+		let brushes = match brushes {
+			Brushes::Owned(list) => Some(bvh_brushes(list.iter().cloned().map(BvhBrush::Brush))),
+			Brushes::Shared(handle) => self.asset_brushes(BvhAssetId::List(handle.id()), || {
+				brush_lists.get(handle).map(|list| list.iter().cloned().map(BvhBrush::Brush).collect())
+			}),
+			#[cfg(feature = "bsp")]
+			Brushes::Bsp(handle) => self.asset_brushes(BvhAssetId::Bsp(handle.id()), || {
+				bsp_brushes.get(handle).map(|asset| asset.brushes.iter().cloned().map(BvhBrush::Bsp).collect())
+			}),
+		};
+
+		match brushes {
+			Some(brushes) => self.entities.insert(entity, (brushes, to_world)),
+			None => self.entities.remove(&entity),
+		};
+	}
+
+	/// Returns the brushes copied from the asset `id`, copying them with `load` if they haven't been yet.
+	fn asset_brushes(&mut self, id: BvhAssetId, load: impl FnOnce() -> Option<Vec<BvhBrush>>) -> Option<BvhBrushes> {
+		if let Some(brushes) = self.assets.get(&id) {
+			return Some(brushes.clone());
+		}
+
+		let brushes = bvh_brushes(load()?);
+		self.assets.insert(id, brushes.clone());
+		Some(brushes)
+	}
+
+	/// Returns the transform from the space of an entity's brushes to world space, from its [`GlobalTransform`] and the [`local_offset`](Brushes::local_offset) of its brushes.
+	fn to_world(global_transform: &GlobalTransform, local_offset: Vec3) -> DAffine3 {
+		// Map-space brushes are moved into the entity's local space before its transform is applied
+		DAffine3::from_mat4(global_transform.compute_matrix().as_dmat4()) * DAffine3::from_translation(local_offset.as_dvec3())
+	}
+
+	/// Builds a hierarchy from every entity with [`Brushes`] in `query` whose assets are loaded,
+	/// along with the [`local_offset`](Brushes::local_offset) of their brushes.
+	pub fn from_entities<'a>(
+		query: impl IntoIterator<Item = (Entity, &'a Brushes, &'a GlobalTransform, Vec3)>,
+		brush_lists: &Assets<BrushList>,
+		#[cfg(feature = "bsp")] bsp_brushes: &Assets<BspBrushesAsset>,
+	) -> Self {
+		let mut bvh = Self::default();
+
+		for (entity, brushes, global_transform, local_offset) in query {
+			bvh.load_entity(
+				entity,
+				brushes,
+				Self::to_world(global_transform, local_offset),
+				brush_lists,
+				#[cfg(feature = "bsp")]
+				bsp_brushes,
+			);
+		}
+
+		bvh.rebuild();
+		bvh
+	}
+
+	/// Every brush in the hierarchy.
+	pub fn entries(&self) -> &[BvhEntry] {
+		&self.entries
+	}
+
+	/// Casts a ray from `origin` along `direction`, returning the closest brush it enters within `max_distance` (as a multiple of `direction`'s length)
+	/// whose entity passes `filter`. Rays starting inside a brush don't hit it.
+	pub fn cast_ray(&self, origin: Vec3, direction: Vec3, max_distance: f32, filter: impl Fn(Entity) -> bool) -> Option<BrushHit<'_>> {
+		self.cast(origin, direction, max_distance, None, filter)
+	}
+
+	/// Casts a line segment from `start` to `end`, [`BrushHit::distance`] being how far along the segment the hit is from 0 to 1.
+	pub fn cast_segment(&self, start: Vec3, end: Vec3, filter: impl Fn(Entity) -> bool) -> Option<BrushHit<'_>> {
+		self.cast(start, end - start, 1., None, filter)
+	}
+
+	/// Sweeps an axis-aligned box with `half_extents` centered on `origin` along `direction`, returning the closest brush it touches within `max_distance`.
+	///
+	/// The box is axis-aligned in the local space of each brush entity, which makes it slightly larger against rotated entities.
+	pub fn cast_aabb(
+		&self,
+		origin: Vec3,
+		half_extents: Vec3,
+		direction: Vec3,
+		max_distance: f32,
+		filter: impl Fn(Entity) -> bool,
+	) -> Option<BrushHit<'_>> {
+		self.cast(origin, direction, max_distance, Some(half_extents), filter)
+	}
+
+	fn cast(
+		&self,
+		origin: Vec3,
+		direction: Vec3,
+		max_distance: f32,
+		half_extents: Option<Vec3>,
+		filter: impl Fn(Entity) -> bool,
+	) -> Option<BrushHit<'_>> {
+		let (origin, direction) = (origin.as_dvec3(), direction.as_dvec3());
+		let half_extents = half_extents.map(Vec3::as_dvec3);
+		let padding = half_extents.unwrap_or(DVec3::ZERO);
+
+		let mut best: Option<(&BvhEntry, HullHit)> = None;
+		// Shrinks as closer hits are found, shared between both closures
+		let max_distance = std::cell::Cell::new(max_distance as f64);
+
+		self.visit(
+			|min, max| ray_aabb_entry(origin, direction, min - padding, max + padding).is_some_and(|distance| distance <= max_distance.get()),
+			|entry| {
+				if !filter(entry.entity) {
+					return;
+				}
+
+				// Affine transforms keep distances along the ray the same, so they can be compared between entities
+				let local_origin = entry.to_local.transform_point3(origin);
+				let local_direction = entry.to_local.transform_vector3(direction);
+
+				let hit = match half_extents {
+					None => entry.brush().cast_ray(local_origin, local_direction, max_distance.get()),
+					Some(half_extents) => {
+						let local_half_extents = entry.to_local.matrix3.abs() * half_extents;
+						cast_through_planes(
+							expanded_planes(entry.brush(), Some((entry.local_min, entry.local_max)), local_half_extents),
+							local_origin,
+							local_direction,
+							max_distance.get(),
+						)
+					}
+				};
+
+				if let Some(hit) = hit {
+					max_distance.set(hit.distance);
+					best = Some((entry, hit));
+				}
+			},
+		);
+
+		best.map(|(entry, hit)| {
+			let normal_matrix = entry.to_local.matrix3.transpose();
+			BrushHit {
+				entity: entry.entity,
+				brush_idx: entry.brush_idx,
+				distance: hit.distance as f32,
+				position: (origin + direction * hit.distance).as_vec3(),
+				normal: (normal_matrix * hit.normal).normalize_or_zero().as_vec3(),
+				surface: hit.plane_idx.and_then(|plane_idx| entry.brush().surface(plane_idx)),
+			}
+		})
+	}
+
+	/// Sweeps an axis-aligned box with `half_extents` from `start` to `end` against every brush whose entity passes `filter`, like Quake's `trace`.
+	///
+	/// The box stops `epsilon` short of whatever it hits so that the next trace doesn't start inside it, see [`QUAKE_TRACE_EPSILON`](brush::QUAKE_TRACE_EPSILON).
+	/// Like [`cast_aabb`](Self::cast_aabb), the box is axis-aligned in the local space of each brush entity.
+	pub fn trace(&self, start: Vec3, end: Vec3, half_extents: Vec3, epsilon: f32, filter: impl Fn(Entity) -> bool) -> BrushTrace<'_> {
+		let (start, end, half_extents, epsilon) = (start.as_dvec3(), end.as_dvec3(), half_extents.as_dvec3(), epsilon as f64);
+		let (sweep_min, sweep_max) = (start.min(end) - half_extents, start.max(end) + half_extents);
+
+		let mut closest = HullTrace::unobstructed(end);
+		let mut closest_entry: Option<&BvhEntry> = None;
+		let mut start_solid = false;
+		// Nothing else matters once stuck, shared between both closures
+		let all_solid = std::cell::Cell::new(false);
+
+		self.visit(
+			|min, max| !all_solid.get() && sweep_min.cmple(max).all() && sweep_max.cmpge(min).all(),
+			|entry| {
+				if all_solid.get() || !filter(entry.entity) {
+					return;
+				}
+
+				let local_half_extents = entry.to_local.matrix3.abs() * half_extents;
+				let trace = trace_hull(
+					entry.brush(),
+					Some((entry.local_min, entry.local_max)),
+					entry.to_local.transform_point3(start),
+					entry.to_local.transform_point3(end),
+					local_half_extents,
+					epsilon,
+				);
+
+				start_solid |= trace.start_solid;
+				all_solid.set(trace.all_solid);
+				if trace.all_solid || trace.fraction < closest.fraction {
+					closest = trace;
+					closest_entry = Some(entry);
+				}
+			},
+		);
+
+		let plane = closest_entry.zip(closest.plane).map(|(entry, plane)| plane.transformed(entry.to_world));
+
+		BrushTrace {
+			fraction: closest.fraction as f32,
+			end: start.lerp(end, closest.fraction).as_vec3(),
+			plane,
+			brush: closest_entry.map(|entry| (entry.entity, entry.brush_idx)),
+			surface: closest_entry
+				.zip(closest.plane_idx)
+				.and_then(|(entry, plane_idx)| entry.brush().surface(plane_idx)),
+			start_solid,
+			all_solid: closest.all_solid,
+		}
+	}
+
+	/// Returns every brush containing `point`.
+	pub fn brushes_at_point(&self, point: Vec3) -> Vec<&BvhEntry> {
+		let point = point.as_dvec3();
+		let mut found = Vec::new();
+
+		self.visit(
+			|min, max| point.cmpge(min).all() && point.cmple(max).all(),
+			|entry| {
+				if entry.brush().contains_point(entry.to_local.transform_point3(point)) {
+					found.push(entry);
+				}
+			},
+		);
+
+		found
+	}
+
+	/// Calls `visit_entry` on every entry in nodes whose bounds pass `test_bounds`.
+	fn visit<'a>(&'a self, mut test_bounds: impl FnMut(DVec3, DVec3) -> bool, mut visit_entry: impl FnMut(&'a BvhEntry)) {
+		if self.nodes.is_empty() {
+			return;
+		}
+
+		let mut stack = vec![0];
+		while let Some(node_idx) = stack.pop() {
+			let node = &self.nodes[node_idx];
+			let (min, max) = node.bounds();
+			if !test_bounds(min, max) {
+				continue;
+			}
+
+			match node {
+				BvhNode::Leaf { entries, .. } => {
+					for entry in &self.entries[entries.clone()] {
+						visit_entry(entry);
+					}
+				}
+				BvhNode::Branch { children, .. } => stack.extend(children),
+			}
+		}
+	}
+}
+
+/// Returns the distance along the ray where it enters the box, 0 if it starts inside, or `None` if it misses.
+fn ray_aabb_entry(origin: DVec3, direction: DVec3, min: DVec3, max: DVec3) -> Option<f64> {
+	let inverse = direction.recip();
+	let a = (min - origin) * inverse;
+	let b = (max - origin) * inverse;
+	// NaN (from 0 * infinity) is ignored by min and max
+	let enter = a.min(b).max_element().max(0.);
+	let exit = a.max(b).min_element();
+	(enter <= exit).then_some(enter)
+}
+
+/// Returns the ID of the asset an event is for, unless it's only about the asset being unused.
+fn asset_event_id<A: Asset>(event: &AssetEvent<A>) -> Option<AssetId<A>> {
+	match event {
+		AssetEvent::Added { id } | AssetEvent::Modified { id } | AssetEvent::Removed { id } | AssetEvent::LoadedWithDependencies { id } => Some(*id),
+		AssetEvent::Unused { .. } => None,
+	}
+}
+
+impl GeometryPlugin {
+	/// Updates [`BrushBvh`] if it exists, rebuilding it if any brushes have changed, or only moving entries if brush entities have moved.
+	pub fn update_brush_bvh(
+		mut bvh: ResMut<BrushBvh>,
+		changed: Query<Entity, Changed<Brushes>>,
+		moved: Query<Entity, (With<Brushes>, Changed<GlobalTransform>)>,
+		mut removed: RemovedComponents<Brushes>,
+		mut brush_list_events: EventReader<AssetEvent<BrushList>>,
+		#[cfg(feature = "bsp")] mut bsp_brush_events: EventReader<AssetEvent<BspBrushesAsset>>,
+		query: Query<(Entity, &Brushes, &GlobalTransform, &Transform, Option<&BrushOrigin>, Has<LocalBrushes>)>,
+		brush_lists: Res<Assets<BrushList>>,
+		#[cfg(feature = "bsp")] bsp_brushes: Res<Assets<BspBrushesAsset>>,
+	) {
+		let mut changed_assets: HashSet<BvhAssetId> = brush_list_events.read().filter_map(asset_event_id).map(BvhAssetId::List).collect();
+		#[cfg(feature = "bsp")]
+		changed_assets.extend(bsp_brush_events.read().filter_map(asset_event_id).map(BvhAssetId::Bsp));
+
+		let removed = removed.read().collect_vec();
+		let mut reload: HashSet<Entity> = changed.iter().collect();
+		// Entities using changed assets are reloaded, including ones that were waiting for them to load
+		if !changed_assets.is_empty() {
+			reload.extend(query.iter().filter_map(|(entity, brushes, ..)| {
+				let id = match brushes {
+					Brushes::Owned(_) => return None,
+					Brushes::Shared(handle) => BvhAssetId::List(handle.id()),
+					#[cfg(feature = "bsp")]
+					Brushes::Bsp(handle) => BvhAssetId::Bsp(handle.id()),
+				};
+				changed_assets.contains(&id).then_some(entity)
+			}));
+		}
+		let moved = moved.iter().filter(|entity| !reload.contains(entity)).collect_vec();
+
+		if removed.is_empty() && reload.is_empty() && moved.is_empty() {
+			return;
+		}
+		let bvh = &mut *bvh;
+
+		fn to_world(brushes: &Brushes, global_transform: &GlobalTransform, transform: &Transform, origin: Option<&BrushOrigin>, local: bool) -> DAffine3 {
+			BrushBvh::to_world(global_transform, brushes.local_offset(BrushOrigin::or_transform(origin, transform), local))
+		}
+
+		let mut rebuild = false;
+		bvh.assets.retain(|id, _| !changed_assets.contains(id));
+
+		for entity in removed {
+			rebuild |= bvh.entities.remove(&entity).is_some();
+		}
+
+		for entity in reload {
+			let Ok((_, brushes, global_transform, transform, origin, local)) = query.get(entity) else { continue };
+			bvh.load_entity(
+				entity,
+				brushes,
+				to_world(brushes, global_transform, transform, origin, local),
+				&brush_lists,
+				#[cfg(feature = "bsp")]
+				&bsp_brushes,
+			);
+			rebuild = true;
+		}
+
+		for entity in &moved {
+			let (Some((_, entity_to_world)), Ok((_, brushes, global_transform, transform, origin, local))) =
+				(bvh.entities.get_mut(entity), query.get(*entity))
+			else {
+				continue;
+			};
+			*entity_to_world = to_world(brushes, global_transform, transform, origin, local);
+		}
+
+		if rebuild {
+			bvh.rebuild();
+		} else if !moved.is_empty() {
+			bvh.refit();
+		}
+	}
+}
+
+#[test]
+fn brush_bvh_queries() {
+	let cube = |center: DVec3| Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane {
+					normal,
+					distance: -1. - normal.dot(center),
+				},
+				texture: default(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	};
+
+	let mut world = World::new();
+	let near = world.spawn(()).id();
+	let far = world.spawn(()).id();
+	let brush_lists = Assets::<BrushList>::default();
+	let near_brushes = Brushes::Owned(BrushList(vec![cube(DVec3::ZERO)]));
+	// Brushes are relative to the entity's transform
+	let far_brushes = Brushes::Owned(BrushList((0..10).map(|i| cube(dvec3(0., 0., i as f64 * 3.))).collect()));
+
+	let bvh = BrushBvh::from_entities(
+		[
+			(near, &near_brushes, &GlobalTransform::from_translation(vec3(5., 0., 0.)), Vec3::ZERO),
+			(far, &far_brushes, &GlobalTransform::from_translation(vec3(10., 0., 0.)), Vec3::ZERO),
+		],
+		&brush_lists,
+		#[cfg(feature = "bsp")]
+		&default(),
+	);
+	assert_eq!(bvh.entries().len(), 11);
+
+	let hit = bvh.cast_ray(Vec3::ZERO, Vec3::X, 100., |_| true).unwrap();
+	assert_eq!(hit.entity, near);
+	assert_eq!(hit.distance, 4.);
+	assert_eq!(hit.normal, Vec3::NEG_X);
+	assert!(hit.surface.is_some());
+
+	let hit = bvh.cast_ray(Vec3::ZERO, Vec3::X, 100., |entity| entity != near).unwrap();
+	assert_eq!(hit.entity, far);
+	assert_eq!(hit.distance, 9.);
+
+	let hit = bvh.cast_segment(vec3(10., 0., 30.), vec3(10., 0., 20.), |_| true).unwrap();
+	assert_eq!((hit.entity, hit.brush_idx), (far, 9));
+
+	assert!(bvh.cast_ray(Vec3::ZERO, Vec3::NEG_X, 100., |_| true).is_none());
+	assert_eq!(bvh.brushes_at_point(vec3(10., 0.5, 6.)).len(), 1);
+	assert!(bvh.brushes_at_point(vec3(10., 0.5, 4.5)).is_empty());
+
+	let trace = bvh.trace(Vec3::ZERO, vec3(10., 0., 0.), Vec3::splat(0.5), 0.25, |_| true);
+	assert!(trace.hit());
+	assert!(trace.end.abs_diff_eq(vec3(3.25, 0., 0.), 1e-5));
+	assert_eq!(trace.brush, Some((near, 0)));
+	assert_eq!(
+		trace.plane,
+		Some(BrushPlane {
+			normal: DVec3::NEG_X,
+			distance: 4.
+		})
+	);
+	assert!(trace.surface.is_some());
+
+	let trace = bvh.trace(vec3(5., 0., 0.), vec3(5., 0.2, 0.), Vec3::splat(0.5), 0.25, |_| true);
+	assert!(trace.all_solid);
+	assert_eq!(trace.end, vec3(5., 0., 0.));
+}
+
+#[test]
+fn brush_bvh_map_space_entities() {
+	let mut world = World::new();
+	let door = world.spawn(()).id();
+	let brush_lists = Assets::<BrushList>::default();
+	// A cube around (20, 0, 0) in map space, on an entity with its origin there
+	let brushes = Brushes::Owned(BrushList(vec![Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane {
+					normal,
+					distance: -1. - normal.x * 20.,
+				},
+				texture: default(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	}]));
+	let origin = BrushOrigin(vec3(20., 0., 0.));
+
+	for translation in [vec3(20., 0., 0.), vec3(30., 0., 0.)] {
+		let local_offset = brushes.local_offset(BrushOrigin::or_transform(Some(&origin), &Transform::from_translation(translation)), false);
+		let bvh = BrushBvh::from_entities(
+			[(door, &brushes, &GlobalTransform::from_translation(translation), local_offset)],
+			&brush_lists,
+			#[cfg(feature = "bsp")]
+			&default(),
+		);
+
+		let entry = &bvh.entries()[0];
+		assert!(entry.min.abs_diff_eq(translation.as_dvec3() - 1., 1e-9));
+		assert!(entry.max.abs_diff_eq(translation.as_dvec3() + 1., 1e-9));
+
+		let hit = bvh.cast_ray(Vec3::ZERO, Vec3::X, 100., |_| true).unwrap();
+		assert!((hit.distance - (translation.x - 1.)).abs() < 1e-4);
+		assert_eq!(bvh.brushes_at_point(translation).len(), 1);
+	}
+}
+
+#[test]
+fn brush_bvh_traces_map_space_entities() {
+	let mut world = World::new();
+	let door = world.spawn(()).id();
+	let brush_lists = Assets::<BrushList>::default();
+	// A cube around (20, 0, 0) in map space, like a func_door with its origin there
+	let brushes = Brushes::Owned(BrushList(vec![Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane {
+					normal,
+					distance: -1. - normal.x * 20.,
+				},
+				texture: default(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	}]));
+	let origin = BrushOrigin(vec3(20., 0., 0.));
+
+	// The door has opened by 10 units
+	let transform = Transform::from_xyz(30., 0., 0.);
+	let local_offset = brushes.local_offset(BrushOrigin::or_transform(Some(&origin), &transform), false);
+	let bvh = BrushBvh::from_entities(
+		[(door, &brushes, &GlobalTransform::from(transform), local_offset)],
+		&brush_lists,
+		#[cfg(feature = "bsp")]
+		&default(),
+	);
+
+	let trace = bvh.trace(Vec3::ZERO, vec3(40., 0., 0.), Vec3::splat(0.5), 0.25, |_| true);
+	assert!(trace.hit());
+	assert!(!trace.start_solid);
+	assert!(trace.end.abs_diff_eq(vec3(28.25, 0., 0.), 1e-4));
+	assert_eq!(trace.brush, Some((door, 0)));
+	let plane = trace.plane.unwrap();
+	assert!(plane.normal.abs_diff_eq(DVec3::NEG_X, 1e-9));
+	assert!((plane.distance - 29.).abs() < 1e-9);
+
+	let hit = bvh.cast_aabb(Vec3::ZERO, Vec3::splat(0.5), Vec3::X, 100., |_| true).unwrap();
+	assert!((hit.distance - 28.5).abs() < 1e-4);
+
+	// Nothing is left where the door was, or where transforming its brushes twice would put it
+	assert!(!bvh.trace(vec3(15., 0., 0.), vec3(25., 0., 0.), Vec3::splat(0.5), 0.25, |_| true).hit());
+	assert!(!bvh.trace(vec3(45., 0., 0.), vec3(55., 0., 0.), Vec3::splat(0.5), 0.25, |_| true).hit());
+}
+
+#[test]
+fn brush_bvh_updates() {
+	let cube = Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane { normal, distance: -1. },
+				texture: default(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	};
+
+	let mut app = App::new();
+	app.add_plugins((AssetPlugin::default(), TaskPoolPlugin::default()))
+		.init_asset::<BrushList>()
+		.init_resource::<BrushBvh>();
+	#[cfg(feature = "bsp")]
+	app.init_asset::<BspBrushesAsset>();
+	let world = app.world_mut();
+	let update = world.register_system(GeometryPlugin::update_brush_bvh);
+
+	let handle = world.resource_mut::<Assets<BrushList>>().add(BrushList(vec![cube]));
+	let mut spawn = |translation: Vec3| {
+		let transform = Transform::from_translation(translation);
+		world
+			.spawn((Brushes::Shared(handle.clone()), LocalBrushes, transform, GlobalTransform::from(transform)))
+			.id()
+	};
+	let (a, b) = (spawn(vec3(5., 0., 0.)), spawn(vec3(10., 0., 0.)));
+	world.run_system(update).unwrap();
+
+	let bvh = world.resource::<BrushBvh>();
+	assert_eq!(bvh.entries().len(), 2);
+	// Entities with the same asset share its brushes
+	assert!(Arc::ptr_eq(&bvh.entries()[0].brushes, &bvh.entries()[1].brushes));
+	assert_eq!(bvh.cast_ray(Vec3::ZERO, Vec3::X, 100., |_| true).unwrap().entity, a);
+
+	// Moving an entity only moves its entry, rather than rebuilding the hierarchy
+	let entries_ptr = bvh.entries().as_ptr();
+	let transform = Transform::from_xyz(20., 0., 0.);
+	world.entity_mut(a).insert((transform, GlobalTransform::from(transform)));
+	world.run_system(update).unwrap();
+
+	let bvh = world.resource::<BrushBvh>();
+	assert_eq!(bvh.entries().as_ptr(), entries_ptr);
+	let hit = bvh.cast_ray(Vec3::ZERO, Vec3::X, 100., |_| true).unwrap();
+	assert_eq!((hit.entity, hit.distance), (b, 9.));
+	let hit = bvh.cast_ray(vec3(15., 0., 0.), Vec3::X, 100., |_| true).unwrap();
+	assert_eq!((hit.entity, hit.distance), (a, 4.));
+	assert!(bvh.brushes_at_point(vec3(5., 0., 0.)).is_empty());
+
+	world.entity_mut(b).remove::<Brushes>();
+	world.run_system(update).unwrap();
+	let bvh = world.resource::<BrushBvh>();
+	assert_eq!(bvh.entries().len(), 1);
+	assert_eq!(bvh.entries()[0].entity, a);
+}
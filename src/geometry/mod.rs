@@ -0,0 +1,555 @@
+flat! {
+	bvh;
+	regeneration;
+}
+
+use bevy::ecs::entity::{EntityMapper, MapEntities};
+use bevy::transform::TransformSystem;
+use bevy_mesh::{Indices, VertexAttributeValues};
+use brush::{Brush, BrushSurfacePolygon, SurfaceAttributes, fix_t_junctions, polygonize_without_hidden_faces};
+#[cfg(feature = "bsp")]
+use bsp::BspBrushesAsset;
+#[cfg(all(feature = "client", feature = "bsp"))]
+use bsp::lighting::{AnimatedLighting, AnimatedLightingHandle};
+use qmap::QuakeMapEntity;
+
+use crate::{class::ErasedQuakeClass, *};
+
+/// A good starting threshold in radians for interpolating similar normals, creating smoother curved surfaces.
+pub const DEFAULT_NORMAL_SMOOTH_THRESHOLD: f32 = std::f32::consts::FRAC_PI_4;
+
+pub struct GeometryPlugin;
+impl Plugin for GeometryPlugin {
+	fn build(&self, app: &mut App) {
+		#[rustfmt::skip]
+		app
+			.init_asset::<BrushList>()
+			.register_type::<Brushes>()
+			.register_type::<AutoRemoveTexturesOverride>()
+			.register_type::<ScaleOverride>()
+			.register_type::<LocalBrushes>()
+			.register_type::<BrushOrigin>()
+			.register_type::<MapGeometry>()
+			.register_type::<StaticBatch>()
+			.register_type::<SurfaceAttributes>()
+			.register_type::<RegenerateBrushMeshes>()
+			.init_resource::<BrushTextureCache>()
+
+			.add_systems(PostUpdate, (
+				Self::mark_changed_owned_brushes,
+				Self::regenerate_owned_brush_meshes,
+			).chain())
+			.add_systems(PostUpdate, Self::update_brush_bvh.run_if(resource_exists::<BrushBvh>).after(TransformSystem::TransformPropagate))
+		;
+	}
+}
+
+/// Contains the brushes that a solid entity is made of.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+#[require(Transform)]
+pub enum Brushes {
+	/// Brushes are stored directly in the component itself, useful if you need to dynamically edit brushes.
+	///
+	/// Meshes are regenerated whenever this changes, see [`GeometryPlugin::regenerate_owned_brush_meshes`].
+	Owned(BrushList),
+	/// Reads an asset instead for completely static geometry.
+	Shared(Handle<BrushList>),
+	/// Used with the `BRUSHLIST` BSPX lump. Collision only.
+	#[cfg(feature = "bsp")]
+	Bsp(Handle<BspBrushesAsset>),
+}
+impl Brushes {
+	/// Returns the translation from this entity's brushes into its local space, given the origin they were authored at (see [`BrushOrigin::or_transform`]) and whether it has [`LocalBrushes`].
+	///
+	/// Brushes loaded from `.map` files are in map space, so this is `-origin` for them, while BSP brushes are already local.
+	pub fn local_offset(&self, origin: Vec3, local: bool) -> Vec3 {
+		match self {
+			#[cfg(feature = "bsp")]
+			Self::Bsp(_) => Vec3::ZERO,
+			_ if local => Vec3::ZERO,
+			_ => -origin,
+		}
+	}
+}
+
+/// Marks an entity's [`Brushes`] as relative to its [`Transform`] instead of to the map it was loaded from.
+///
+/// Inserted on entities sharing a [`BrushList`] through [`TrenchBroomConfig::share_identical_brush_entities`], as copies in different places can't share map-space brushes.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Default)]
+pub struct LocalBrushes;
+
+/// The origin a brush entity was spawned at, which its map-space [`Brushes`] are relative to. Inserted when loading `.map` files.
+///
+/// Meshes, colliders and [`BrushBvh`] entries are offset by this rather than the entity's current [`Transform`], so that they stay the same when the entity moves.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Component, Default)]
+pub struct BrushOrigin(pub Vec3);
+impl BrushOrigin {
+	/// Returns the origin in `origin`, or `transform`'s translation for entities spawned without a [`BrushOrigin`].
+	pub fn or_transform(origin: Option<&Self>, transform: &Transform) -> Vec3 {
+		origin.map_or(transform.translation, |origin| origin.0)
+	}
+}
+
+/// The [`auto_remove_textures`](TrenchBroomConfig::auto_remove_textures) a brush entity's map was loaded with,
+/// inserted when overridden by [`MapLoadSettings::auto_remove_textures`](crate::config::MapLoadSettings::auto_remove_textures).
+///
+/// Colliders are created after loading, where the global [`TrenchBroomConfig`] would otherwise be used.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Default)]
+pub struct AutoRemoveTexturesOverride(pub HashSet<String>);
+impl AutoRemoveTexturesOverride {
+	/// Returns `config` with this override applied.
+	pub fn apply(&self, config: &TrenchBroomConfig) -> TrenchBroomConfig {
+		TrenchBroomConfig {
+			auto_remove_textures: self.0.clone(),
+			..config.clone()
+		}
+	}
+}
+
+/// The [`scale`](TrenchBroomConfig::scale) a brush entity's map was loaded with,
+/// inserted when overridden by [`MapLoadSettings::scale`](crate::config::MapLoadSettings::scale).
+///
+/// Meshes regenerated after loading use the scale for their texture coordinates, where the global [`TrenchBroomConfig`] would otherwise be used.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Component, Default)]
+pub struct ScaleOverride(pub f32);
+impl ScaleOverride {
+	/// Returns `config` with this override applied.
+	pub fn apply(&self, config: &TrenchBroomConfig) -> TrenchBroomConfig {
+		TrenchBroomConfig {
+			scale: self.0,
+			..config.clone()
+		}
+	}
+}
+
+#[derive(Asset, Reflect, Debug, Clone)]
+pub struct BrushList(pub Vec<Brush>);
+impl std::ops::Deref for BrushList {
+	type Target = [Brush];
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+#[derive(Reflect, Debug, Clone, PartialEq, Eq)]
+pub struct MapGeometryTexture {
+	pub name: String,
+	pub material: Handle<GenericMaterial>,
+	/// Quake 2 face attributes shared by all faces in this mesh. Always defaulted for BSPs.
+	pub attributes: SurfaceAttributes,
+	#[cfg(all(feature = "client", feature = "bsp"))]
+	pub lightmap: Option<Handle<AnimatedLighting>>,
+	/// If the texture should be full-bright
+	#[cfg(feature = "bsp")]
+	pub flags: BspTexFlags,
+}
+
+/// Marker component that marks meshes as level geometry produced by brushes.
+#[derive(Component, Reflect, Clone)]
+#[reflect(Component)]
+pub struct MapGeometry;
+
+/// A mesh containing the geometry of multiple entities sharing a material, see [`GeometryProvider::static_batch`].
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Debug)]
+#[component(map_entities)]
+pub struct StaticBatch {
+	/// The entities this batch was made from, in the order their indices appear in the mesh.
+	pub sources: Vec<StaticBatchSource>,
+}
+impl StaticBatch {
+	/// Returns the entity that the triangle at `triangle_idx` came from, such as the `triangle_index` of a mesh picking hit.
+	pub fn source_of_triangle(&self, triangle_idx: usize) -> Option<Entity> {
+		let index = (triangle_idx * 3) as u32;
+		self.sources
+			.iter()
+			.find(|source| source.indices.contains(&index))
+			.map(|source| source.entity)
+	}
+}
+impl MapEntities for StaticBatch {
+	fn map_entities<E: EntityMapper>(&mut self, entity_mapper: &mut E) {
+		for source in &mut self.sources {
+			source.entity = entity_mapper.get_mapped(source.entity);
+		}
+	}
+}
+
+/// The part of a [`StaticBatch`] mesh that came from a specific entity.
+#[derive(Reflect, Debug, Clone, PartialEq, Eq)]
+pub struct StaticBatchSource {
+	pub entity: Entity,
+	/// The range of the batch mesh's indices containing this entity's triangles.
+	pub indices: std::ops::Range<u32>,
+}
+
+pub struct GeometryProviderMeshView<'l> {
+	pub entity: Entity,
+	pub mesh: &'l mut Mesh,
+	pub texture: &'l mut MapGeometryTexture,
+}
+
+pub struct GeometryProviderView<'w, 'l> {
+	pub world: &'w mut World,
+	pub entity: Entity,
+	pub tb_server: &'w TrenchBroomServer,
+	pub map_entity: &'w QuakeMapEntity,
+	pub map_entity_idx: usize,
+	pub class: &'w ErasedQuakeClass,
+
+	pub meshes: Vec<GeometryProviderMeshView<'l>>,
+}
+
+pub type GeometryProviderFn = dyn Fn(&mut GeometryProviderView) + Send + Sync;
+pub type GeometryProviderFnOnce = dyn FnOnce(&mut GeometryProviderView) + Send + Sync;
+
+#[derive(Default)]
+pub struct GeometryProvider {
+	pub providers: Vec<Box<GeometryProviderFnOnce>>,
+	/// See [`Self::static_batch`].
+	pub static_batch: bool,
+	/// See [`Self::chunked`].
+	pub chunk_size: Option<f32>,
+	/// See [`Self::remove_hidden_faces`].
+	pub remove_hidden_faces: bool,
+	/// See [`Self::fix_t_junctions`].
+	pub fix_t_junctions: bool,
+}
+
+impl GeometryProvider {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a function to the settings' spawner stack.
+	pub fn push(mut self, provider: impl FnOnce(&mut GeometryProviderView) + Send + Sync + 'static) -> Self {
+		self.providers.push(Box::new(provider));
+		self
+	}
+
+	/// When loading `.map` files, merges the meshes of this entity with those of other statically batched entities using the same material,
+	/// to reduce draw calls in maps with lots of brush entities.
+	///
+	/// Batched meshes are spawned as separate entities with a [`StaticBatch`] component to map triangles back to the entities they came from,
+	/// optionally split into cells via [`TrenchBroomConfig::static_batch_cell_size`]. This entity keeps its [`Brushes`], but won't have any mesh entities of its own,
+	/// so anything geometry providers insert onto mesh entities (such as [`trimesh_collider`](Self::trimesh_collider)) is discarded.
+	/// Meshes are moved into world space with this entity's [`Transform`] at load time.
+	///
+	/// Only use this for entities that never move, batched entities also don't take part in [`TrenchBroomConfig::share_identical_brush_entities`].
+	pub fn static_batch(mut self) -> Self {
+		self.static_batch = true;
+		self
+	}
+
+	/// Splits each mesh of this entity into a grid of cubic cells `size` Bevy units wide, each with its own mesh entity.
+	///
+	/// Without this, large entities like worldspawn have one mesh per texture covering the whole level, which frustum culling can't do anything with.
+	/// Triangles aren't cut, so cells overlap slightly where triangles cross cell borders.
+	pub fn chunked(mut self, size: f32) -> Self {
+		self.chunk_size = Some(size);
+		self
+	}
+
+	/// When loading `.map` files, clips away the parts of faces that are inside or flush against other brushes of this entity before meshes are generated,
+	/// reducing triangle count and z-fighting. See [`polygonize_without_hidden_faces`](brush::polygonize_without_hidden_faces).
+	///
+	/// BSPs have already had this done by the compiler.
+	pub fn remove_hidden_faces(mut self) -> Self {
+		self.remove_hidden_faces = true;
+		self
+	}
+
+	/// When loading `.map` files, inserts vertices where the corners of faces lie on the edges of other faces of this entity, removing the cracks these T-junctions can cause.
+	/// See [`fix_t_junctions`](brush::fix_t_junctions).
+	///
+	/// This adds vertices, so it's best combined with [`remove_hidden_faces`](Self::remove_hidden_faces) to avoid fixing junctions on faces that can't be seen.
+	pub fn fix_t_junctions(mut self) -> Self {
+		self.fix_t_junctions = true;
+		self
+	}
+
+	/// Polygonizes `brushes` for mesh generation, applying [`remove_hidden_faces`](Self::remove_hidden_faces) and [`fix_t_junctions`](Self::fix_t_junctions) if enabled.
+	pub fn polygonize<'b>(&self, brushes: &'b [Brush], config: &TrenchBroomConfig) -> Vec<BrushSurfacePolygon<'b>> {
+		let mut polygons = match self.remove_hidden_faces {
+			true => polygonize_without_hidden_faces(brushes, config),
+			false => brushes.iter().flat_map(Brush::polygonize).collect(),
+		};
+		if self.fix_t_junctions {
+			fix_t_junctions(&mut polygons);
+		}
+		polygons
+	}
+
+	/// Any intersecting vertices where the angle between their normals in radians is less than [`DEFAULT_NORMAL_SMOOTH_THRESHOLD`] will have their normals interpolated, making curved surfaces look smooth.
+	///
+	/// Shorthand for `self.smooth_by_angle(DEFAULT_NORMAL_SMOOTH_THRESHOLD)` to reduce syntactic noise.
+	pub fn smooth_by_default_angle(self) -> Self {
+		self.smooth_by_angle(DEFAULT_NORMAL_SMOOTH_THRESHOLD)
+	}
+
+	/// Any intersecting vertices where the angle between their normals in radians is less than `normal_smooth_threshold` will have their normals interpolated, making curved surfaces look smooth.
+	/// [`DEFAULT_NORMAL_SMOOTH_THRESHOLD`] is a good starting value for this, shorthanded by [`Self::smooth_by_default_angle`].
+	///
+	/// if `normal_smooth_threshold` is <= 0, nothing will happen.
+	pub fn smooth_by_angle(self, normal_smooth_threshold: f32) -> Self {
+		self.push(move |view| {
+			if normal_smooth_threshold <= 0. {
+				return; // The user doesn't want to smooth after all!
+			}
+
+			#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+			struct Vec3Ord([FloatOrd; 3]);
+
+			// It's either a map or a doubly-connected edge list, the prior seems to work well enough.
+			let mut vertex_map: HashMap<Vec3Ord, Vec<&mut [f32; 3]>> = default();
+
+			let ent_index = view.map_entity_idx; // Borrow checker
+			// We go through all the meshes and add all their normals into vertex_map
+			for mesh_view in &mut view.meshes {
+				// SAFETY: Getting ATTRIBUTE_POSITION and ATTRIBUTE_NORMAL gives us 2 different attributes, but the borrow checker doesn't know that!
+				let mesh2 = unsafe { &mut *std::ptr::from_mut(&mut mesh_view.mesh) };
+
+				let Some(positions) = mesh_view
+					.mesh
+					.attribute(Mesh::ATTRIBUTE_POSITION)
+					.and_then(VertexAttributeValues::as_float3)
+				else {
+					error!(
+						"[entity {} (map entity {:?})] Tried to smooth by angle, but the ATTRIBUTE_POSITION doesn't exist on mesh!",
+						mesh_view.entity, ent_index
+					);
+					return;
+				};
+				let positions_len = positions.len();
+
+				let Some(normals) = mesh2.attribute_mut(Mesh::ATTRIBUTE_NORMAL).and_then(|values| match values {
+					VertexAttributeValues::Float32x3(v) => Some(v),
+					_ => None,
+				}) else {
+					error!(
+						"[entity {} (map entity {:?})] Tried to smooth by angle, but the ATTRIBUTE_NORMAL doesn't exist on mesh!",
+						mesh_view.entity, ent_index
+					);
+					return;
+				};
+				let normals_len = normals.len();
+
+				if normals_len != positions_len {
+					error!(
+						"[entity {} (map entity {:?})] Tried to smooth by angle, but ATTRIBUTE_NORMAL len doesn't match ATTRIBUTE_POSITION len! ({} and {})",
+						mesh_view.entity, ent_index, normals_len, positions_len
+					);
+					return;
+				}
+
+				for (i, normal) in normals.iter_mut().enumerate() {
+					// Let's make this lower precision, just in case
+					let position = Vec3Ord(positions[i].map(|v| FloatOrd((v * 10000.).round() / 10000.)));
+
+					vertex_map.entry(position).or_default().push(normal);
+				}
+			}
+
+			for (_position, mut normals) in vertex_map {
+				use disjoint_sets::*;
+
+				if normals.len() <= 1 {
+					// There are no duplicates
+					continue;
+				}
+
+				// Group normals to be smoothed
+				let mut uf = UnionFind::new(normals.len());
+
+				for ((a_i, a), (b_i, b)) in normals.iter().map(|v| Vec3::from(**v)).enumerate().tuple_combinations() {
+					if a.angle_between(b) < normal_smooth_threshold {
+						uf.union(a_i, b_i);
+					}
+				}
+
+				// Put the groups into an easily iterable structure, then average the normals in each group
+				let mut groups: HashMap<usize, Vec<usize>> = HashMap::default();
+				for i in 0..normals.len() {
+					let root = uf.find(i);
+					groups.entry(root).or_default().push(i);
+				}
+
+				for (_, group) in groups {
+					let new_normal = group.iter().map(|idx| Vec3::from(*normals[*idx])).sum::<Vec3>() / normals.len() as f32;
+
+					for idx in group {
+						*normals[idx] = new_normal.to_array();
+					}
+				}
+			}
+		})
+	}
+
+	/// Inserts lightmaps if available.
+	#[cfg(all(feature = "client", feature = "bsp"))]
+	pub fn with_lightmaps(self) -> Self {
+		self.push(|view| {
+			for mesh_view in &view.meshes {
+				let Some(animated_lighting_handle) = &mesh_view.texture.lightmap else { continue };
+
+				view.world
+					.entity_mut(mesh_view.entity)
+					.insert(AnimatedLightingHandle(animated_lighting_handle.clone()));
+			}
+		})
+	}
+	#[cfg(all(not(feature = "client"), feature = "bsp"))]
+	pub fn with_lightmaps(self) -> Self {
+		self
+	}
+
+	/// Inserts a bundle onto the entity.
+	///
+	/// This is a convenience function, you should generally use required components instead of this if at all possible.
+	pub fn with(self, bundle: impl Bundle) -> Self {
+		self.push(move |view| {
+			view.world.entity_mut(view.entity).insert(bundle);
+		})
+	}
+	/// Inserts a bundle onto all mesh entities.
+	pub fn meshes_with(self, bundle: impl Bundle + Clone) -> Self {
+		self.push(move |view| {
+			for mesh_view in &view.meshes {
+				view.world.entity_mut(mesh_view.entity).insert(bundle.clone());
+			}
+		})
+	}
+
+	/// Inserts trimesh colliders on each mesh of this entity. This means that brushes will be hollow. Not recommended to use on physics objects.
+	#[cfg(any(feature = "rapier", feature = "avian"))]
+	pub fn trimesh_collider(self) -> Self {
+		self.meshes_with(physics::TrimeshCollision)
+	}
+
+	/// Inserts a compound collider of every brush in this entity into said entity. Brushes will be fully solid.
+	#[cfg(any(feature = "rapier", feature = "avian"))]
+	pub fn convex_collider(self) -> Self {
+		self.with(physics::ConvexCollision)
+	}
+
+	/// Inserts a compound sensor collider of every brush in this entity into said entity, for triggers that detect intersections without blocking anything.
+	#[cfg(any(feature = "rapier", feature = "avian"))]
+	pub fn sensor_collider(self) -> Self {
+		self.with(physics::TriggerCollision)
+	}
+}
+
+/// Splits `mesh` into a mesh for each cubic cell of size `cell_size` that the centers of its triangles fall into, ordered by cell.
+///
+/// If `mesh` isn't indexed, or has vertex attributes that aren't made of 32-bit floats, it is returned as-is.
+pub fn split_mesh_into_cells(mesh: Mesh, cell_size: f32) -> Vec<Mesh> {
+	if cell_size <= 0. {
+		return vec![mesh];
+	}
+
+	match split_mesh_by(&mesh, |triangle| {
+		(triangle.into_iter().sum::<Vec3>() / 3. / cell_size).floor().as_ivec3().to_array()
+	}) {
+		Some(cells) if cells.len() > 1 => cells.into_iter().map(|(_, cell_mesh)| cell_mesh).collect(),
+		_ => vec![mesh],
+	}
+}
+
+/// Splits `mesh` into a mesh for each distinct key that `triangle_key` returns for the positions of its triangles, ordered by key.
+///
+/// Returns [`None`] if `mesh` isn't indexed, or has vertex attributes that aren't made of 32-bit floats.
+pub fn split_mesh_by<K: Ord + std::hash::Hash>(mesh: &Mesh, mut triangle_key: impl FnMut([Vec3; 3]) -> K) -> Option<Vec<(K, Mesh)>> {
+	let (Some(indices), Some(positions)) = (
+		mesh.indices(),
+		mesh.attribute(Mesh::ATTRIBUTE_POSITION).and_then(VertexAttributeValues::as_float3),
+	) else {
+		return None;
+	};
+	let float_attributes = mesh.attributes().all(|(_, values)| {
+		matches!(
+			values,
+			VertexAttributeValues::Float32x2(_) | VertexAttributeValues::Float32x3(_) | VertexAttributeValues::Float32x4(_)
+		)
+	});
+	if !float_attributes {
+		return None;
+	}
+
+	// The old indices of each group's triangles
+	let mut groups: HashMap<K, Vec<usize>> = default();
+	for triangle in &indices.iter().chunks(3) {
+		let triangle = triangle.collect_vec();
+		if triangle.len() != 3 {
+			break;
+		}
+		let key = triangle_key([0, 1, 2].map(|i| Vec3::from(positions[triangle[i]])));
+		groups.entry(key).or_default().extend(triangle);
+	}
+
+	Some(
+		groups
+			.into_iter()
+			.sorted_by(|(a, _), (b, _)| a.cmp(b))
+			.map(|(key, old_indices)| {
+				// Maps old vertex indices to new ones
+				let mut remap: HashMap<usize, u32> = default();
+				let mut vertices = Vec::new();
+				let new_indices = old_indices
+					.into_iter()
+					.map(|old_index| {
+						*remap.entry(old_index).or_insert_with(|| {
+							vertices.push(old_index);
+							vertices.len() as u32 - 1
+						})
+					})
+					.collect_vec();
+
+				let mut group_mesh = Mesh::new(mesh.primitive_topology(), mesh.asset_usages);
+				for (attribute, values) in mesh.attributes() {
+					let values = match values {
+						VertexAttributeValues::Float32x2(values) => VertexAttributeValues::Float32x2(vertices.iter().map(|&i| values[i]).collect()),
+						VertexAttributeValues::Float32x3(values) => VertexAttributeValues::Float32x3(vertices.iter().map(|&i| values[i]).collect()),
+						VertexAttributeValues::Float32x4(values) => VertexAttributeValues::Float32x4(vertices.iter().map(|&i| values[i]).collect()),
+						_ => unreachable!("checked above"),
+					};
+					group_mesh.insert_attribute(attribute.clone(), values);
+				}
+				group_mesh.insert_indices(Indices::U32(new_indices));
+				(key, group_mesh)
+			})
+			.collect(),
+	)
+}
+
+#[test]
+fn mesh_cell_splitting() {
+	let mut mesh = Mesh::new(bevy_mesh::PrimitiveTopology::TriangleList, default());
+	// Two quads, one on each side of x = 1
+	mesh.insert_attribute(
+		Mesh::ATTRIBUTE_POSITION,
+		vec![
+			[0., 0., 0.],
+			[0.5, 0., 0.],
+			[0.5, 0.5, 0.],
+			[0., 0.5, 0.],
+			[1.5, 0., 0.],
+			[2., 0., 0.],
+			[2., 0.5, 0.],
+			[1.5, 0.5, 0.],
+		],
+	);
+	mesh.insert_indices(Indices::U32(vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]));
+
+	let cells = split_mesh_into_cells(mesh, 1.);
+	assert_eq!(cells.len(), 2);
+	for cell in cells {
+		assert_eq!(cell.count_vertices(), 4);
+		assert_eq!(cell.indices().unwrap().len(), 6);
+	}
+}
@@ -0,0 +1,424 @@
+//! Regeneration of [`Brushes::Owned`] meshes at runtime.
+
+use bevy::asset::LoadState;
+use brush::generate_mesh_from_brush_polygons;
+use class::{QuakeClassType, ReflectQuakeClass};
+
+use super::*;
+
+/// Marks an entity to have its meshes regenerated from its [`Brushes::Owned`]. Inserted automatically when the component changes.
+///
+/// Stays on the entity until the textures it needs have been loaded.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Default)]
+pub struct RegenerateBrushMeshes;
+
+/// Materials and texture sizes used when regenerating brush meshes at runtime.
+///
+/// When loading maps, [`TrenchBroomConfig::load_loose_texture`] is used, but that requires a [`LoadContext`](bevy::asset::LoadContext) which isn't available at runtime.
+/// Instead, materials are taken from the entity's existing mesh entities, then from this cache, and if neither has it, the texture's image is loaded as the material.
+///
+/// If you want textures that an entity didn't previously have to use other materials (such as `.toml` files), insert them into [`materials`](Self::materials) ahead of time.
+#[derive(Resource, Debug, Default)]
+pub struct BrushTextureCache {
+	pub materials: HashMap<String, Handle<GenericMaterial>>,
+	pub sizes: HashMap<String, UVec2>,
+	/// Images being loaded to find texture sizes, along with the index into [`TrenchBroomConfig::texture_extensions`] being tried.
+	loading: HashMap<String, (usize, Handle<Image>)>,
+}
+impl BrushTextureCache {
+	/// Returns the material and size of `texture`, or `None` if it's still loading.
+	fn poll_texture(
+		&mut self,
+		texture: &str,
+		config: &TrenchBroomConfig,
+		asset_server: &AssetServer,
+		images: &Assets<Image>,
+	) -> Option<(Handle<GenericMaterial>, UVec2)> {
+		if let (Some(material), Some(size)) = (self.materials.get(texture), self.sizes.get(texture)) {
+			return Some((material.clone(), *size));
+		}
+
+		let (mut ext_idx, mut handle) = self.loading.remove(texture).unwrap_or_default();
+
+		loop {
+			let Some(ext) = config.texture_extensions.get(ext_idx) else {
+				error!(
+					"Failed to get size for texture {texture:?} looking for the following extensions: {:?}",
+					config.texture_extensions
+				);
+				self.sizes.insert(texture.s(), UVec2::splat(1));
+				let material = self.materials.entry(texture.s()).or_default().clone();
+				return Some((material, UVec2::splat(1)));
+			};
+			let path = config.material_root.join(format!("{texture}.{ext}"));
+
+			if handle == Handle::default() {
+				handle = asset_server.load(path.clone());
+			}
+
+			match asset_server.load_state(&handle) {
+				LoadState::Loaded => {
+					let size = images.get(&handle).map(Image::size).unwrap_or(UVec2::splat(1));
+					self.sizes.insert(texture.s(), size);
+					let material = self.materials.entry(texture.s()).or_insert_with(|| asset_server.load(path)).clone();
+					return Some((material, size));
+				}
+				LoadState::Failed(_) => {
+					ext_idx += 1;
+					handle = Handle::default();
+				}
+				_ => {
+					self.loading.insert(texture.s(), (ext_idx, handle));
+					return None;
+				}
+			}
+		}
+	}
+}
+
+impl GeometryPlugin {
+	/// Inserts [`RegenerateBrushMeshes`] on entities whose [`Brushes::Owned`] have changed.
+	pub fn mark_changed_owned_brushes(mut commands: Commands, query: Query<(Entity, &Brushes), Changed<Brushes>>) {
+		for (entity, brushes) in &query {
+			if matches!(brushes, Brushes::Owned(_)) {
+				commands.entity(entity).insert(RegenerateBrushMeshes);
+			}
+		}
+	}
+
+	/// Replaces the [`MapGeometry`] children of entities with [`RegenerateBrushMeshes`] with new ones generated from their [`Brushes::Owned`],
+	/// running the geometry provider of their class like map loading does.
+	///
+	/// Because there is no map at runtime, geometry providers are given a [`QuakeMapEntity`] only containing the entity's classname, and a `map_entity_idx` of [`usize::MAX`].
+	/// [`GeometryProvider::static_batch`] is ignored.
+	pub fn regenerate_owned_brush_meshes(world: &mut World) {
+		let pending = world.query_filtered::<Entity, With<RegenerateBrushMeshes>>().iter(world).collect_vec();
+		if pending.is_empty() {
+			return;
+		}
+
+		let tb_server = world.resource::<TrenchBroomServer>().clone();
+		let config = &tb_server.config;
+		let asset_server = world.resource::<AssetServer>().clone();
+		let solid_classes = world
+			.resource::<AppTypeRegistry>()
+			.read()
+			.iter_with_data::<ReflectQuakeClass>()
+			.filter(|(_, class)| class.enabled && class.erased_class.info.ty.is_solid())
+			.map(|(_, class)| class.erased_class)
+			.collect_vec();
+
+		world.resource_scope(|world, mut cache: Mut<BrushTextureCache>| {
+			for entity in pending {
+				let Ok(entity_ref) = world.get_entity(entity) else { continue };
+				let Some(Brushes::Owned(brushes)) = entity_ref.get::<Brushes>().cloned() else {
+					world.entity_mut(entity).remove::<RegenerateBrushMeshes>();
+					continue;
+				};
+				let Some(class) = solid_classes.iter().copied().find(|class| entity_ref.contains_type_id((class.type_id)())) else {
+					error!(
+						"Entity {entity} has owned brushes, but no solid class to get a geometry provider from, so its meshes can't be regenerated"
+					);
+					world.entity_mut(entity).remove::<RegenerateBrushMeshes>();
+					continue;
+				};
+				let QuakeClassType::Solid(geometry_provider) = class.info.ty else { continue };
+				let geometry_provider = geometry_provider();
+
+				// Owned brushes are in map space like when loading, so the meshes are moved into the entity's space the same way,
+				// using the origin it was spawned at rather than where it has moved since.
+				let transform = entity_ref.get::<Transform>().copied().unwrap_or_default();
+				let origin = BrushOrigin::or_transform(entity_ref.get::<BrushOrigin>(), &transform);
+				let local_brushes = entity_ref.contains::<LocalBrushes>();
+				let mesh_offset = entity_ref
+					.get::<Brushes>()
+					.map_or(Vec3::ZERO, |brushes| brushes.local_offset(origin, local_brushes));
+
+				let old_meshes = entity_ref
+					.get::<Children>()
+					.map(|children| children.to_vec())
+					.unwrap_or_default()
+					.into_iter()
+					.filter(|child| world.get::<MapGeometry>(*child).is_some())
+					.collect_vec();
+
+				// The textures this entity had before are the best guess for what materials to use
+				for child in &old_meshes {
+					if let (Some(name), Some(material)) = (world.get::<Name>(*child), world.get::<GenericMaterial3d>(*child)) {
+						cache.materials.entry(name.to_string()).or_insert_with(|| material.0.clone());
+					}
+				}
+
+				let overridden_config = match (entity_ref.get::<AutoRemoveTexturesOverride>(), entity_ref.get::<ScaleOverride>()) {
+					(None, None) => None,
+					(auto_remove_textures, scale) => {
+						let config = auto_remove_textures.map_or_else(|| config.clone(), |auto_remove_textures| auto_remove_textures.apply(config));
+						Some(match scale {
+							Some(scale) => scale.apply(&config),
+							None => config,
+						})
+					}
+				};
+				let config = overridden_config.as_ref().unwrap_or(config);
+
+				// Faces with different Quake 2 attributes are kept in separate meshes so that the attributes can be queried per mesh entity.
+				let mut grouped_polygons: HashMap<(&str, SurfaceAttributes), Vec<BrushSurfacePolygon>> = default();
+				for polygon in geometry_provider.polygonize(&brushes, config) {
+					if !config.is_texture_rendered(&polygon.surface.texture) {
+						continue;
+					}
+					grouped_polygons
+						.entry((&polygon.surface.texture, polygon.surface.attributes))
+						.or_default()
+						.push(polygon);
+				}
+
+				// We don't touch the old meshes until every texture is ready, so that the entity doesn't flicker
+				let mut textures: HashMap<&str, (Handle<GenericMaterial>, UVec2)> = default();
+				let mut ready = true;
+				for (texture, _) in grouped_polygons.keys() {
+					match cache.poll_texture(texture, config, &asset_server, world.resource::<Assets<Image>>()) {
+						Some(texture_data) => {
+							textures.insert(*texture, texture_data);
+						}
+						None => ready = false,
+					}
+				}
+				if !ready {
+					continue;
+				}
+
+				for child in old_meshes {
+					world.despawn(child);
+				}
+
+				let mut meshes = Vec::with_capacity(grouped_polygons.len());
+
+				for ((texture, attributes), polygons) in grouped_polygons {
+					let (material, texture_size) = textures[texture].clone();
+					let mesh = generate_mesh_from_brush_polygons(&polygons, config, texture_size).translated_by(mesh_offset);
+					let cell_meshes = match geometry_provider.chunk_size {
+						Some(chunk_size) => split_mesh_into_cells(mesh, chunk_size),
+						None => vec![mesh],
+					};
+
+					for mesh in cell_meshes {
+						let mesh_entity = world.spawn((Name::new(texture.s()), attributes)).id();
+
+						meshes.push((
+							mesh_entity,
+							mesh,
+							MapGeometryTexture {
+								name: texture.s(),
+								material: material.clone(),
+								attributes,
+								#[cfg(all(feature = "client", feature = "bsp"))]
+								lightmap: None,
+								#[cfg(feature = "bsp")]
+								flags: BspTexFlags::Normal,
+							},
+						));
+					}
+				}
+
+				let map_entity = QuakeMapEntity {
+					properties: [("classname".s(), class.info.name.s())].into_iter().collect(),
+					..default()
+				};
+
+				let mut view = GeometryProviderView {
+					world,
+					entity,
+					tb_server: &tb_server,
+					map_entity: &map_entity,
+					map_entity_idx: usize::MAX,
+					class,
+					meshes: meshes
+						.iter_mut()
+						.map(|(entity, mesh, texture)| GeometryProviderMeshView {
+							entity: *entity,
+							mesh,
+							texture,
+						})
+						.collect(),
+				};
+
+				for provider in geometry_provider.providers {
+					provider(&mut view);
+				}
+
+				(config.global_geometry_provider)(&mut view);
+
+				for (mesh_entity, mesh, _) in meshes {
+					let handle = world.resource_mut::<Assets<Mesh>>().add(mesh);
+					world.entity_mut(mesh_entity).insert((Mesh3d(handle), ChildOf(entity), MapGeometry));
+				}
+
+				world.entity_mut(entity).remove::<RegenerateBrushMeshes>();
+			}
+		});
+	}
+}
+
+#[test]
+fn owned_brush_change_detection() {
+	use bevy::ecs::system::RunSystemOnce;
+
+	let mut world = World::new();
+	let owned = world.spawn(Brushes::Owned(BrushList(Vec::new()))).id();
+	let shared = world.spawn(Brushes::Shared(Handle::default())).id();
+
+	world.run_system_once(GeometryPlugin::mark_changed_owned_brushes).unwrap();
+	assert!(world.entity(owned).contains::<RegenerateBrushMeshes>());
+	assert!(!world.entity(shared).contains::<RegenerateBrushMeshes>());
+
+	// Unchanged brushes shouldn't be marked again
+	world.entity_mut(owned).remove::<RegenerateBrushMeshes>();
+	world.run_system_once(GeometryPlugin::mark_changed_owned_brushes).unwrap();
+	assert!(!world.entity(owned).contains::<RegenerateBrushMeshes>());
+
+	let Brushes::Owned(brushes) = &mut *world.get_mut::<Brushes>(owned).unwrap() else { unreachable!() };
+	brushes.0.push(Brush::default());
+	world.run_system_once(GeometryPlugin::mark_changed_owned_brushes).unwrap();
+	assert!(world.entity(owned).contains::<RegenerateBrushMeshes>());
+}
+
+#[test]
+fn regenerated_meshes_are_local() {
+	use brush::{BrushPlane, BrushSurface};
+
+	#[derive(SolidClass, Component, Reflect)]
+	#[reflect(QuakeClass, Component)]
+	#[geometry(GeometryProvider::new())]
+	struct Wall;
+
+	let mut app = App::new();
+
+	#[rustfmt::skip]
+	app
+		.add_plugins((AssetPlugin::default(), TaskPoolPlugin::default()))
+		.insert_resource(TrenchBroomServer::new(TrenchBroomConfig::default().suppress_invalid_entity_definitions(true)))
+		.register_type::<Wall>()
+		.init_asset::<Image>()
+		.init_asset::<Mesh>()
+	;
+
+	// Known textures skip loading their images
+	let mut cache = BrushTextureCache::default();
+	cache.materials.insert("wall".s(), Handle::default());
+	cache.sizes.insert("wall".s(), UVec2::splat(16));
+	app.insert_resource(cache);
+
+	// A cube from (63, -1, -1) to (65, 1, 1) in map space
+	let mut brush = Brush::default();
+	for normal in [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z] {
+		brush.surfaces.push(BrushSurface {
+			plane: BrushPlane {
+				normal,
+				distance: -1. - normal.x * 64.,
+			},
+			texture: "wall".s(),
+			uv: default(),
+			attributes: default(),
+		});
+	}
+
+	let entity = app
+		.world_mut()
+		.spawn((Wall, Transform::from_xyz(64., 0., 0.), Brushes::Owned(BrushList(vec![brush.clone()]))))
+		.insert(RegenerateBrushMeshes)
+		.id();
+	// Spawned at the same origin, but moved since, like a door that opened
+	let moved = app
+		.world_mut()
+		.spawn((
+			Wall,
+			Transform::from_xyz(64., 32., 0.),
+			BrushOrigin(Vec3::new(64., 0., 0.)),
+			Brushes::Owned(BrushList(vec![brush])),
+		))
+		.insert(RegenerateBrushMeshes)
+		.id();
+	GeometryPlugin::regenerate_owned_brush_meshes(app.world_mut());
+
+	let world = app.world();
+	for entity in [entity, moved] {
+		assert!(!world.entity(entity).contains::<RegenerateBrushMeshes>());
+		let children = world.get::<Children>(entity).unwrap();
+		assert_eq!(children.len(), 1);
+		let mesh = world
+			.resource::<Assets<Mesh>>()
+			.get(&world.get::<Mesh3d>(children[0]).unwrap().0)
+			.unwrap();
+		let aabb = mesh.compute_aabb().unwrap();
+		assert!(Vec3::from(aabb.min()).abs_diff_eq(Vec3::splat(-1.), 0.001));
+		assert!(Vec3::from(aabb.max()).abs_diff_eq(Vec3::splat(1.), 0.001));
+	}
+}
+
+#[test]
+fn regenerated_meshes_use_load_scale() {
+	use bevy_mesh::VertexAttributeValues;
+	use brush::{BrushPlane, BrushSurface};
+
+	#[derive(SolidClass, Component, Reflect)]
+	#[reflect(QuakeClass, Component)]
+	#[geometry(GeometryProvider::new())]
+	struct Wall;
+
+	let mut app = App::new();
+
+	#[rustfmt::skip]
+	app
+		.add_plugins((AssetPlugin::default(), TaskPoolPlugin::default()))
+		.insert_resource(TrenchBroomServer::new(TrenchBroomConfig::default().suppress_invalid_entity_definitions(true)))
+		.register_type::<Wall>()
+		.init_asset::<Image>()
+		.init_asset::<Mesh>()
+	;
+
+	// Known textures skip loading their images
+	let mut cache = BrushTextureCache::default();
+	cache.materials.insert("wall".s(), Handle::default());
+	cache.sizes.insert("wall".s(), UVec2::splat(16));
+	app.insert_resource(cache);
+
+	let mut brush = Brush::default();
+	for normal in [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z] {
+		brush.surfaces.push(BrushSurface {
+			plane: BrushPlane { normal, distance: -0.5 },
+			texture: "wall".s(),
+			uv: default(),
+			attributes: default(),
+		});
+	}
+
+	let scale = 16.;
+	let [default_scaled, overridden] = [None, Some(ScaleOverride(scale))].map(|scale_override| {
+		let mut entity = app.world_mut().spawn((Wall, Brushes::Owned(BrushList(vec![brush.clone()])), RegenerateBrushMeshes));
+		if let Some(scale_override) = scale_override {
+			entity.insert(scale_override);
+		}
+		entity.id()
+	});
+	GeometryPlugin::regenerate_owned_brush_meshes(app.world_mut());
+
+	let world = app.world();
+	let uvs_of = |entity: Entity| {
+		let children = world.get::<Children>(entity).unwrap();
+		let mesh = world
+			.resource::<Assets<Mesh>>()
+			.get(&world.get::<Mesh3d>(children[0]).unwrap().0)
+			.unwrap();
+		let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) else { panic!("no uvs") };
+		uvs.clone()
+	};
+
+	// UVs are in texels, which depend on the scale the map was loaded with
+	let config = TrenchBroomConfig::default().scale(scale);
+	let expected = generate_mesh_from_brush_polygons(&GeometryProvider::new().polygonize(&[brush], &config), &config, UVec2::splat(16));
+	let Some(VertexAttributeValues::Float32x2(expected)) = expected.attribute(Mesh::ATTRIBUTE_UV_0) else { panic!("no uvs") };
+	assert_eq!(&uvs_of(overridden), expected);
+	assert_ne!(&uvs_of(default_scaled), expected);
+}
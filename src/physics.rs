@@ -1,8 +1,9 @@
 use crate::*;
-use brush::ConvexHull;
+use brush::{Brush, ConvexHull, SurfaceAttributes};
 #[cfg(feature = "bsp")]
 use bsp::BspBrushesAsset;
-use geometry::{BrushList, Brushes};
+use class::builtin::PhysicsBody;
+use geometry::{AutoRemoveTexturesOverride, BrushList, BrushOrigin, Brushes, LocalBrushes};
 
 #[cfg(feature = "rapier")]
 use bevy_rapier3d::prelude::*;
@@ -17,28 +18,79 @@ use avian3d::prelude::*;
 #[reflect(Component)]
 pub struct ConvexCollision;
 
+/// Like [`ConvexCollision`], but makes the collider a sensor, detecting intersections without blocking anything. Useful for `trigger_*` entities.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct TriggerCollision;
+
 /// Automatically creates trimesh colliders for entities with [`Mesh3d`].
 #[derive(Component, Reflect, Debug, Clone)]
 #[reflect(Component)]
 pub struct TrimeshCollision;
 
+/// Holds the colliders of brushes in different collision layers than the rest of its parent's brushes, see [`TrenchBroomConfig::collision_layer_rules`].
+///
+/// Despawned and recreated along with its parent's collider.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct LayeredBrushCollider;
+
+/// The physics engine's collision layers component.
+#[cfg(feature = "avian")]
+pub type CollisionLayersComponent = CollisionLayers;
+/// The physics engine's collision layers component.
+#[cfg(feature = "rapier")]
+pub type CollisionLayersComponent = CollisionGroups;
+
+/// Returns the physics engine's collision layers component with the specified bit masks.
+#[cfg(feature = "avian")]
+pub fn collision_layers_component(memberships: u32, filters: u32) -> CollisionLayers {
+	CollisionLayers::from_bits(memberships, filters)
+}
+
+/// Returns the physics engine's collision layers component with the specified bit masks.
+#[cfg(feature = "rapier")]
+pub fn collision_layers_component(memberships: u32, filters: u32) -> CollisionGroups {
+	CollisionGroups::new(Group::from_bits_retain(memberships), Group::from_bits_retain(filters))
+}
+
+/// Returns the memberships and filters bit masks of the physics engine's collision layers component.
+#[cfg(feature = "avian")]
+pub fn collision_layers_bits(layers: &CollisionLayers) -> (u32, u32) {
+	(layers.memberships.0, layers.filters.0)
+}
+
+/// Returns the memberships and filters bit masks of the physics engine's collision layers component.
+#[cfg(feature = "rapier")]
+pub fn collision_layers_bits(groups: &CollisionGroups) -> (u32, u32) {
+	(groups.memberships.bits(), groups.filters.bits())
+}
+
 pub type BrushVertices = Vec<Vec3>;
 
 /// Attempts to calculate vertices on the brushes contained within for use in physics, if it can find said brushes.
 ///
+/// Brushes that shouldn't collide according to [`TrenchBroomConfig::is_brush_colliding`] with `config` get no vertices.
+/// Pass an [`AutoRemoveTexturesOverride`] [applied](AutoRemoveTexturesOverride::apply) config for entities that have one.
+///
 /// If it can't find them (like if the asset isn't loaded), returns [`None`].
 pub fn calculate_brushes_vertices<'l, 'w: 'l>(
 	brushes: &Brushes,
+	config: &TrenchBroomConfig,
 	brush_lists: &'w Assets<BrushList>,
 	#[cfg(feature = "bsp")] bsp_brushes: &'w Assets<BspBrushesAsset>,
 ) -> Option<Vec<BrushVertices>> {
 	fn extract_vertices<T: ConvexHull>(brush: &T) -> Vec<Vec3> {
 		brush.calculate_vertices().map(|(position, _)| position.as_vec3()).collect()
 	}
+	let extract_colliding_vertices = |brush: &Brush| match config.is_brush_colliding(brush) {
+		true => extract_vertices(brush),
+		false => Vec::new(),
+	};
 
 	match brushes {
-		Brushes::Owned(list) => Some(list.iter().map(extract_vertices).collect()),
-		Brushes::Shared(handle) => brush_lists.get(handle).map(|list| list.iter().map(extract_vertices).collect()),
+		Brushes::Owned(list) => Some(list.iter().map(extract_colliding_vertices).collect()),
+		Brushes::Shared(handle) => brush_lists.get(handle).map(|list| list.iter().map(extract_colliding_vertices).collect()),
 		#[cfg(feature = "bsp")]
 		Brushes::Bsp(handle) => bsp_brushes
 			.get(handle)
@@ -52,11 +104,14 @@ impl Plugin for PhysicsPlugin {
 		#[rustfmt::skip]
 		app
 			.register_type::<ConvexCollision>()
+			.register_type::<TriggerCollision>()
 			.register_type::<TrimeshCollision>()
+			.register_type::<LayeredBrushCollider>()
 
 			.init_resource::<SceneCollidersReadyTests>()
 
 			.add_systems(PostUpdate, (
+				Self::remove_outdated_convex_colliders,
 				Self::add_convex_colliders,
 				Self::add_trimesh_colliders,
 				Self::trigger_scene_colliders_ready,
@@ -65,18 +120,59 @@ impl Plugin for PhysicsPlugin {
 	}
 }
 impl PhysicsPlugin {
+	/// Removes the colliders of entities with [`ConvexCollision`] or [`TriggerCollision`] whose [`Brushes`] have changed, so that they can be recalculated.
+	pub fn remove_outdated_convex_colliders(
+		mut commands: Commands,
+		query: Query<(Entity, Option<&Children>), (Changed<Brushes>, Or<(With<ConvexCollision>, With<TriggerCollision>)>, With<Collider>)>,
+		layered_collider_query: Query<(), With<LayeredBrushCollider>>,
+	) {
+		for (entity, children) in &query {
+			commands.entity(entity).remove::<Collider>();
+
+			for child in children.into_iter().flatten().copied() {
+				if layered_collider_query.contains(child) {
+					commands.entity(child).despawn();
+				}
+			}
+		}
+	}
+
+	/// Adds compound colliders to entities with [`ConvexCollision`] or [`TriggerCollision`] whose brushes are available.
+	///
+	/// Brushes matching one of [`TrenchBroomConfig::collision_layer_rules`] are put in that rule's layers, which win over the entity's own.
+	/// The rest keep the layers the entity has from [`PhysicsBody`], or otherwise its existing collision layers component, on both the entity and [`LayeredBrushCollider`]s.
+	/// The entity's own collider is made of the brushes without a rule if there are any, so its layers are only replaced if every brush matches a rule.
 	pub fn add_convex_colliders(
 		mut commands: Commands,
-		query: Query<(Entity, &Brushes, &Transform), (With<ConvexCollision>, Without<Collider>)>,
+		query: Query<
+			(
+				Entity,
+				&Brushes,
+				&Transform,
+				Option<&BrushOrigin>,
+				Has<LocalBrushes>,
+				Has<TriggerCollision>,
+				Option<&AutoRemoveTexturesOverride>,
+				Option<&PhysicsBody>,
+				Option<&CollisionLayersComponent>,
+			),
+			(Or<(With<ConvexCollision>, With<TriggerCollision>)>, Without<Collider>),
+		>,
+		tb_server: Res<TrenchBroomServer>,
 		brush_lists: Res<Assets<BrushList>>,
 		#[cfg(feature = "bsp")] brush_assets: Res<Assets<BspBrushesAsset>>,
 		mut tests: ResMut<SceneCollidersReadyTests>,
 	) {
 		#[allow(unused)]
-		for (entity, brushes, transform) in &query {
-			let mut colliders = Vec::new();
+		for (entity, brushes, transform, brush_origin, local_brushes, sensor, auto_remove_textures, physics_body, existing_layers) in &query {
+			let overridden_config = auto_remove_textures.map(|auto_remove_textures| auto_remove_textures.apply(&tb_server.config));
+			let config = overridden_config.as_ref().unwrap_or(&tb_server.config);
+
+			// Grouped by collision layers, in order of first appearance
+			let mut colliders: Vec<(Option<(u32, u32)>, Vec<(Vec3, Quat, Collider)>)> = Vec::new();
 			let Some(brush_vertices) = calculate_brushes_vertices(
 				brushes,
+				config,
 				&brush_lists,
 				#[cfg(feature = "bsp")]
 				&brush_assets,
@@ -84,6 +180,13 @@ impl PhysicsPlugin {
 				continue;
 			};
 
+			let brush_list = match brushes {
+				Brushes::Owned(list) => Some(&**list),
+				Brushes::Shared(handle) => brush_lists.get(handle).map(|list| &**list),
+				#[cfg(feature = "bsp")]
+				Brushes::Bsp(_) => None,
+			};
+
 			for (brush_idx, vertices) in brush_vertices.into_iter().enumerate() {
 				if vertices.is_empty() {
 					continue;
@@ -106,42 +209,95 @@ impl PhysicsPlugin {
 					fail!();
 				};
 
+				let rule = brush_list
+					.and_then(|list| list.get(brush_idx))
+					.and_then(|brush| config.brush_collision_layers(brush));
 				#[cfg(feature = "bsp")]
-				#[rustfmt::skip]
-				let position = if matches!(brushes, Brushes::Bsp(_)) { Vec3::ZERO } else { -transform.translation };
-				#[cfg(not(feature = "bsp"))]
-				let position = Vec3::ZERO;
-				colliders.push((position, Quat::IDENTITY, collider));
+				let rule = rule.or_else(|| match brushes {
+					Brushes::Bsp(handle) => brush_assets
+						.get(handle)
+						.and_then(|brushes_asset| brushes_asset.brushes.get(brush_idx))
+						.and_then(|brush| config.bsp_brush_collision_layers(brush)),
+					_ => None,
+				});
+				let layers = rule.map(|rule| (rule.memberships, rule.filters));
+
+				let collider = (
+					brushes.local_offset(BrushOrigin::or_transform(brush_origin, transform), local_brushes),
+					Quat::IDENTITY,
+					collider,
+				);
+				match colliders.iter_mut().find(|(group_layers, _)| *group_layers == layers) {
+					Some((_, group)) => group.push(collider),
+					None => colliders.push((layers, vec![collider])),
+				}
 			}
 
 			if colliders.is_empty() {
+				// Entities made entirely of non-colliding textures are expected to have no colliders
+				if brush_list.is_some_and(|list| list.iter().all(|brush| !config.is_brush_colliding(brush))) {
+					commands.entity(entity).remove::<(ConvexCollision, TriggerCollision)>();
+					continue;
+				}
 				error!(
-					"No colliders produced by brushes for entity {entity}, removing ConvexCollision component. If this is expected behavior, make an issue and i will remove this message."
+					"No colliders produced by brushes for entity {entity}, removing ConvexCollision and TriggerCollision components. If this is expected behavior, make an issue and i will remove this message."
 				);
-				commands.entity(entity).remove::<ConvexCollision>();
+				commands.entity(entity).remove::<(ConvexCollision, TriggerCollision)>();
 				continue;
 			}
 
-			#[cfg(feature = "avian")]
-			commands
-				.entity(entity)
-				.insert(Collider::compound(colliders))
-				.insert_if_new(RigidBody::Static);
+			// Brushes without a rule stay in the entity's layers
+			let base_layers = physics_body
+				.map(|body| (body.collision_layers, body.collision_mask))
+				.or(existing_layers.map(collision_layers_bits));
+			if let Some(group_idx) = colliders.iter().position(|(layers, _)| layers.is_none()) {
+				let group = colliders.remove(group_idx);
+				colliders.insert(0, group);
+			}
 
-			#[cfg(feature = "rapier")]
-			commands.entity(entity).insert(Collider::compound(colliders));
+			// Compound colliders can only be in one set of layers, so the rest go on child entities
+			for (group_idx, (layers, colliders)) in colliders.into_iter().enumerate() {
+				let mut entity_commands = match group_idx {
+					0 => commands.entity(entity),
+					_ => commands.spawn((
+						Name::new("Layered brush collider"),
+						LayeredBrushCollider,
+						Transform::default(),
+						ChildOf(entity),
+					)),
+				};
+
+				entity_commands.insert(Collider::compound(colliders));
+
+				#[cfg(feature = "avian")]
+				if group_idx == 0 {
+					entity_commands.insert_if_new(RigidBody::Static);
+				}
+
+				if let Some((memberships, filters)) = layers.or(base_layers) {
+					entity_commands.insert(collision_layers_component(memberships, filters));
+				}
+
+				if sensor {
+					entity_commands.insert(Sensor);
+				}
+			}
 
 			tests.added_colliders_to_entities.insert(entity);
 		}
 	}
 
+	/// Adds trimesh colliders to entities with [`TrimeshCollision`].
+	///
+	/// Map geometry entities are named after their texture, which along with their [`SurfaceAttributes`] is used to find their [`collision layers`](TrenchBroomConfig::collision_layer_rules).
 	pub fn add_trimesh_colliders(
 		mut commands: Commands,
-		query: Query<(Entity, &Mesh3d), (With<TrimeshCollision>, Without<Collider>)>,
+		query: Query<(Entity, &Mesh3d, Option<&Name>, Option<&SurfaceAttributes>), (With<TrimeshCollision>, Without<Collider>)>,
+		tb_server: Res<TrenchBroomServer>,
 		meshes: Res<Assets<Mesh>>,
 		mut tests: ResMut<SceneCollidersReadyTests>,
 	) {
-		for (entity, mesh3d) in &query {
+		for (entity, mesh3d, name, attributes) in &query {
 			let Some(mesh) = meshes.get(mesh3d.id()) else {
 				continue;
 			};
@@ -169,6 +325,10 @@ impl PhysicsPlugin {
 				commands.entity(entity).insert(collider);
 			}
 
+			if let Some(rule) = name.and_then(|name| tb_server.config.face_collision_layers(name, attributes.unwrap_or(&default()))) {
+				commands.entity(entity).insert(collision_layers_component(rule.memberships, rule.filters));
+			}
+
 			tests.added_colliders_to_entities.insert(entity);
 		}
 	}
@@ -182,7 +342,13 @@ impl PhysicsPlugin {
 
 		children_query: Query<&Children>,
 		has_collider: Query<(), With<Collider>>,
-		still_not_collider_query: Query<(), (Or<(With<ConvexCollision>, With<TrimeshCollision>)>, Without<Collider>)>,
+		still_not_collider_query: Query<
+			(),
+			(
+				Or<(With<ConvexCollision>, With<TriggerCollision>, With<TrimeshCollision>)>,
+				Without<Collider>,
+			),
+		>,
 	) {
 		let mut scene_roots = HashSet::new();
 
@@ -222,3 +388,104 @@ pub struct SceneCollidersReadyTests {
 pub struct SceneCollidersReady {
 	pub collider_entities: Vec<Entity>,
 }
+
+#[test]
+fn trigger_colliders() {
+	use bevy::ecs::system::RunSystemOnce;
+	use brush::{BrushPlane, BrushSurface};
+
+	let cube = Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane { normal, distance: -1. },
+				texture: "trigger".s(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	};
+
+	let mut world = World::new();
+	world.insert_resource(TrenchBroomServer::new(default()));
+	world.init_resource::<Assets<BrushList>>();
+	#[cfg(feature = "bsp")]
+	world.init_resource::<Assets<BspBrushesAsset>>();
+	world.init_resource::<SceneCollidersReadyTests>();
+
+	let solid = world.spawn((Brushes::Owned(BrushList(vec![cube.clone()])), ConvexCollision)).id();
+	let trigger = world.spawn((Brushes::Owned(BrushList(vec![cube])), TriggerCollision)).id();
+
+	world.run_system_once(PhysicsPlugin::add_convex_colliders).unwrap();
+
+	assert!(world.entity(solid).contains::<Collider>());
+	assert!(!world.entity(solid).contains::<Sensor>());
+	assert!(world.entity(trigger).contains::<Collider>());
+	assert!(world.entity(trigger).contains::<Sensor>());
+	assert!(
+		world
+			.resource::<SceneCollidersReadyTests>()
+			.added_colliders_to_entities
+			.contains(&trigger)
+	);
+}
+
+#[test]
+fn collision_layer_rules_with_physics_body() {
+	use bevy::ecs::system::RunSystemOnce;
+	use brush::{BrushPlane, BrushSurface};
+	use config::{CollisionLayerCondition, CollisionLayerRule};
+
+	let cube = |center: DVec3, texture: &str| Brush {
+		surfaces: [DVec3::X, DVec3::NEG_X, DVec3::Y, DVec3::NEG_Y, DVec3::Z, DVec3::NEG_Z]
+			.map(|normal| BrushSurface {
+				plane: BrushPlane {
+					normal,
+					distance: -1. - normal.dot(center),
+				},
+				texture: texture.s(),
+				uv: default(),
+				attributes: default(),
+			})
+			.into(),
+	};
+
+	let mut world = World::new();
+	world.insert_resource(TrenchBroomServer::new(TrenchBroomConfig::default().collision_layer_rules(vec![
+		CollisionLayerRule::new(CollisionLayerCondition::Texture("monsterclip".s()), 0b100, 0b1),
+	])));
+	world.init_resource::<Assets<BrushList>>();
+	#[cfg(feature = "bsp")]
+	world.init_resource::<Assets<BspBrushesAsset>>();
+	world.init_resource::<SceneCollidersReadyTests>();
+
+	let body = PhysicsBody {
+		collision_layers: 0b10,
+		collision_mask: 0b11,
+		..default()
+	};
+	// The clip comes first, but the entity's own collider is still the one keeping its layers
+	let mixed = world
+		.spawn((
+			Brushes::Owned(BrushList(vec![cube(DVec3::ZERO, "monsterclip"), cube(DVec3::X * 4., "wall")])),
+			ConvexCollision,
+			body.clone(),
+			collision_layers_component(0b10, 0b11),
+		))
+		.id();
+	let clip_only = world
+		.spawn((Brushes::Owned(BrushList(vec![cube(DVec3::ZERO, "monsterclip")])), ConvexCollision, body))
+		.id();
+
+	world.run_system_once(PhysicsPlugin::add_convex_colliders).unwrap();
+
+	let layers_of = |world: &World, entity: Entity| world.get::<CollisionLayersComponent>(entity).map(collision_layers_bits);
+	assert_eq!(layers_of(&world, mixed), Some((0b10, 0b11)));
+	let children = world.get::<Children>(mixed).unwrap();
+	assert_eq!(children.len(), 1);
+	assert!(world.entity(children[0]).contains::<LayeredBrushCollider>());
+	assert_eq!(layers_of(&world, children[0]), Some((0b100, 0b1)));
+
+	// Rules win over the body's layers for the brushes they match
+	assert_eq!(layers_of(&world, clip_only), Some((0b100, 0b1)));
+	assert!(world.get::<Children>(clip_only).is_none());
+}
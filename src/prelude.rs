@@ -40,7 +40,7 @@ pub use crate::{
 		builtin::{Target, Targetable},
 		spawn_util::*,
 	},
-	config::TrenchBroomConfig,
+	config::{MapLoadSettings, TrenchBroomConfig},
 	geometry::{GeometryProvider, GeometryProviderView},
 	qmap::QuakeMapEntity,
 	util::IsSceneWorld,
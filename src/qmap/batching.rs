@@ -0,0 +1,337 @@
+//! Merging of meshes across entities, see [`GeometryProvider::static_batch`](geometry::GeometryProvider::static_batch).
+
+use bevy::asset::LoadContext;
+use bevy_mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+use brush::SurfaceAttributes;
+use geometry::{MapGeometry, MapGeometryTexture, StaticBatch, StaticBatchSource};
+
+use super::*;
+
+/// A mesh of a statically batched entity, waiting to be merged.
+pub(crate) struct StaticBatchMesh {
+	pub entity: Entity,
+	pub mesh: Mesh,
+	pub texture: MapGeometryTexture,
+}
+
+/// Merges `meshes` by material and surface attributes (and cell, if [`TrenchBroomConfig::static_batch_cell_size`] is set), spawning an entity for each batch.
+///
+/// Batches are in world space, so this has to run after entities have been given their final [`Transform`]s.
+pub(crate) fn spawn_static_batches(
+	world: &mut World,
+	load_context: &mut LoadContext,
+	config: &TrenchBroomConfig,
+	meshes: Vec<StaticBatchMesh>,
+	mesh_handles: &mut Vec<Handle<Mesh>>,
+) {
+	// Keep the order of first appearance so that labels are deterministic
+	let mut batches: Vec<(
+		(Handle<GenericMaterial>, SurfaceAttributes, IVec3),
+		Vec<(StaticBatchMesh, GlobalTransform)>,
+	)> = Vec::new();
+
+	for batch_mesh in meshes {
+		let Some(aabb) = batch_mesh.mesh.compute_aabb() else {
+			// Without positions there's nowhere to put it, so it stays on its entity
+			spawn_unbatched_mesh(world, load_context, batch_mesh, mesh_handles);
+			continue;
+		};
+		let transform = world_transform(world, batch_mesh.entity);
+		let cell = match config.static_batch_cell_size {
+			Some(cell_size) if cell_size > 0. => (transform.transform_point(aabb.center.into()) / cell_size).floor().as_ivec3(),
+			_ => IVec3::ZERO,
+		};
+		// Batch entities hold the surface attributes of their meshes, so those have to match too
+		let key = (batch_mesh.texture.material.clone(), batch_mesh.texture.attributes, cell);
+
+		match batches.iter_mut().find(|(batch_key, _)| *batch_key == key) {
+			Some((_, batch)) => batch.push((batch_mesh, transform)),
+			None => batches.push((key, vec![(batch_mesh, transform)])),
+		}
+	}
+
+	for (_, batch) in batches {
+		let texture = batch[0].0.texture.clone();
+		let (mesh, sources) = merge_meshes(batch, config);
+
+		let handle = load_context.add_labeled_asset(format!("Mesh{}", mesh_handles.len()), mesh);
+		mesh_handles.push(handle.clone());
+
+		world.spawn((
+			Name::new(format!("Static batch ({})", texture.name)),
+			texture.attributes,
+			Mesh3d(handle),
+			GenericMaterial3d(texture.material),
+			StaticBatch { sources },
+			MapGeometry,
+			Transform::default(),
+			#[cfg(feature = "client")]
+			Visibility::default(),
+		));
+	}
+}
+
+/// Returns the transform of `entity` in world space, from the [`Transform`]s of it and its ancestors,
+/// as [`GlobalTransform`]s aren't propagated in the scene world.
+fn world_transform(world: &World, entity: Entity) -> GlobalTransform {
+	let transform = GlobalTransform::from(world.get::<Transform>(entity).copied().unwrap_or_default());
+	match world.get::<ChildOf>(entity) {
+		Some(child_of) => world_transform(world, child_of.parent()) * transform,
+		None => transform,
+	}
+}
+
+/// Spawns `batch_mesh` as a mesh entity of its own entity, like it would be without batching.
+fn spawn_unbatched_mesh(world: &mut World, load_context: &mut LoadContext, batch_mesh: StaticBatchMesh, mesh_handles: &mut Vec<Handle<Mesh>>) {
+	let handle = load_context.add_labeled_asset(format!("Mesh{}", mesh_handles.len()), batch_mesh.mesh);
+	mesh_handles.push(handle.clone());
+
+	world.spawn((
+		Name::new(batch_mesh.texture.name),
+		batch_mesh.texture.attributes,
+		Mesh3d(handle),
+		GenericMaterial3d(batch_mesh.texture.material),
+		ChildOf(batch_mesh.entity),
+		MapGeometry,
+	));
+}
+
+/// Appends the meshes of `batch` together, moving them into world space with their transforms,
+/// returning the combined mesh and which indices belong to which entity.
+///
+/// Only 32-bit float attributes that every mesh has in the same format are kept, others are dropped with a warning.
+fn merge_meshes(batch: Vec<(StaticBatchMesh, GlobalTransform)>, config: &TrenchBroomConfig) -> (Mesh, Vec<StaticBatchSource>) {
+	let mut merged = Mesh::new(PrimitiveTopology::TriangleList, config.brush_mesh_asset_usages);
+	let mut attributes: Vec<(bevy_mesh::MeshVertexAttribute, VertexAttributeValues)> = batch[0]
+		.0
+		.mesh
+		.attributes()
+		.filter(|(attribute, values)| {
+			batch.iter().all(|(batch_mesh, _)| {
+				batch_mesh
+					.mesh
+					.attribute(attribute.id)
+					.is_some_and(|other| mem::discriminant(other) == mem::discriminant(*values))
+			})
+		})
+		.filter_map(|(attribute, values)| Some((attribute.clone(), empty_values(values)?)))
+		.collect();
+
+	let dropped = batch
+		.iter()
+		.flat_map(|(batch_mesh, _)| batch_mesh.mesh.attributes())
+		.map(|(attribute, _)| attribute)
+		.filter(|attribute| !attributes.iter().any(|(kept, _)| kept.id == attribute.id))
+		.map(|attribute| attribute.name)
+		.unique()
+		.collect_vec();
+	if !dropped.is_empty() {
+		warn!(
+			"Dropping mesh attributes {dropped:?} from static batch of {:?}, as not every mesh has them in the same 32-bit float format",
+			batch[0].0.texture.name
+		);
+	}
+	let mut indices: Vec<u32> = Vec::new();
+	let mut sources: Vec<StaticBatchSource> = Vec::new();
+	let mut vertex_count = 0;
+
+	for (batch_mesh, transform) in batch {
+		let start = indices.len() as u32;
+		let mesh_vertex_count = batch_mesh.mesh.count_vertices() as u32;
+		match batch_mesh.mesh.indices() {
+			Some(mesh_indices) => indices.extend(mesh_indices.iter().map(|index| index as u32 + vertex_count)),
+			// Every 3 vertices are a triangle
+			None => indices.extend(vertex_count..vertex_count + mesh_vertex_count),
+		}
+
+		for (attribute, values) in &mut attributes {
+			let Some(src) = batch_mesh.mesh.attribute(attribute.id) else { continue };
+			extend_values(values, src, transform_vertex_fn(attribute.id, &transform));
+		}
+		vertex_count += mesh_vertex_count;
+
+		let end = indices.len() as u32;
+		// Entities with multiple meshes of the same material end up next to each other
+		match sources.last_mut() {
+			Some(source) if source.entity == batch_mesh.entity && source.indices.end == start => source.indices.end = end,
+			_ => sources.push(StaticBatchSource {
+				entity: batch_mesh.entity,
+				indices: start..end,
+			}),
+		}
+	}
+
+	for (attribute, values) in attributes {
+		merged.insert_attribute(attribute, values);
+	}
+	merged.insert_indices(Indices::U32(indices));
+
+	(merged, sources)
+}
+
+/// Returns empty values of the same format as `values`, or [`None`] if it isn't a format batching supports.
+fn empty_values(values: &VertexAttributeValues) -> Option<VertexAttributeValues> {
+	match values {
+		VertexAttributeValues::Float32x2(_) => Some(VertexAttributeValues::Float32x2(Vec::new())),
+		VertexAttributeValues::Float32x3(_) => Some(VertexAttributeValues::Float32x3(Vec::new())),
+		VertexAttributeValues::Float32x4(_) => Some(VertexAttributeValues::Float32x4(Vec::new())),
+		// Brush meshes don't use any other formats, if a geometry provider adds one, it's dropped
+		_ => None,
+	}
+}
+
+/// Returns how the xyz components of the attribute `id` are moved into world space by `transform`, as positions, normals, tangents, or not at all.
+fn transform_vertex_fn(id: bevy_mesh::MeshVertexAttributeId, transform: &GlobalTransform) -> impl Fn(Vec3) -> Vec3 {
+	let affine = transform.affine();
+	// Inverse transpose, to stay perpendicular to surfaces under non-uniform scale
+	let normal_matrix = Mat3::from(affine.matrix3).inverse().transpose();
+	move |v| {
+		if id == Mesh::ATTRIBUTE_POSITION.id {
+			affine.transform_point3(v)
+		} else if id == Mesh::ATTRIBUTE_NORMAL.id {
+			(normal_matrix * v).normalize_or_zero()
+		} else if id == Mesh::ATTRIBUTE_TANGENT.id {
+			affine.transform_vector3(v).normalize_or_zero()
+		} else {
+			v
+		}
+	}
+}
+
+/// Appends `src` to `dst`, passing the xyz components of 3D and 4D vectors through `transform`.
+fn extend_values(dst: &mut VertexAttributeValues, src: &VertexAttributeValues, transform: impl Fn(Vec3) -> Vec3) {
+	match (dst, src) {
+		(VertexAttributeValues::Float32x2(dst), VertexAttributeValues::Float32x2(src)) => dst.extend_from_slice(src),
+		(VertexAttributeValues::Float32x3(dst), VertexAttributeValues::Float32x3(src)) => {
+			dst.extend(src.iter().map(|v| transform(Vec3::from(*v)).to_array()))
+		}
+		(VertexAttributeValues::Float32x4(dst), VertexAttributeValues::Float32x4(src)) => {
+			dst.extend(src.iter().map(|v| transform(Vec3::from_slice(v)).extend(v[3]).to_array()))
+		}
+		// Attributes are filtered to ones with the same supported format in every mesh beforehand
+		_ => unreachable!(),
+	}
+}
+
+#[test]
+fn static_batch_merging() {
+	use std::f32::consts::FRAC_PI_2;
+
+	let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, default());
+	mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; 3]);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.], [1., 0.], [0., 1.]]);
+	mesh.insert_indices(Indices::U32(vec![0, 1, 2]));
+
+	let texture = MapGeometryTexture {
+		name: "base".s(),
+		material: default(),
+		attributes: default(),
+		#[cfg(all(feature = "client", feature = "bsp"))]
+		lightmap: None,
+		#[cfg(feature = "bsp")]
+		flags: BspTexFlags::Normal,
+	};
+
+	let batch = [
+		(Entity::from_raw(1), Transform::IDENTITY),
+		(Entity::from_raw(1), Transform::IDENTITY),
+		(
+			Entity::from_raw(2),
+			Transform::from_translation(Vec3::X).with_rotation(Quat::from_rotation_y(FRAC_PI_2)),
+		),
+	]
+	.map(|(entity, transform)| {
+		(
+			StaticBatchMesh {
+				entity,
+				mesh: mesh.clone(),
+				texture: texture.clone(),
+			},
+			GlobalTransform::from(transform),
+		)
+	})
+	.into();
+
+	let (merged, sources) = merge_meshes(batch, &default());
+
+	assert_eq!(merged.count_vertices(), 9);
+	assert_eq!(merged.indices().unwrap().iter().collect_vec(), [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+	// Rotated a quarter turn around Y, then translated along X
+	let positions = merged.attribute(Mesh::ATTRIBUTE_POSITION).unwrap().as_float3().unwrap();
+	assert!(Vec3::from(positions[7]).abs_diff_eq(vec3(1., 0., -1.), 1e-5));
+	let normals = merged.attribute(Mesh::ATTRIBUTE_NORMAL).unwrap().as_float3().unwrap();
+	assert!(Vec3::from(normals[6]).abs_diff_eq(Vec3::X, 1e-5));
+	assert_eq!(normals[0], [0., 0., 1.]);
+	assert_eq!(
+		sources,
+		[
+			StaticBatchSource {
+				entity: Entity::from_raw(1),
+				indices: 0..6
+			},
+			StaticBatchSource {
+				entity: Entity::from_raw(2),
+				indices: 6..9
+			},
+		]
+	);
+
+	let batch = StaticBatch { sources };
+	assert_eq!(batch.source_of_triangle(2), Some(Entity::from_raw(2)));
+
+	// Attributes in unsupported formats would end up the wrong length, so they're dropped
+	mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_INDEX, VertexAttributeValues::Uint16x4(vec![[0; 4]; 3]));
+	let batch = (0..2)
+		.map(|_| {
+			(
+				StaticBatchMesh {
+					entity: Entity::from_raw(1),
+					mesh: mesh.clone(),
+					texture: texture.clone(),
+				},
+				GlobalTransform::IDENTITY,
+			)
+		})
+		.collect();
+	let (merged, _) = merge_meshes(batch, &default());
+	assert!(!merged.contains_attribute(Mesh::ATTRIBUTE_JOINT_INDEX));
+	assert_eq!(merged.attribute(Mesh::ATTRIBUTE_UV_0).map(VertexAttributeValues::len), Some(6));
+
+	// Attributes only some meshes have are dropped too, and meshes without indices keep their triangles
+	mesh.remove_attribute(Mesh::ATTRIBUTE_JOINT_INDEX);
+	let mut tangent_mesh = mesh.clone();
+	tangent_mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, vec![[1., 0., 0., 1.]; 3]);
+	let mut unindexed_mesh = mesh.clone();
+	unindexed_mesh.remove_indices();
+	let batch = [tangent_mesh, unindexed_mesh]
+		.map(|mesh| {
+			(
+				StaticBatchMesh {
+					entity: Entity::from_raw(1),
+					mesh,
+					texture: texture.clone(),
+				},
+				GlobalTransform::IDENTITY,
+			)
+		})
+		.into();
+	let (merged, sources) = merge_meshes(batch, &default());
+	assert!(!merged.contains_attribute(Mesh::ATTRIBUTE_TANGENT));
+	assert_eq!(merged.count_vertices(), 6);
+	assert_eq!(merged.indices().unwrap().iter().collect_vec(), [0, 1, 2, 3, 4, 5]);
+	assert_eq!(sources[0].indices, 0..6);
+}
+
+#[test]
+fn static_batch_world_transforms() {
+	let mut world = World::new();
+	let parent = world
+		.spawn(Transform::from_xyz(0., 10., 0.).with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2)))
+		.id();
+	let child = world.spawn((Transform::from_xyz(1., 0., 0.), ChildOf(parent))).id();
+
+	let transform = world_transform(&world, child);
+	assert!(transform.translation().abs_diff_eq(vec3(0., 10., -1.), 1e-5));
+	assert!(transform.transform_point(Vec3::X).abs_diff_eq(vec3(0., 10., -2.), 1e-5));
+}
@@ -0,0 +1,479 @@
+//! Load-time support for `ericw-tools`' `misc_external_map` prefab system, see `BspExternalMap` for the keys used.
+
+use std::path::{Path, PathBuf};
+
+use bevy::asset::{AssetPath, LoadContext};
+use class::builtin::read_rotation_from_entity;
+use util::{angle_to_quat, angles_to_quat};
+
+use super::{hierarchy::TrenchBroomHierarchy, *};
+
+/// How many external maps deep we will go before giving up, protects against maps that reference each other.
+pub const MAX_EXTERNAL_MAP_DEPTH: usize = 8;
+
+/// Properties that reference other entities by name, and so get [`_external_map_prefix`](EXTERNAL_MAP_PREFIX_KEY) and [`_external_map_suffix`](EXTERNAL_MAP_SUFFIX_KEY) applied.
+pub const EXTERNAL_MAP_REMAPPED_KEYS: &[&str] = &["targetname", "target", "killtarget"];
+
+/// Prepended to the names of entities instantiated from an external `.map` file. This isn't an `ericw-tools` key.
+pub const EXTERNAL_MAP_PREFIX_KEY: &str = "_external_map_prefix";
+/// Appended to the names of entities instantiated from an external `.map` file. This isn't an `ericw-tools` key.
+pub const EXTERNAL_MAP_SUFFIX_KEY: &str = "_external_map_suffix";
+
+/// A `.bsp` referenced by an external map entity. These are instanced by spawning their scene rather than merging brushes, as compiled maps don't contain textured brushes.
+pub(crate) struct ExternalBspInstance {
+	pub path: AssetPath<'static>,
+	pub transform: Transform,
+}
+
+/// Where an entity of the map being loaded came from.
+#[derive(Debug, Clone, Default)]
+struct ExternalMapSource {
+	/// How many external maps deep the entity's map file is.
+	depth: usize,
+	/// Directory of the entity's map file, which nested external maps are relative to.
+	dir: PathBuf,
+	/// Transform of every external map entity the entity was instantiated through, composed.
+	affine: DAffine3,
+	/// Rotation part of [`Self::affine`].
+	rotation: Quat,
+	/// Prefixes of every external map entity the entity was instantiated through, outermost first.
+	prefix: String,
+	/// Suffixes of every external map entity the entity was instantiated through, outermost last.
+	suffix: String,
+}
+
+/// Resolves every entity with an `_external_map` key in `entities`, recursively.
+///
+/// For external `.map` files, brushes from worldspawn and `func_group`s are transformed and either given to the instancing entity (if `_external_map_classname` is set),
+/// or merged into our worldspawn. Every other entity is appended to `entities`, transformed, with its names remapped, and in the TrenchBroom layer or group of the instancing entity.
+/// Nested external maps are transformed and remapped by every external map entity above them.
+///
+/// External `.bsp` files are returned by the index of the instancing entity, to be spawned as scenes.
+pub(crate) async fn resolve_external_maps(
+	entities: &mut QuakeMapEntities,
+	load_context: &mut LoadContext<'_>,
+	config: &TrenchBroomConfig,
+) -> anyhow::Result<HashMap<usize, ExternalBspInstance>> {
+	let root_dir = load_context.path().parent().map(Path::to_path_buf).unwrap_or_default();
+
+	resolve_external_maps_with(entities, root_dir, config, async |path| Ok(load_context.read_asset_bytes(path).await?)).await
+}
+
+/// [`resolve_external_maps`], reading external `.map` files with `read`.
+async fn resolve_external_maps_with(
+	entities: &mut QuakeMapEntities,
+	root_dir: PathBuf,
+	config: &TrenchBroomConfig,
+	mut read: impl AsyncFnMut(PathBuf) -> anyhow::Result<Vec<u8>>,
+) -> anyhow::Result<HashMap<usize, ExternalBspInstance>> {
+	let mut bsp_instances = HashMap::default();
+
+	let mut sources = vec![ExternalMapSource { dir: root_dir, ..default() }; entities.len()];
+
+	let mut map_entity_idx = 0;
+	while map_entity_idx < entities.len() {
+		let map_entity = &entities[map_entity_idx];
+		let Some(external_map) = map_entity.properties.get("_external_map").cloned() else {
+			map_entity_idx += 1;
+			continue;
+		};
+		let source = sources[map_entity_idx].clone();
+
+		let transform = read_external_map_transform(map_entity, config).map_err(|err| anyhow!("external map entity {map_entity_idx}: {err}"))?;
+		let path = source.dir.join(&external_map);
+
+		if source.depth >= MAX_EXTERNAL_MAP_DEPTH {
+			error!(
+				"External map {path:?} on entity {map_entity_idx} is nested more than {MAX_EXTERNAL_MAP_DEPTH} levels deep, are there maps referencing each other?"
+			);
+			entities[map_entity_idx].properties.remove("classname");
+			map_entity_idx += 1;
+			continue;
+		}
+
+		// The origin of an entity from another external map has already been transformed by it, but its angles and scale are still relative to it
+		let local_affine = DAffine3::from_scale_rotation_translation(transform.scale.as_dvec3(), transform.rotation.as_dquat(), DVec3::ZERO);
+		let affine = DAffine3 {
+			matrix3: source.affine.matrix3 * local_affine.matrix3,
+			translation: transform.translation.as_dvec3(),
+		};
+		let rotation = source.rotation * transform.rotation;
+
+		if path.extension().is_some_and(|ext| ext == "bsp") {
+			bsp_instances.insert(
+				map_entity_idx,
+				ExternalBspInstance {
+					path: AssetPath::from(path).with_label("Scene"),
+					transform: Transform::from_matrix(DMat4::from(affine).as_mat4()),
+				},
+			);
+			map_entity_idx += 1;
+			continue;
+		}
+
+		let bytes = read(path.clone())
+			.await
+			.map_err(|err| anyhow!("reading external map {path:?} on entity {map_entity_idx}: {err}"))?;
+		let quake_util_map = quake_util::qmap::parse(&mut io::Cursor::new(bytes))
+			.map_err(|err| anyhow!("parsing external map {path:?} on entity {map_entity_idx}: {err}"))?;
+		let mut external_entities = QuakeMapEntities::from_quake_util(quake_util_map, config);
+		// Fold func_groups and remove layers omitted from export, the hierarchy itself isn't kept
+		let hierarchy = TrenchBroomHierarchy::build(&mut external_entities, &mut World::new(), true, false);
+
+		let map_entity = &entities[map_entity_idx];
+		let prefix = source.prefix + map_entity.properties.get(EXTERNAL_MAP_PREFIX_KEY).map(String::as_str).unwrap_or_default();
+		let suffix = map_entity.properties.get(EXTERNAL_MAP_SUFFIX_KEY).cloned().unwrap_or_default() + &source.suffix;
+		let classname = map_entity.properties.get("_external_map_classname").cloned();
+		// TrenchBroom layer and group ids are only unique within a map, so external entities go in the layer or group of the entity instancing them
+		let hierarchy_keys = ["_tb_layer", "_tb_group"]
+			.into_iter()
+			.filter_map(|key| Some((key, map_entity.properties.get(key)?.clone())))
+			.collect_vec();
+
+		let mut merged_brushes = Vec::new();
+		let external_source = ExternalMapSource {
+			depth: source.depth + 1,
+			dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+			affine,
+			rotation,
+			prefix,
+			suffix,
+		};
+		let ExternalMapSource { prefix, suffix, .. } = &external_source;
+
+		for (external_idx, mut external_entity) in external_entities.0.into_iter().enumerate() {
+			if hierarchy.is_skipped(external_idx) {
+				continue;
+			}
+
+			for brush in &mut external_entity.brushes {
+				brush.transform(affine, config);
+			}
+
+			if external_entity.classname() == Ok("worldspawn") {
+				merged_brushes.append(&mut external_entity.brushes);
+				continue;
+			}
+
+			if let Ok(origin) = external_entity.get::<Vec3>("origin") {
+				let origin = affine.transform_point3(config.to_bevy_space(origin).as_dvec3()).as_vec3();
+				external_entity
+					.properties
+					.insert("origin".s(), config.from_bevy_space(origin).fgd_to_string_unquoted());
+			}
+
+			if rotation != Quat::IDENTITY {
+				let rotation = rotation * read_rotation_from_entity(&external_entity).unwrap_or(Quat::IDENTITY);
+				write_rotation_to_entity(&mut external_entity, rotation);
+			}
+
+			for key in ["_tb_layer", "_tb_group", "_tb_id"] {
+				external_entity.properties.remove(key);
+			}
+			for (key, value) in &hierarchy_keys {
+				external_entity.properties.insert(key.s(), value.clone());
+			}
+
+			for key in EXTERNAL_MAP_REMAPPED_KEYS {
+				if let Some(value) = external_entity.properties.get_mut(*key) {
+					*value = format!("{prefix}{value}{suffix}");
+				}
+			}
+
+			entities.push(external_entity);
+			sources.push(external_source.clone());
+		}
+
+		let map_entity = &mut entities[map_entity_idx];
+		match classname {
+			Some(classname) => {
+				map_entity.properties.insert("classname".s(), classname);
+				map_entity.brushes.append(&mut merged_brushes);
+			}
+			None => {
+				// The entity only existed to place the brushes
+				map_entity.properties.remove("classname");
+				if let Some(worldspawn) = entities.iter_mut().find(|map_entity| map_entity.classname() == Ok("worldspawn")) {
+					worldspawn.brushes.append(&mut merged_brushes);
+				}
+			}
+		}
+
+		map_entity_idx += 1;
+	}
+
+	Ok(bsp_instances)
+}
+
+/// Reads the origin, `_external_map_angles` (or `_external_map_angle`), and `_external_map_scale` of an external map entity into a Bevy-space transform.
+fn read_external_map_transform(map_entity: &QuakeMapEntity, config: &TrenchBroomConfig) -> Result<Transform, QuakeEntityError> {
+	let translation = config.to_bevy_space(map_entity.get::<Vec3>("origin").with_default(Vec3::ZERO)?);
+
+	let rotation = match map_entity.get::<Vec3>("_external_map_angles") {
+		Ok(angles) => angles_to_quat(angles),
+		Err(QuakeEntityError::RequiredPropertyNotFound { .. }) => angle_to_quat(map_entity.get::<f32>("_external_map_angle").with_default(0.)?),
+		Err(err) => return Err(err),
+	};
+
+	// Either a single uniform scale, or one per axis
+	let scale = match map_entity.get::<Vec3>("_external_map_scale") {
+		Ok(scale) => scale,
+		Err(QuakeEntityError::RequiredPropertyNotFound { .. }) => Vec3::ONE,
+		Err(_) => Vec3::splat(map_entity.get::<f32>("_external_map_scale")?),
+	};
+	// TrenchBroom -> Bevy axis order, without flipping signs
+	let scale = vec3(scale.y, scale.z, scale.x);
+
+	Ok(Transform {
+		translation,
+		rotation,
+		scale,
+	})
+}
+
+/// Writes `rotation` to whichever of `mangle`, `angles`, or `angle` [`read_rotation_from_entity`] would read it from,
+/// as `mangle` means something different for lights. `angle` is only replaced with `angles` if it can't represent `rotation`.
+fn write_rotation_to_entity(map_entity: &mut QuakeMapEntity, rotation: Quat) {
+	// Inverse of `angles_to_quat`
+	let (yaw, pitch, roll) = rotation.to_euler(EulerRot::YXZ);
+	let angles = vec3(-pitch.to_degrees(), yaw.to_degrees(), -roll.to_degrees());
+
+	let key = if map_entity.properties.contains_key("mangle") {
+		if map_entity.classname().is_ok_and(|classname| classname.starts_with("light")) {
+			// Inverse of `mangle_to_quat`
+			let (yaw, pitch, roll) = rotation.to_euler(EulerRot::YXZEx);
+			let mangle = vec3(yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees());
+			map_entity.properties.insert("mangle".s(), mangle.fgd_to_string_unquoted());
+			return;
+		}
+		"mangle"
+	} else if map_entity.properties.contains_key("angles") || angles.x.abs() > 0.001 || angles.z.abs() > 0.001 {
+		map_entity.properties.remove("angle");
+		"angles"
+	} else {
+		map_entity.properties.insert("angle".s(), angles.y.fgd_to_string_unquoted());
+		return;
+	};
+
+	map_entity.properties.insert(key.s(), angles.fgd_to_string_unquoted());
+}
+
+#[cfg(test)]
+fn resolve_test_maps(root: &str, files: &[(&str, &str)]) -> QuakeMapEntities {
+	let config = TrenchBroomConfig::default();
+	let mut entities = QuakeMapEntities::from_quake_util(quake_util::qmap::parse(&mut io::Cursor::new(root)).unwrap(), &config);
+	let files: HashMap<PathBuf, Vec<u8>> = files.iter().map(|(path, map)| (PathBuf::from(path), map.as_bytes().to_vec())).collect();
+
+	smol::block_on(resolve_external_maps_with(&mut entities, PathBuf::new(), &config, async |path| {
+		files.get(&path).cloned().ok_or_else(|| anyhow!("{path:?} not found"))
+	}))
+	.unwrap();
+
+	entities
+}
+
+#[cfg(test)]
+fn find_test_entity<'a>(entities: &'a QuakeMapEntities, classname: &str) -> &'a QuakeMapEntity {
+	entities.iter().find(|entity| entity.classname() == Ok(classname)).unwrap()
+}
+
+#[cfg(test)]
+const TEST_PREFAB_MAP: &str = r#"
+{
+"classname" "worldspawn"
+{
+( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) base 0 0 0 1 1
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) base 0 0 0 1 1
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) base 0 0 0 1 1
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) base 0 0 0 1 1
+}
+}
+{
+"classname" "info_target"
+"origin" "8 0 0"
+"targetname" "t"
+}
+"#;
+
+#[test]
+fn single_external_map() {
+	let entities = resolve_test_maps(
+		r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "misc_external_map"
+"_external_map" "prefab.map"
+"_external_map_angle" "90"
+"_external_map_prefix" "a_"
+"origin" "64 0 0"
+}
+"#,
+		&[("prefab.map", TEST_PREFAB_MAP)],
+	);
+
+	assert_eq!(find_test_entity(&entities, "worldspawn").brushes.len(), 1);
+	let target = find_test_entity(&entities, "info_target");
+	assert!(target.get::<Vec3>("origin").unwrap().abs_diff_eq(vec3(64., 8., 0.), 0.001));
+	assert_eq!(target.properties.get("targetname").map(String::as_str), Some("a_t"));
+}
+
+#[test]
+fn nested_external_map() {
+	let entities = resolve_test_maps(
+		r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "misc_external_map"
+"_external_map" "outer.map"
+"_external_map_angle" "90"
+"_external_map_prefix" "a_"
+"_external_map_suffix" "_a"
+"origin" "64 0 0"
+}
+"#,
+		&[
+			(
+				"outer.map",
+				r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "misc_external_map"
+"_external_map" "prefab.map"
+"_external_map_angle" "90"
+"_external_map_prefix" "b_"
+"_external_map_suffix" "_b"
+"origin" "16 0 0"
+}
+"#,
+			),
+			("prefab.map", TEST_PREFAB_MAP),
+		],
+	);
+
+	// Rotated by both external map entities, so (8, 0) -> (0, 8) -> (16, 8) -> (-8, 16) -> (56, 16)
+	let target = find_test_entity(&entities, "info_target");
+	assert!(target.get::<Vec3>("origin").unwrap().abs_diff_eq(vec3(56., 16., 0.), 0.001));
+	assert_eq!(target.properties.get("targetname").map(String::as_str), Some("a_b_t_b_a"));
+
+	let worldspawn = find_test_entity(&entities, "worldspawn");
+	assert_eq!(worldspawn.brushes.len(), 1);
+}
+
+#[test]
+fn external_map_hierarchy() {
+	let mut entities = resolve_test_maps(
+		r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "func_group"
+"_tb_type" "_tb_layer"
+"_tb_name" "Hidden"
+"_tb_id" "1"
+"_tb_layer_omit_from_export" "1"
+}
+{
+"classname" "func_group"
+"_tb_type" "_tb_group"
+"_tb_name" "Props"
+"_tb_id" "2"
+}
+{
+"classname" "misc_external_map"
+"_external_map" "prefab.map"
+"_tb_group" "2"
+}
+"#,
+		&[(
+			"prefab.map",
+			r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "func_group"
+"_tb_type" "_tb_layer"
+"_tb_name" "Lights"
+"_tb_id" "1"
+}
+{
+"classname" "light"
+"_tb_layer" "1"
+}
+"#,
+		)],
+	);
+
+	// The light's layer id means nothing in the root map, so it shouldn't end up in the omitted layer sharing it
+	let light = find_test_entity(&entities, "light");
+	assert_eq!(light.properties.get("_tb_layer"), None);
+	assert_eq!(light.properties.get("_tb_group").map(String::as_str), Some("2"));
+
+	let mut world = World::new();
+	let hierarchy = TrenchBroomHierarchy::build(&mut entities, &mut world, true, false);
+	let light_idx = entities.iter().position(|entity| entity.classname() == Ok("light")).unwrap();
+	assert!(!hierarchy.is_skipped(light_idx));
+	let group = hierarchy.parent_of(&entities[light_idx]).unwrap();
+	assert_eq!(world.entity(group).get::<Name>().unwrap().as_str(), "Props");
+}
+
+#[test]
+fn external_map_rotation_keys() {
+	let entities = resolve_test_maps(
+		r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "misc_external_map"
+"_external_map" "prefab.map"
+"_external_map_angle" "90"
+}
+"#,
+		&[(
+			"prefab.map",
+			r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "light_spot"
+"mangle" "45 -30 0"
+}
+{
+"classname" "info_target"
+"angle" "10"
+}
+{
+"classname" "info_null"
+"angles" "-30 10 0"
+}
+"#,
+		)],
+	);
+	let turn = angle_to_quat(90.);
+
+	// Spotlights keep aiming with `mangle`
+	let light = find_test_entity(&entities, "light_spot");
+	assert!(!light.properties.contains_key("angles"));
+	let expected = turn * util::mangle_to_quat(vec3(45., -30., 0.));
+	assert!(read_rotation_from_entity(light).unwrap().angle_between(expected) < 0.001);
+
+	let target = find_test_entity(&entities, "info_target");
+	assert!(!target.properties.contains_key("angles"));
+	assert!((target.get::<f32>("angle").unwrap() - 100.).abs() < 0.001);
+
+	let null = find_test_entity(&entities, "info_null");
+	assert!(null.properties.contains_key("angles") && !null.properties.contains_key("angle"));
+	let expected = turn * angles_to_quat(vec3(-30., 10., 0.));
+	assert!(read_rotation_from_entity(null).unwrap().angle_between(expected) < 0.001);
+}
@@ -0,0 +1,233 @@
+//! Handling of TrenchBroom's layers and groups, which are saved as `func_group` entities.
+
+use super::*;
+
+/// A TrenchBroom layer or group.
+#[derive(Debug, Clone)]
+pub(crate) struct TrenchBroomHierarchyNode {
+	/// The entity in the scene world representing this layer or group.
+	pub entity: Entity,
+	/// The `_tb_id` of the layer or group containing this one.
+	parent: Option<String>,
+}
+
+/// The layers and groups of a `.map` file, and which entities should be skipped because of them.
+#[derive(Debug, Default)]
+pub(crate) struct TrenchBroomHierarchy {
+	/// Maps `_tb_id` to the layer or group it belongs to.
+	nodes: HashMap<String, TrenchBroomHierarchyNode>,
+	/// Indexes of entities not to spawn, either because they're `func_group`s or because they're in a layer omitted from export.
+	skipped: Vec<bool>,
+	/// Indexes of linked groups kept out of worldspawn, see [`Self::is_linked_group`].
+	linked_groups: Vec<bool>,
+}
+
+impl TrenchBroomHierarchy {
+	/// Rebuilds the layers and groups of `entities` as named entities in `world`, and folds the brushes of `func_group`s into worldspawn.
+	///
+	/// Plain `func_group`s without `_tb_type` are only folded if `fold_plain_func_groups` is `true`, so that users can define their own `func_group` class.
+	/// If `keep_linked_groups` is `true`, groups with a `_tb_linked_group_id` keep their brushes, see [`Self::is_linked_group`].
+	pub fn build(entities: &mut QuakeMapEntities, world: &mut World, fold_plain_func_groups: bool, keep_linked_groups: bool) -> Self {
+		let mut hierarchy = Self {
+			nodes: default(),
+			skipped: vec![false; entities.len()],
+			linked_groups: vec![false; entities.len()],
+		};
+
+		let mut omitted_ids: HashSet<String> = default();
+		let mut parents: HashMap<String, Option<String>> = default();
+
+		for map_entity in entities.iter() {
+			let Some(tb_type) = structure_type(map_entity) else { continue };
+			let Some(id) = map_entity.properties.get("_tb_id") else { continue };
+
+			if tb_type == "_tb_layer" && map_entity.properties.get("_tb_layer_omit_from_export").is_some_and(|value| value == "1") {
+				omitted_ids.insert(id.clone());
+			}
+
+			parents.insert(id.clone(), parent_id(map_entity).cloned());
+		}
+
+		// Groups inherit omission from the layers and groups containing them
+		let is_omitted = |mut id: Option<&String>| {
+			// Limit the depth in case of cycles in malformed maps
+			for _ in 0..parents.len() + 1 {
+				let Some(current) = id else { return false };
+				if omitted_ids.contains(current) {
+					return true;
+				}
+				id = parents.get(current).and_then(Option::as_ref);
+			}
+			false
+		};
+
+		let worldspawn_idx = entities.iter().position(|map_entity| map_entity.classname() == Ok("worldspawn"));
+		let mut folded_brushes = Vec::new();
+
+		for (map_entity_idx, map_entity) in entities.iter_mut().enumerate() {
+			if is_omitted(parent_id(map_entity)) {
+				hierarchy.skipped[map_entity_idx] = true;
+				continue;
+			}
+
+			let structure_type = structure_type(map_entity);
+
+			if let (Some(_), Some(id)) = (structure_type, map_entity.properties.get("_tb_id")) {
+				if omitted_ids.contains(id) {
+					hierarchy.skipped[map_entity_idx] = true;
+					continue;
+				}
+
+				let name = map_entity
+					.properties
+					.get("_tb_name")
+					.cloned()
+					.unwrap_or_else(|| format!("TrenchBroom group {id}"));
+				let entity = world.spawn((Name::new(name), Transform::default(), Visibility::default())).id();
+
+				hierarchy.nodes.insert(
+					id.clone(),
+					TrenchBroomHierarchyNode {
+						entity,
+						parent: parent_id(map_entity).cloned(),
+					},
+				);
+			}
+
+			if keep_linked_groups && structure_type == Some("_tb_group") && map_entity.properties.contains_key("_tb_linked_group_id") {
+				hierarchy.linked_groups[map_entity_idx] = true;
+				continue;
+			}
+
+			if map_entity.classname() == Ok("func_group") && (structure_type.is_some() || fold_plain_func_groups) {
+				folded_brushes.append(&mut map_entity.brushes);
+				hierarchy.skipped[map_entity_idx] = true;
+			}
+		}
+
+		if let Some(worldspawn_idx) = worldspawn_idx {
+			entities[worldspawn_idx].brushes.append(&mut folded_brushes);
+		}
+
+		// Now that all nodes are spawned, we can connect them together
+		for node in hierarchy.nodes.values() {
+			if let Some(parent) = node.parent.as_ref().and_then(|parent| hierarchy.nodes.get(parent)) {
+				world.entity_mut(node.entity).insert(ChildOf(parent.entity));
+			}
+		}
+
+		hierarchy
+	}
+
+	/// Returns `true` if the entity at `map_entity_idx` should not be spawned.
+	pub fn is_skipped(&self, map_entity_idx: usize) -> bool {
+		self.skipped.get(map_entity_idx).copied().unwrap_or(false)
+	}
+
+	/// Returns `true` if the entity at `map_entity_idx` is a linked group that kept its brushes instead of having them folded into worldspawn.
+	/// These are spawned with [`LinkedGroupBrushes`] and worldspawn's geometry provider, as children of [`Self::node_of`].
+	pub fn is_linked_group(&self, map_entity_idx: usize) -> bool {
+		self.linked_groups.get(map_entity_idx).copied().unwrap_or(false)
+	}
+
+	/// Returns the classname of the class to spawn the entity at `map_entity_idx` with, which is worldspawn for [linked groups](Self::is_linked_group),
+	/// though only worldspawn's geometry provider is used for them.
+	pub fn classname_of<'a>(&self, map_entity_idx: usize, map_entity: &'a QuakeMapEntity) -> Option<&'a str> {
+		match self.is_linked_group(map_entity_idx) {
+			true => Some("worldspawn"),
+			false => map_entity.properties.get("classname").map(String::as_str),
+		}
+	}
+
+	/// Returns the entity of the layer or group that `map_entity` is in, if any.
+	pub fn parent_of(&self, map_entity: &QuakeMapEntity) -> Option<Entity> {
+		parent_id(map_entity).and_then(|id| self.nodes.get(id)).map(|node| node.entity)
+	}
+
+	/// Returns the entity of the layer or group that `map_entity` is, if it is one.
+	pub fn node_of(&self, map_entity: &QuakeMapEntity) -> Option<Entity> {
+		map_entity
+			.properties
+			.get("_tb_id")
+			.and_then(|id| self.nodes.get(id))
+			.map(|node| node.entity)
+	}
+}
+
+/// Returns `_tb_layer` or `_tb_group` if `map_entity` is a TrenchBroom layer or group.
+fn structure_type(map_entity: &QuakeMapEntity) -> Option<&str> {
+	if map_entity.classname() != Ok("func_group") {
+		return None;
+	}
+	map_entity
+		.properties
+		.get("_tb_type")
+		.map(String::as_str)
+		.filter(|tb_type| matches!(*tb_type, "_tb_layer" | "_tb_group"))
+}
+
+/// Returns the `_tb_id` of the layer or group containing `map_entity`, groups take priority over layers as they are more specific.
+fn parent_id(map_entity: &QuakeMapEntity) -> Option<&String> {
+	map_entity.properties.get("_tb_group").or_else(|| map_entity.properties.get("_tb_layer"))
+}
+
+#[test]
+fn trenchbroom_hierarchy() {
+	let input = r#"
+{
+"classname" "worldspawn"
+}
+{
+"classname" "func_group"
+"_tb_type" "_tb_layer"
+"_tb_name" "Details"
+"_tb_id" "1"
+{
+( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) base 0 0 0 1 1
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) base 0 0 0 1 1
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) base 0 0 0 1 1
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) base 0 0 0 1 1
+}
+}
+{
+"classname" "func_group"
+"_tb_type" "_tb_group"
+"_tb_name" "Lamp"
+"_tb_id" "2"
+"_tb_layer" "1"
+}
+{
+"classname" "light"
+"_tb_group" "2"
+}
+{
+"classname" "func_group"
+"_tb_type" "_tb_layer"
+"_tb_name" "Notes"
+"_tb_id" "3"
+"_tb_layer_omit_from_export" "1"
+}
+{
+"classname" "info_note"
+"_tb_layer" "3"
+}
+"#;
+
+	let config = TrenchBroomConfig::default();
+	let mut entities = QuakeMapEntities::from_quake_util(quake_util::qmap::parse(&mut io::Cursor::new(input)).unwrap(), &config);
+	let mut world = World::new();
+	let hierarchy = TrenchBroomHierarchy::build(&mut entities, &mut world, true, false);
+
+	assert_eq!(entities[0].brushes.len(), 1);
+	assert_eq!(
+		(0..entities.len()).map(|i| hierarchy.is_skipped(i)).collect_vec(),
+		[false, true, true, false, true, true]
+	);
+
+	let lamp = hierarchy.parent_of(&entities[3]).unwrap();
+	assert_eq!(world.entity(lamp).get::<Name>().unwrap().as_str(), "Lamp");
+	let details = world.entity(lamp).get::<ChildOf>().unwrap().parent();
+	assert_eq!(world.entity(details).get::<Name>().unwrap().as_str(), "Details");
+}
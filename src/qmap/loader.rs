@@ -1,19 +1,27 @@
 use bevy::{
-	asset::{AssetLoader, AsyncReadExt},
-	platform::collections::hash_map::Entry,
+	asset::{AssetLoader, AsyncReadExt, LoadContext},
 	tasks::ConditionalSendFuture,
 };
-use brush::{BrushSurfacePolygon, ConvexHull, generate_mesh_from_brush_polygons};
+use brush::{BrushSurfacePolygon, ConvexHull, SurfaceAttributes, generate_mesh_from_brush_polygons};
 use class::QuakeClassType;
-use config::TextureLoadView;
-use geometry::{BrushList, Brushes, GeometryProviderMeshView, MapGeometryTexture};
+use config::{MapLoadSettings, TextureLoadView};
+use geometry::{
+	AutoRemoveTexturesOverride, BrushList, BrushOrigin, Brushes, GeometryProviderMeshView, LocalBrushes, MapGeometryTexture, ScaleOverride,
+	split_mesh_into_cells,
+};
 
 use crate::{
 	class::{QuakeClassSpawnView, generate_class_map},
 	geometry::MapGeometry,
 };
 
-use super::*;
+use super::{
+	batching::{StaticBatchMesh, spawn_static_batches},
+	external::resolve_external_maps,
+	hierarchy::TrenchBroomHierarchy,
+	sharing::{SharedBrushEntity, find_shared_brush_entities},
+	*,
+};
 
 pub struct QuakeMapLoader {
 	pub asset_server: AssetServer,
@@ -38,27 +46,44 @@ impl QuakeMapLoader {
 }
 impl AssetLoader for QuakeMapLoader {
 	type Asset = QuakeMap;
-	type Settings = ();
+	type Settings = MapLoadSettings;
 	type Error = anyhow::Error;
 
 	fn load(
 		&self,
 		reader: &mut dyn bevy::asset::io::Reader,
-		_settings: &Self::Settings,
+		settings: &Self::Settings,
 		load_context: &mut bevy::asset::LoadContext,
 	) -> impl ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
 		Box::pin(async move {
+			let tb_server = settings.apply(&self.tb_server);
+
 			let mut input = String::new();
 			reader.read_to_string(&mut input).await?;
 
 			let quake_util_map = quake_util::qmap::parse(&mut io::Cursor::new(input))?;
-			let mut entities = QuakeMapEntities::from_quake_util(quake_util_map, &self.tb_server.config);
+			let mut entities = QuakeMapEntities::from_quake_util(quake_util_map, &tb_server.config);
+			let external_bsp_instances = resolve_external_maps(&mut entities, load_context, &tb_server.config).await?;
 
 			let mut mesh_handles = Vec::new();
 			let mut brush_lists = HashMap::default();
+			let mut texture_size_cache: HashMap<String, UVec2> = default();
+			let mut material_cache: HashMap<String, Handle<GenericMaterial>> = default();
+			let mut shared_brush_entities: HashMap<String, SharedBrushEntity> = default();
+			let mut static_batch_meshes: Vec<StaticBatchMesh> = Vec::new();
 
 			let mut world = World::new();
 
+			let class_map = self.generate_class_map();
+
+			// If the user has their own `func_group` class, we only fold groups that are actually TrenchBroom layers or groups
+			let hierarchy = TrenchBroomHierarchy::build(
+				&mut entities,
+				&mut world,
+				!class_map.contains_key("func_group"),
+				tb_server.config.share_identical_brush_entities,
+			);
+
 			// Handle origin brushes
 			for map_entity in entities.iter_mut() {
 				let origin_point = map_entity
@@ -69,9 +94,9 @@ impl AssetLoader for QuakeMapLoader {
 						brush
 							.surfaces
 							.iter()
-							.all(|surface| self.tb_server.config.origin_textures.contains(&surface.texture))
+							.all(|surface| tb_server.config.origin_textures.contains(&surface.texture))
 					})
-					.map(|(brush_idx, brush)| (brush_idx, self.tb_server.config.from_bevy_space_f64(brush.center()).as_vec3()));
+					.map(|(brush_idx, brush)| (brush_idx, tb_server.config.from_bevy_space_f64(brush.center()).as_vec3()));
 
 				if let Some((origin_brush_idx, origin_point)) = origin_point {
 					map_entity.properties.insert("origin".s(), origin_point.fgd_to_string_unquoted());
@@ -79,123 +104,196 @@ impl AssetLoader for QuakeMapLoader {
 				}
 			}
 
-			let class_map = self.generate_class_map();
+			let shared_keys = if tb_server.config.share_identical_brush_entities && settings.generate_meshes {
+				let mut candidates = Vec::new();
+
+				for (map_entity_idx, map_entity) in entities.iter().enumerate() {
+					if hierarchy.is_skipped(map_entity_idx) || map_entity.brushes.is_empty() {
+						continue;
+					}
+					let Some(classname) = hierarchy.classname_of(map_entity_idx, map_entity) else { continue };
+					let Some(QuakeClassType::Solid(geometry_provider)) = class_map.get(classname).map(|class| class.info.ty) else { continue };
+					if geometry_provider().static_batch {
+						continue;
+					}
+
+					if hierarchy.is_linked_group(map_entity_idx) {
+						let Some(id) = map_entity.properties.get("_tb_linked_group_id") else { continue };
+						candidates.push((map_entity_idx, format!("_tb_linked_group_id {id}")));
+					} else if classname != "worldspawn" && map_entity.properties.contains_key("origin") {
+						// Worldspawn is never going to be identical to anything, and users wouldn't expect it to move.
+						// Entities without an origin are left in map space, as their class might not have a transform to move their geometry back with.
+						candidates.push((map_entity_idx, classname.s()));
+					}
+				}
+
+				// Texture sizes are needed to account for wrapping of texture offsets
+				for texture in candidates
+					.iter()
+					.flat_map(|(map_entity_idx, _)| &entities[*map_entity_idx].brushes)
+					.flat_map(|brush| &brush.surfaces)
+					.map(|surface| &surface.texture)
+					.unique()
+				{
+					texture_size(load_context, &tb_server.config, &mut texture_size_cache, texture).await;
+				}
+
+				find_shared_brush_entities(&entities, candidates, &texture_size_cache, &tb_server.config)
+			} else {
+				HashMap::default()
+			};
 
 			for (map_entity_idx, map_entity) in entities.iter().enumerate() {
-				let Some(classname) = map_entity.properties.get("classname") else { continue };
-				let Some(class) = class_map.get(classname.as_str()).copied() else {
-					if !self.tb_server.config.suppress_invalid_entity_definitions {
+				if hierarchy.is_skipped(map_entity_idx) {
+					continue;
+				}
+				if let Some(instance) = external_bsp_instances.get(&map_entity_idx) {
+					let mut entity = world.spawn((
+						Name::new(instance.path.to_string()),
+						instance.transform,
+						Visibility::default(),
+						SceneRoot(load_context.load(instance.path.clone())),
+					));
+					if let Some(parent) = hierarchy.parent_of(map_entity) {
+						entity.insert(ChildOf(parent));
+					}
+					continue;
+				}
+				let linked_group = hierarchy.is_linked_group(map_entity_idx);
+				let Some(classname) = hierarchy.classname_of(map_entity_idx, map_entity) else { continue };
+				if !settings.classname_filter.allows(classname) {
+					continue;
+				}
+				let Some(class) = class_map.get(classname).copied() else {
+					if !tb_server.config.suppress_invalid_entity_definitions {
 						error!("No class found for classname `{classname}` on entity {map_entity_idx}");
 					}
 
 					continue;
 				};
 
+				let share_key = shared_keys.get(&map_entity_idx);
+				let origin = map_entity
+					.get::<Vec3>("origin")
+					.ok()
+					.or(share_key.and_then(|share_key| share_key.anchor))
+					.map(|origin| tb_server.config.to_bevy_space(origin));
+
 				let mut entity = world.spawn_empty();
 				let entity_id = entity.id();
 
-				class
-					.apply_spawn_fn_recursive(&mut QuakeClassSpawnView {
-						config: &self.tb_server.config,
-						src_entity: map_entity,
-						type_registry: &self.type_registry.read(),
-						class_map: &class_map,
-						class,
-						entity: &mut entity,
-						load_context,
-					})
-					.map_err(|err| anyhow!("spawning entity {map_entity_idx} ({classname}): {err}"))?;
+				if linked_group {
+					// Linked groups get worldspawn's geometry, but aren't worldspawn, so they skip its spawn function and the global spawner
+					entity.insert((
+						LinkedGroupBrushes,
+						Name::new("Linked group brushes"),
+						Transform::from_translation(origin.unwrap_or(Vec3::ZERO)),
+					));
+				} else {
+					class
+						.apply_spawn_fn_recursive(&mut QuakeClassSpawnView {
+							config: &tb_server.config,
+							src_entity: map_entity,
+							type_registry: &self.type_registry.read(),
+							class_map: &class_map,
+							class,
+							entity: &mut entity,
+							load_context,
+						})
+						.map_err(|err| anyhow!("spawning entity {map_entity_idx} ({classname}): {err}"))?;
+				}
 
 				if let QuakeClassType::Solid(geometry_provider) = class.info.ty {
 					let geometry_provider = geometry_provider();
+					let static_batch = geometry_provider.static_batch;
 
-					let mut grouped_polygons: HashMap<&str, Vec<BrushSurfacePolygon>> = default();
-					let mut texture_size_cache: HashMap<&str, UVec2> = default();
-					let mut material_cache: HashMap<&str, Handle<GenericMaterial>> = default();
+					let share_key = share_key.map(|share_key| &share_key.key);
+					let shared_entity = share_key.and_then(|key| shared_brush_entities.get(key)).cloned();
 
-					for brush in &map_entity.brushes {
-						for polygon in brush.polygonize() {
-							grouped_polygons.entry(&polygon.surface.texture).or_default().push(polygon);
-						}
-					}
+					// Mesh entity, mesh, texture, and the mesh's handle if it is shared with another entity
+					let mut meshes = Vec::new();
 
-					let mut meshes = Vec::with_capacity(grouped_polygons.len());
-
-					for (texture, polygons) in grouped_polygons {
-						if self.tb_server.config.auto_remove_textures.contains(texture) {
-							continue;
+					if let Some(shared_entity) = &shared_entity {
+						for (handle, mesh, texture) in &shared_entity.meshes {
+							let mesh_entity = world.spawn((Name::new(texture.name.clone()), texture.attributes)).id();
+							meshes.push((mesh_entity, mesh.clone(), texture.clone(), Some(handle.clone())));
 						}
-
-						let texture_size = *match texture_size_cache.entry(texture) {
-							Entry::Occupied(x) => x.into_mut(),
-							Entry::Vacant(x) => x.insert('size_searcher: {
-								for ext in &self.tb_server.config.texture_extensions {
-									if let Ok(image) = load_context
-										.loader()
-										.immediate()
-										.load::<Image>(
-											self.tb_server
-												.config
-												.material_root
-												.join(format!("{}.{}", &polygons[0].surface.texture, ext)),
-										)
-										.await
-									{
-										break 'size_searcher image.take().size();
-									}
-								}
-
-								error!(
-									"Failed to get size for texture {texture:?} looking for the following extensions: {:?}",
-									self.tb_server.config.texture_extensions
-								);
-								UVec2::splat(1)
-							}),
-						};
-
-						let material = match material_cache.entry(texture) {
-							Entry::Occupied(x) => x.into_mut(),
-							Entry::Vacant(x) => x.insert(
-								(self.tb_server.config.load_loose_texture)(TextureLoadView {
-									name: texture,
-									tb_config: &self.tb_server.config,
-									load_context,
-									asset_server: &self.asset_server,
-									entities: &entities,
-									#[cfg(feature = "client")]
-									alpha_mode: None,
-									embedded_textures: None,
-								})
-								.await,
-							),
+					} else if settings.generate_meshes {
+						// Faces with different Quake 2 attributes are kept in separate meshes so that the attributes can be queried per mesh entity.
+						let mut grouped_polygons: HashMap<(&str, SurfaceAttributes), Vec<BrushSurfacePolygon>> = default();
+
+						for polygon in geometry_provider.polygonize(&map_entity.brushes, &tb_server.config) {
+							grouped_polygons
+								.entry((&polygon.surface.texture, polygon.surface.attributes))
+								.or_default()
+								.push(polygon);
 						}
-						.clone();
 
-						let mut mesh = generate_mesh_from_brush_polygons(&polygons, &self.tb_server.config, texture_size);
-
-						if let Ok(origin_point) = map_entity.get::<Vec3>("origin") {
-							mesh = mesh.translated_by(self.tb_server.config.to_bevy_space(-origin_point));
+						for ((texture, attributes), polygons) in grouped_polygons {
+							if !tb_server.config.is_texture_rendered(texture) {
+								continue;
+							}
+
+							let texture_size = texture_size(load_context, &tb_server.config, &mut texture_size_cache, texture).await;
+
+							let material = match material_cache.get(texture) {
+								Some(material) => material.clone(),
+								None => {
+									let material = (tb_server.config.load_loose_texture)(TextureLoadView {
+										name: texture,
+										tb_config: &tb_server.config,
+										load_context,
+										asset_server: &self.asset_server,
+										entities: &entities,
+										#[cfg(feature = "client")]
+										alpha_mode: None,
+										embedded_textures: None,
+									})
+									.await;
+									material_cache.insert(texture.s(), material.clone());
+									material
+								}
+							};
+
+							let mut mesh = generate_mesh_from_brush_polygons(&polygons, &tb_server.config, texture_size);
+
+							if let Some(origin) = origin {
+								mesh = mesh.translated_by(-origin);
+							}
+
+							let cell_meshes = match geometry_provider.chunk_size {
+								Some(chunk_size) => split_mesh_into_cells(mesh, chunk_size),
+								None => vec![mesh],
+							};
+
+							for mesh in cell_meshes {
+								let mesh_entity = world.spawn((Name::new(texture.s()), attributes)).id();
+
+								meshes.push((
+									mesh_entity,
+									mesh,
+									MapGeometryTexture {
+										name: texture.s(),
+										material: material.clone(),
+										attributes,
+										#[cfg(all(feature = "client", feature = "bsp"))]
+										lightmap: None,
+										#[cfg(feature = "bsp")]
+										flags: BspTexFlags::Normal,
+									},
+									None,
+								));
+							}
 						}
-
-						let mesh_entity = world.spawn(Name::new(texture.s())).id();
-
-						meshes.push((
-							mesh_entity,
-							mesh,
-							MapGeometryTexture {
-								name: texture.s(),
-								material,
-								#[cfg(all(feature = "client", feature = "bsp"))]
-								lightmap: None,
-								#[cfg(feature = "bsp")]
-								flags: BspTexFlags::Normal,
-							},
-						));
 					}
 
+					// Geometry providers modify meshes, so if this entity is going to be shared, we need to keep the original meshes for the next copy.
+					let original_meshes = (share_key.is_some() && shared_entity.is_none())
+						.then(|| meshes.iter().map(|(_, mesh, texture, _)| (mesh.clone(), texture.clone())).collect_vec());
+
 					let mesh_views = meshes
 						.iter_mut()
-						.map(|(entity, mesh, texture)| GeometryProviderMeshView {
+						.map(|(entity, mesh, texture, _)| GeometryProviderMeshView {
 							entity: *entity,
 							mesh,
 							texture,
@@ -205,7 +303,7 @@ impl AssetLoader for QuakeMapLoader {
 					let mut view = GeometryProviderView {
 						world: &mut world,
 						entity: entity_id,
-						tb_server: &self.tb_server,
+						tb_server: &tb_server,
 						map_entity,
 						map_entity_idx,
 						class,
@@ -216,39 +314,111 @@ impl AssetLoader for QuakeMapLoader {
 						provider(&mut view);
 					}
 
-					(self.tb_server.config.global_geometry_provider)(&mut view);
+					(tb_server.config.global_geometry_provider)(&mut view);
 
-					for (mesh_entity, mesh, _) in meshes {
-						let handle = load_context.add_labeled_asset(format!("Mesh{}", mesh_handles.len()), mesh);
+					let mut final_handles = Vec::with_capacity(meshes.len());
+
+					for (mesh_entity, mesh, texture, shared_handle) in meshes {
+						if static_batch {
+							// The mesh entity was only needed for geometry providers
+							world.despawn(mesh_entity);
+							static_batch_meshes.push(StaticBatchMesh {
+								entity: entity_id,
+								mesh,
+								texture,
+							});
+							continue;
+						}
+
+						let handle = match shared_handle {
+							Some(handle) => handle,
+							None => {
+								let handle = load_context.add_labeled_asset(format!("Mesh{}", mesh_handles.len()), mesh);
+								mesh_handles.push(handle.clone());
+								handle
+							}
+						};
 
 						// We add the children at the end to prevent the console flooding with warnings about broken Transform and Visibility hierarchies.
 						world
 							.entity_mut(mesh_entity)
 							.insert((Mesh3d(handle.clone()), ChildOf(entity_id), MapGeometry));
 
-						mesh_handles.push(handle);
+						final_handles.push(handle);
 					}
 
-					let brush_list_handle = load_context.add_labeled_asset(format!("Brushes{map_entity_idx}"), BrushList(map_entity.brushes.clone()));
+					let brush_list_handle = match &shared_entity {
+						Some(shared_entity) => shared_entity.brush_list.clone(),
+						None => {
+							let mut brushes = map_entity.brushes.clone();
+							// Copies are in different places, so shared brushes are relative to the entity instead of the map
+							if share_key.is_some() {
+								let offset = DAffine3::from_translation(-origin.unwrap_or(Vec3::ZERO).as_dvec3());
+								for brush in &mut brushes {
+									brush.transform(offset, &tb_server.config);
+								}
+							}
+							load_context.add_labeled_asset(format!("Brushes{map_entity_idx}"), BrushList(brushes))
+						}
+					};
 					brush_lists.insert(map_entity_idx, brush_list_handle.clone());
 
+					if let (Some(key), Some(original_meshes)) = (share_key, original_meshes) {
+						shared_brush_entities.insert(
+							key.clone(),
+							SharedBrushEntity {
+								meshes: final_handles
+									.into_iter()
+									.zip(original_meshes)
+									.map(|(handle, (mesh, texture))| (handle, mesh, texture))
+									.collect(),
+								brush_list: brush_list_handle.clone(),
+							},
+						);
+					}
+
 					world.entity_mut(entity_id).insert(Brushes::Shared(brush_list_handle));
+					match share_key {
+						Some(_) => world.entity_mut(entity_id).insert(LocalBrushes),
+						None => world.entity_mut(entity_id).insert(BrushOrigin(origin.unwrap_or(Vec3::ZERO))),
+					};
+					if let Some(auto_remove_textures) = &settings.auto_remove_textures {
+						world
+							.entity_mut(entity_id)
+							.insert(AutoRemoveTexturesOverride(auto_remove_textures.clone()));
+					}
+					if let Some(scale) = settings.scale {
+						world.entity_mut(entity_id).insert(ScaleOverride(scale));
+					}
+				}
+
+				if !linked_group {
+					let mut entity = world.entity_mut(entity_id);
+
+					(tb_server.config.global_spawner)(&mut QuakeClassSpawnView {
+						config: &tb_server.config,
+						src_entity: map_entity,
+						type_registry: &self.type_registry.read(),
+						class_map: &class_map,
+						class,
+						entity: &mut entity,
+						load_context,
+					})
+					.map_err(|err| anyhow!("spawning entity {map_entity_idx} ({classname}) with global spawner: {err}"))?;
 				}
 
-				let mut entity = world.entity_mut(entity_id);
-
-				(self.tb_server.config.global_spawner)(&mut QuakeClassSpawnView {
-					config: &self.tb_server.config,
-					src_entity: map_entity,
-					type_registry: &self.type_registry.read(),
-					class_map: &class_map,
-					class,
-					entity: &mut entity,
-					load_context,
-				})
-				.map_err(|err| anyhow!("spawning entity {map_entity_idx} ({classname}) with global spawner: {err}"))?;
+				// Linked groups go under their group's entity, rather than the layer or group containing it
+				let parent = match linked_group {
+					true => hierarchy.node_of(map_entity),
+					false => hierarchy.parent_of(map_entity),
+				};
+				if let Some(parent) = parent {
+					world.entity_mut(entity_id).insert(ChildOf(parent));
+				}
 			}
 
+			spawn_static_batches(&mut world, load_context, &tb_server.config, static_batch_meshes, &mut mesh_handles);
+
 			Ok(QuakeMap {
 				scene: load_context.add_labeled_asset("Scene".s(), Scene::new(world)),
 				meshes: mesh_handles,
@@ -263,6 +433,35 @@ impl AssetLoader for QuakeMapLoader {
 	}
 }
 
+/// Finds the size of a loose texture by loading its image, caching the result.
+async fn texture_size(load_context: &mut LoadContext<'_>, config: &TrenchBroomConfig, cache: &mut HashMap<String, UVec2>, texture: &str) -> UVec2 {
+	if let Some(size) = cache.get(texture) {
+		return *size;
+	}
+
+	let size = 'size_searcher: {
+		for ext in &config.texture_extensions {
+			if let Ok(image) = load_context
+				.loader()
+				.immediate()
+				.load::<Image>(config.material_root.join(format!("{texture}.{ext}")))
+				.await
+			{
+				break 'size_searcher image.take().size();
+			}
+		}
+
+		error!(
+			"Failed to get size for texture {texture:?} looking for the following extensions: {:?}",
+			config.texture_extensions
+		);
+		UVec2::splat(1)
+	};
+
+	cache.insert(texture.s(), size);
+	size
+}
+
 #[cfg(feature = "client")]
 #[test]
 fn map_loading() {
@@ -292,3 +491,201 @@ fn map_loading() {
 			.unwrap();
 	});
 }
+
+#[cfg(feature = "client")]
+#[test]
+fn linked_group_sharing() {
+	#[derive(SolidClass, Component, Reflect)]
+	#[reflect(QuakeClass, Component)]
+	#[geometry(GeometryProvider::new())]
+	struct Worldspawn;
+
+	let mut app = App::new();
+
+	#[rustfmt::skip]
+	app
+		.add_plugins((AssetPlugin::default(), TaskPoolPlugin::default(), bevy::time::TimePlugin))
+		.insert_resource(TrenchBroomServer::new(
+			TrenchBroomConfig::default()
+				.suppress_invalid_entity_definitions(true)
+				.share_identical_brush_entities(true)
+		))
+		.register_type::<Worldspawn>()
+		.init_asset::<Image>()
+		.init_asset::<StandardMaterial>()
+		.init_asset::<Mesh>()
+		.init_asset::<BrushList>()
+		.init_asset::<Scene>()
+		.init_asset::<QuakeMap>()
+		.init_asset_loader::<QuakeMapLoader>()
+	;
+
+	let handle = smol::block_on(async {
+		app.world()
+			.resource::<AssetServer>()
+			.load_untyped_async("maps/linked_groups.map")
+			.await
+			.unwrap()
+	});
+	// Adds the loaded assets to their collections
+	app.update();
+
+	let config = app.world().resource::<TrenchBroomServer>().config.clone();
+	let scene = app
+		.world()
+		.resource::<Assets<QuakeMap>>()
+		.get(&handle.typed::<QuakeMap>())
+		.unwrap()
+		.scene
+		.clone();
+	let mut scenes = app.world_mut().resource_mut::<Assets<Scene>>();
+	let scene_world = &mut scenes.get_mut(&scene).unwrap().world;
+
+	let copies = scene_world
+		.query::<(&Mesh3d, &ChildOf)>()
+		.iter(scene_world)
+		.map(|(mesh, child_of)| (mesh.0.clone(), scene_world.get::<Transform>(child_of.parent()).unwrap().translation))
+		.collect_vec();
+
+	assert_eq!(copies.len(), 2);
+	assert_eq!(copies[0].0, copies[1].0);
+
+	// Only the map's own worldspawn is spawned as worldspawn
+	assert_eq!(scene_world.query::<&Worldspawn>().iter(scene_world).count(), 1);
+
+	// Linked groups share a brush list relative to each copy
+	let brush_lists = scene_world
+		.query_filtered::<&Brushes, (With<LinkedGroupBrushes>, With<LocalBrushes>, Without<Worldspawn>)>()
+		.iter(scene_world)
+		.map(|brushes| match brushes {
+			Brushes::Shared(handle) => handle.clone(),
+			_ => panic!("linked group brushes should be shared"),
+		})
+		.collect_vec();
+	assert_eq!(brush_lists.len(), 2);
+	assert_eq!(brush_lists[0], brush_lists[1]);
+
+	let mesh_aabb = app.world().resource::<Assets<Mesh>>().get(&copies[0].0).unwrap().compute_aabb().unwrap();
+	let brush_vertices = app.world().resource::<Assets<BrushList>>().get(&brush_lists[0]).unwrap()[0]
+		.calculate_vertices()
+		.map(|(vertex, _)| vertex.as_vec3())
+		.collect_vec();
+	let brush_bounds = (
+		brush_vertices.iter().copied().reduce(Vec3::min).unwrap(),
+		brush_vertices.iter().copied().reduce(Vec3::max).unwrap(),
+	);
+	assert!(brush_bounds.0.abs_diff_eq(mesh_aabb.min().into(), 0.001) && brush_bounds.1.abs_diff_eq(mesh_aabb.max().into(), 0.001));
+	let world_bounds = copies
+		.iter()
+		.map(|(_, translation)| (Vec3::from(mesh_aabb.min()) + *translation, Vec3::from(mesh_aabb.max()) + *translation))
+		.collect_vec();
+
+	for tb_min in [vec3(0., 0., 0.), vec3(128., 0., 0.)] {
+		let (a, b) = (config.to_bevy_space(tb_min), config.to_bevy_space(tb_min + Vec3::splat(32.)));
+		let expected = (a.min(b), a.max(b));
+		assert!(
+			world_bounds
+				.iter()
+				.any(|(min, max)| min.abs_diff_eq(expected.0, 0.001) && max.abs_diff_eq(expected.1, 0.001)),
+			"{expected:?} not in {world_bounds:?}"
+		);
+	}
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn identical_brush_entity_sharing() {
+	#[derive(SolidClass, Component, Reflect)]
+	#[reflect(QuakeClass, Component)]
+	#[geometry(GeometryProvider::new())]
+	struct FuncWall;
+
+	#[derive(SolidClass, Component, Reflect)]
+	#[reflect(QuakeClass, Component)]
+	#[base(Transform)]
+	#[geometry(GeometryProvider::new())]
+	struct FuncDoor;
+
+	let mut app = App::new();
+
+	#[rustfmt::skip]
+	app
+		.add_plugins((AssetPlugin::default(), TaskPoolPlugin::default(), bevy::time::TimePlugin))
+		.insert_resource(TrenchBroomServer::new(
+			TrenchBroomConfig::default()
+				.suppress_invalid_entity_definitions(true)
+				.share_identical_brush_entities(true)
+		))
+		.register_type::<FuncWall>()
+		.register_type::<FuncDoor>()
+		.init_asset::<Image>()
+		.init_asset::<StandardMaterial>()
+		.init_asset::<Mesh>()
+		.init_asset::<BrushList>()
+		.init_asset::<Scene>()
+		.init_asset::<QuakeMap>()
+		.init_asset_loader::<QuakeMapLoader>()
+	;
+
+	let handle = smol::block_on(async {
+		app.world()
+			.resource::<AssetServer>()
+			.load_untyped_async("maps/identical_brush_entities.map")
+			.await
+			.unwrap()
+	});
+	// Adds the loaded assets to their collections
+	app.update();
+
+	let config = app.world().resource::<TrenchBroomServer>().config.clone();
+	let map = app.world().resource::<Assets<QuakeMap>>().get(&handle.typed::<QuakeMap>()).unwrap();
+	// Entities without an origin aren't given one
+	assert!(map.entities[1..=2].iter().all(|map_entity| !map_entity.properties.contains_key("origin")));
+	let scene = map.scene.clone();
+	let mut scenes = app.world_mut().resource_mut::<Assets<Scene>>();
+	let scene_world = &mut scenes.get_mut(&scene).unwrap().world;
+
+	let meshes_of = |scene_world: &mut World, filter: fn(EntityRef) -> bool| {
+		scene_world
+			.query::<(&Mesh3d, &ChildOf)>()
+			.iter(scene_world)
+			.filter(|(_, child_of)| filter(scene_world.entity(child_of.parent())))
+			.map(|(mesh, _)| mesh.0.clone())
+			.collect_vec()
+	};
+
+	// Walls can't be moved back by a transform, so their geometry stays in map space and isn't shared
+	let walls = meshes_of(scene_world, |entity| entity.contains::<FuncWall>());
+	assert_eq!(walls.len(), 2);
+	assert_ne!(walls[0], walls[1]);
+	assert_eq!(
+		scene_world
+			.query_filtered::<(), (With<FuncWall>, With<LocalBrushes>)>()
+			.iter(scene_world)
+			.count(),
+		0
+	);
+
+	let doors = meshes_of(scene_world, |entity| entity.contains::<FuncDoor>());
+	assert_eq!(doors.len(), 2);
+	assert_eq!(doors[0], doors[1]);
+
+	let meshes = app.world().resource::<Assets<Mesh>>();
+	let wall_bounds = walls
+		.iter()
+		.map(|handle| {
+			let aabb = meshes.get(handle).unwrap().compute_aabb().unwrap();
+			(Vec3::from(aabb.min()), Vec3::from(aabb.max()))
+		})
+		.collect_vec();
+	for tb_min in [vec3(0., 0., 0.), vec3(128., 0., 0.)] {
+		let (a, b) = (config.to_bevy_space(tb_min), config.to_bevy_space(tb_min + Vec3::splat(32.)));
+		let expected = (a.min(b), a.max(b));
+		assert!(
+			wall_bounds
+				.iter()
+				.any(|(min, max)| min.abs_diff_eq(expected.0, 0.001) && max.abs_diff_eq(expected.1, 0.001)),
+			"{expected:?} not in {wall_bounds:?}"
+		);
+	}
+}
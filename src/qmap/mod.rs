@@ -4,7 +4,12 @@ use geometry::BrushList;
 
 use crate::*;
 
+mod batching;
+pub mod external;
+mod hierarchy;
 pub mod loader;
+mod sharing;
+mod writer;
 
 pub struct QuakeMapPlugin;
 impl Plugin for QuakeMapPlugin {
@@ -12,11 +17,18 @@ impl Plugin for QuakeMapPlugin {
 		#[rustfmt::skip]
 		app
 			.init_asset::<QuakeMap>()
+			.register_type::<LinkedGroupBrushes>()
 			.init_asset_loader::<loader::QuakeMapLoader>()
 		;
 	}
 }
 
+/// Marks the entity holding the brushes of a copy of a TrenchBroom linked group, as a child of the group's entity,
+/// see [`TrenchBroomConfig::share_identical_brush_entities`].
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Default)]
+pub struct LinkedGroupBrushes;
+
 /// Quake map loaded from a .map file.
 #[derive(Reflect, Asset, Debug, Clone)]
 pub struct QuakeMap {
@@ -66,7 +78,7 @@ pub struct QuakeMapEntity {
 	pub properties: HashMap<String, String>,
 	/// If the map entity is a [`Solid`](crate::class::QuakeClassType::Solid) entity, this will contain the brushes making it up.
 	///
-	/// NOTE: If loading from a BSP, this will always be empty. Instead, use the `BRUSHLIST` BSPX lump stored within [`BspModel`](crate::bsp::BspModel).
+	/// NOTE: If loading from a BSP, this will always be empty. Instead, use the brushes stored within [`BspModel`](crate::bsp::BspModel).
 	#[cfg(feature = "bsp")]
 	pub brushes: Vec<Brush>,
 	/// If the map entity is a [`Solid`](crate::class::QuakeClassType::Solid) entity, this will contain the brushes making it up.
@@ -134,3 +146,35 @@ impl<T> QuakeEntityErrorResultExt for Result<T, QuakeEntityError> {
 		}
 	}
 }
+
+#[test]
+fn quake2_surface_attributes() {
+	let input = r#"
+{
+"classname" "worldspawn"
+{
+( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) ladder 0 0 0 1 1 8 32 5
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) base 0 0 0 1 1
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) base 0 0 0 1 1
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) base 0 0 0 1 1
+}
+}
+"#;
+
+	let config = TrenchBroomConfig::default();
+	let qmap = quake_util::qmap::parse(&mut io::Cursor::new(input)).unwrap();
+	let entities = QuakeMapEntities::from_quake_util(qmap, &config);
+	let surfaces = &entities.worldspawn().unwrap().brushes[0].surfaces;
+
+	assert_eq!(
+		surfaces[0].attributes,
+		brush::SurfaceAttributes {
+			content_flags: 8,
+			surface_flags: 32,
+			surface_value: 5,
+		}
+	);
+	assert_eq!(surfaces[1].attributes, default());
+}
@@ -0,0 +1,139 @@
+//! Detection of identical brush entities, see [`TrenchBroomConfig::share_identical_brush_entities`].
+
+use std::fmt::Write;
+
+use brush::{Brush, ConvexHull};
+use geometry::{BrushList, MapGeometryTexture};
+
+use super::*;
+
+/// The assets created for a brush entity, to be reused by identical ones.
+#[derive(Clone)]
+pub(crate) struct SharedBrushEntity {
+	/// The final mesh handles, along with the meshes and textures as they were before geometry providers were applied,
+	/// so that providers can be applied to copies without modifying the shared meshes.
+	pub meshes: Vec<(Handle<Mesh>, Mesh, MapGeometryTexture)>,
+	/// The brushes of the entity, relative to its origin.
+	pub brush_list: Handle<BrushList>,
+}
+
+/// The share key of an entity found by [`find_shared_brush_entities`].
+pub(crate) struct SharedBrushEntityKey {
+	/// Equal for all identical entities.
+	pub key: String,
+	/// For entities without an `origin`, the point their meshes and brushes are made relative to instead.
+	///
+	/// This is kept out of the entity's properties, as it isn't part of the map, and classes reading `origin` would be moved by it.
+	pub anchor: Option<Vec3>,
+}
+
+/// Finds which of `candidates` are identical to at least one other, returning their share keys by entity index.
+/// Candidates are entity indexes along with what identical entities need to have in common, such as their classname or linked group.
+///
+/// Shared entities without an `origin` are anchored at the minimum corner of their bounds, so that their meshes can be generated relative to it and reused.
+/// Only entities that are spawned with a [`Transform`] at their anchor should be passed without one, such as linked groups.
+pub(crate) fn find_shared_brush_entities(
+	entities: &QuakeMapEntities,
+	candidates: impl IntoIterator<Item = (usize, String)>,
+	texture_sizes: &HashMap<String, UVec2>,
+	config: &TrenchBroomConfig,
+) -> HashMap<usize, SharedBrushEntityKey> {
+	// Entities by share key, along with their anchors if they don't have an origin
+	let mut groups: HashMap<String, Vec<(usize, Option<Vec3>)>> = default();
+
+	for (map_entity_idx, kind) in candidates {
+		let map_entity = &entities[map_entity_idx];
+
+		let (anchor, new_anchor) = match map_entity.get::<Vec3>("origin") {
+			Ok(origin) => (origin, None),
+			Err(QuakeEntityError::RequiredPropertyNotFound { .. }) => {
+				let Some(min) = map_entity
+					.brushes
+					.iter()
+					.flat_map(|brush| brush.calculate_vertices())
+					.map(|(vertex, _)| config.from_bevy_space_f64(vertex))
+					.reduce(DVec3::min)
+				else {
+					continue;
+				};
+
+				// Opposite corners in TrenchBroom and Bevy space don't matter, it just needs to be consistent
+				let min = min.round().as_vec3();
+				(min, Some(min))
+			}
+			Err(_) => continue,
+		};
+
+		// Compared relative to where they would be anchored, so that copies anywhere in the map are detected
+		let mut local_brushes = map_entity.brushes.clone();
+		let offset = DAffine3::from_translation(-config.to_bevy_space(anchor).as_dvec3());
+		for brush in &mut local_brushes {
+			brush.transform(offset, config);
+		}
+
+		groups
+			.entry(share_key(&kind, &local_brushes, texture_sizes))
+			.or_default()
+			.push((map_entity_idx, new_anchor));
+	}
+
+	let mut shared = HashMap::default();
+
+	for (key, group) in groups {
+		if group.len() < 2 {
+			continue;
+		}
+
+		for (map_entity_idx, anchor) in group {
+			shared.insert(map_entity_idx, SharedBrushEntityKey { key: key.clone(), anchor });
+		}
+	}
+
+	shared
+}
+
+/// Creates a key that will be equal for entities of the same `kind` with the same `brushes` (relative to their origins),
+/// and textures that line up the same way, taking wrapping into account via `texture_sizes`.
+fn share_key(kind: &str, brushes: &[Brush], texture_sizes: &HashMap<String, UVec2>) -> String {
+	// Quantized so that floating-point error doesn't stop identical entities from being detected
+	fn quantize(value: f64, precision: f64) -> i64 {
+		(value * precision).round() as i64
+	}
+
+	let mut key = kind.s();
+
+	for brush in brushes {
+		key.push('{');
+		for surface in &brush.surfaces {
+			let plane = &surface.plane;
+			let uv = &surface.uv;
+			let texture_size = texture_sizes.get(&surface.texture).copied().unwrap_or(UVec2::ONE).as_vec2();
+			let offset = uv.offset.rem_euclid(texture_size).as_dvec2();
+
+			write!(
+				key,
+				"({} {} {} {} {} {:?} {} {} {} {} {}",
+				quantize(plane.normal.x, 1e5),
+				quantize(plane.normal.y, 1e5),
+				quantize(plane.normal.z, 1e5),
+				quantize(plane.distance, 1e5),
+				surface.texture,
+				surface.attributes,
+				quantize(offset.x, 1e2),
+				quantize(offset.y, 1e2),
+				quantize(uv.rotation as f64, 1e2),
+				quantize(uv.scale.x as f64, 1e4),
+				quantize(uv.scale.y as f64, 1e4),
+			)
+			.ok();
+
+			for axis in uv.axes.iter().flatten() {
+				write!(key, " {} {} {}", quantize(axis.x, 1e7), quantize(axis.y, 1e7), quantize(axis.z, 1e7)).ok();
+			}
+			key.push(')');
+		}
+		key.push('}');
+	}
+
+	key
+}
@@ -0,0 +1,218 @@
+use std::io::Write;
+
+use brush::{BrushPlane, BrushSurface};
+use config::MapFileFormat;
+use util::BevyTrenchbroomCoordinateConversions;
+
+use super::*;
+
+/// How far apart the 3 points written for each plane are, in TrenchBroom units.
+const PLANE_POINT_DISTANCE: f64 = 64.;
+
+impl QuakeMapEntities {
+	/// Writes these entities as `.map` text in the specified format, converting brushes back into TrenchBroom space with the inverse of [`TrenchBroomConfig::to_bevy_space`].
+	///
+	/// Properties are written with `classname` first, then in alphabetical order.
+	///
+	/// NOTE: Formats without texture axes (like [`MapFileFormat::Standard`]) can't fully represent surfaces loaded from formats with them (like [`MapFileFormat::Valve`]),
+	/// in that case only the offset, rotation and scale are written.
+	pub fn write_map(&self, writer: &mut impl Write, format: MapFileFormat, config: &TrenchBroomConfig) -> io::Result<()> {
+		writeln!(writer, "// Game: {}", config.name)?;
+		writeln!(writer, "// Format: {}", format.config_str())?;
+
+		for (entity_idx, entity) in self.iter().enumerate() {
+			writeln!(writer, "// entity {entity_idx}")?;
+			writeln!(writer, "{{")?;
+
+			let mut properties = entity.properties.iter().collect_vec();
+			properties.sort_by(|(a, _), (b, _)| (*a != "classname").cmp(&(*b != "classname")).then(a.cmp(b)));
+
+			for (key, value) in properties {
+				writeln!(writer, "\"{key}\" \"{value}\"")?;
+			}
+
+			for (brush_idx, brush) in entity.brushes.iter().enumerate() {
+				writeln!(writer, "// brush {brush_idx}")?;
+				writeln!(writer, "{{")?;
+				for surface in &brush.surfaces {
+					write_surface(writer, surface, format, config)?;
+				}
+				writeln!(writer, "}}")?;
+			}
+
+			writeln!(writer, "}}")?;
+		}
+
+		Ok(())
+	}
+
+	/// Shorthand for [`Self::write_map`] into a [`String`].
+	pub fn to_map_string(&self, format: MapFileFormat, config: &TrenchBroomConfig) -> String {
+		let mut buf = Vec::new();
+		self.write_map(&mut buf, format, config).expect("writing to a Vec can't fail");
+		String::from_utf8(buf).expect("map text should be valid UTF-8")
+	}
+}
+
+fn write_surface(writer: &mut impl Write, surface: &BrushSurface, format: MapFileFormat, config: &TrenchBroomConfig) -> io::Result<()> {
+	let normal = surface.plane.normal.bevy_to_trenchbroom();
+
+	for point in plane_points(&surface.plane, config) {
+		write!(writer, "( {} {} {} ) ", point.x, point.y, point.z)?;
+	}
+	write!(writer, "{}", surface.texture)?;
+
+	let uv = &surface.uv;
+
+	match format {
+		MapFileFormat::Valve | MapFileFormat::Quake2Valve | MapFileFormat::Quake3Valve => {
+			let [x_axis, y_axis] = match uv.axes {
+				Some(axes) => axes.map(|axis| config.from_bevy_space_f64(axis)),
+				None => uv.standard_axes(normal),
+			};
+
+			write!(
+				writer,
+				" [ {} {} {} {} ] [ {} {} {} {} ] {} {} {}",
+				x_axis.x, x_axis.y, x_axis.z, uv.offset.x, y_axis.x, y_axis.y, y_axis.z, uv.offset.y, uv.rotation, uv.scale.x, uv.scale.y
+			)?;
+		}
+		MapFileFormat::Standard | MapFileFormat::Quake2 | MapFileFormat::Quake3Legacy | MapFileFormat::Hexen2 => {
+			write!(writer, " {} {} {} {} {}", uv.offset.x, uv.offset.y, uv.rotation, uv.scale.x, uv.scale.y)?;
+		}
+	}
+
+	match format {
+		MapFileFormat::Quake2 | MapFileFormat::Quake2Valve | MapFileFormat::Quake3Legacy | MapFileFormat::Quake3Valve => {
+			let attributes = &surface.attributes;
+			write!(
+				writer,
+				" {} {} {}",
+				attributes.content_flags, attributes.surface_flags, attributes.surface_value
+			)?;
+		}
+		// Hexen 2 has an extra value we don't load
+		MapFileFormat::Hexen2 => write!(writer, " 0")?,
+		MapFileFormat::Standard | MapFileFormat::Valve => {}
+	}
+
+	writeln!(writer)
+}
+
+/// Converts `plane` into 3 points in TrenchBroom space, ordered so that [`BrushPlane::from_triangle`] gives back the same plane.
+fn plane_points(plane: &BrushPlane, config: &TrenchBroomConfig) -> [DVec3; 3] {
+	let normal = plane.normal.bevy_to_trenchbroom();
+	let origin = -normal * plane.distance * config.scale as f64;
+
+	// Use the world axis least aligned with the normal to build a basis, so it never ends up degenerate
+	let abs_normal = normal.abs();
+	let helper = if abs_normal.x <= abs_normal.y && abs_normal.x <= abs_normal.z {
+		DVec3::X
+	} else if abs_normal.y <= abs_normal.z {
+		DVec3::Y
+	} else {
+		DVec3::Z
+	};
+	let u = helper.cross(normal).normalize();
+	// u × v = normal
+	let v = normal.cross(u);
+
+	[origin, origin + v * PLANE_POINT_DISTANCE, origin + u * PLANE_POINT_DISTANCE].map(round_point)
+}
+
+/// Snaps nearly-integer coordinates back to integers to undo floating-point error, keeping maps tidy for tools expecting integer plane points.
+fn round_point(point: DVec3) -> DVec3 {
+	let rounded = point.round();
+	DVec3::select(point.cmplt(rounded + 0.0001) & point.cmpgt(rounded - 0.0001), rounded, point)
+}
+
+#[test]
+fn map_writing_round_trip() {
+	let config = TrenchBroomConfig::default();
+	let parse = |input: String| QuakeMapEntities::from_quake_util(quake_util::qmap::parse(&mut io::Cursor::new(input)).unwrap(), &config);
+
+	let round_trip = |input: &str, format: MapFileFormat| {
+		let entities = parse(input.s());
+		let written = entities.to_map_string(format, &config);
+		let reparsed = parse(written);
+
+		assert_eq!(reparsed.len(), entities.len());
+		assert_eq!(reparsed[0].properties, entities[0].properties);
+
+		for (original, written) in entities[0].brushes[0].surfaces.iter().zip(&reparsed[0].brushes[0].surfaces) {
+			assert!(original.plane.normal.abs_diff_eq(written.plane.normal, 0.00001));
+			assert!((original.plane.distance - written.plane.distance).abs() < 0.00001);
+			assert_eq!(original.texture, written.texture);
+			assert_eq!(original.uv.offset, written.uv.offset);
+			assert_eq!(original.uv.rotation, written.uv.rotation);
+			assert_eq!(original.uv.scale, written.uv.scale);
+			assert_eq!(original.attributes, written.attributes);
+			match (original.uv.axes, written.uv.axes) {
+				(Some(original_axes), Some(written_axes)) => {
+					for (original_axis, written_axis) in original_axes.iter().zip(&written_axes) {
+						assert!(original_axis.abs_diff_eq(*written_axis, 0.00001), "{original_axis} != {written_axis}");
+					}
+				}
+				(None, None) => {}
+				axes => panic!("{format:?} changed texture axes: {axes:?}"),
+			}
+		}
+
+		entities
+	};
+
+	round_trip(
+		r#"
+{
+"classname" "worldspawn"
+"message" "Round trip"
+{
+( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) ladder [ 0 1 0 8 ] [ 0 0 -1 4 ] 15 1 1
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) base [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) base [ -1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) base [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 0.5 0.5
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) base [ -1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) base [ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+}
+}
+"#,
+		MapFileFormat::Valve,
+	);
+
+	let entities = round_trip(
+		r#"
+{
+"classname" "worldspawn"
+{
+( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) ladder 8 4 45 2 0.5
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) base 0 0 0 1 1
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) base -3 0 90 1 1
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) base 0 0 0 1 1
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) base 0 0 0 1 1
+}
+}
+"#,
+		MapFileFormat::Standard,
+	);
+	assert!(entities[0].brushes[0].surfaces[0].uv.axes.is_none());
+
+	let entities = round_trip(
+		r#"
+{
+"classname" "worldspawn"
+{
+( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) water 8 4 30 1 1 8 32 5
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) base 0 0 0 1 1 1 0 0
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) base 0 0 0 1 1 1 0 0
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) base 0 0 0 0.5 0.5 1 4 100
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) base 0 0 0 1 1 1 0 0
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) base 0 0 0 1 1 1 0 0
+}
+}
+"#,
+		MapFileFormat::Quake2,
+	);
+	assert_eq!(entities[0].brushes[0].surfaces[0].attributes.surface_value, 5);
+	assert_eq!(entities[0].brushes[0].surfaces[3].attributes.surface_flags, 4);
+}
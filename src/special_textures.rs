@@ -41,7 +41,8 @@ pub fn load_special_texture(view: &mut EmbeddedTextureLoadView, material: &Stand
 	}
 
 	if let Some(default_fn) = view.tb_config.embedded_liquid_material {
-		if view.name.starts_with('*') {
+		// GoldSrc uses `!` for liquids
+		if view.name.starts_with('*') || (view.goldsrc && view.name.starts_with('!')) {
 			let water_alpha: f32 = view
 				.entities
 				.worldspawn()
@@ -65,7 +66,8 @@ pub fn load_special_texture(view: &mut EmbeddedTextureLoadView, material: &Stand
 		}
 	}
 	if let Some(default_fn) = view.tb_config.embedded_quake_sky_material {
-		if view.name.starts_with("sky") {
+		// GoldSrc skies are skyboxes, and their `sky` texture is only a placeholder
+		if !view.goldsrc && view.name.starts_with("sky") {
 			// We need to separate the sky into the 2 foreground and background images here because otherwise we will get weird wrapping when linear filtering is on.
 
 			fn separate_sky_image(view: &mut EmbeddedTextureLoadView, x_range: std::ops::Range<u32>, alpha_on_black: bool) -> Image {